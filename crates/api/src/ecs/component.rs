@@ -0,0 +1,10 @@
+use bevy_reflect::{FromReflect, GetTypeRegistration, Typed};
+
+/// Types that can be fetched from entities by a [`Query`](crate::ecs::system::Query)
+pub trait Component
+where
+    Self: Sized + Typed + FromReflect + GetTypeRegistration,
+{
+}
+
+impl<C> Component for C where C: Sized + Typed + FromReflect + GetTypeRegistration {}
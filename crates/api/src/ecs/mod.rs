@@ -1,6 +1,8 @@
+mod component;
 mod generic;
 mod resource;
 pub mod system;
 
+pub use component::Component;
 pub use generic::Reflected;
 pub use resource::Resource;
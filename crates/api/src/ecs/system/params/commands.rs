@@ -59,5 +59,5 @@ impl<'a> EntityCommands<'a> {
 }
 
 /// Similar to bevy's Entity
-#[derive(Debug)]
-pub struct Entity(u32);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Entity(pub(crate) u32);
@@ -1,4 +1,6 @@
 mod commands;
 pub use commands::*;
+mod query;
+pub use query::*;
 mod resource;
 pub use resource::*;
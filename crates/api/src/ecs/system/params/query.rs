@@ -0,0 +1,454 @@
+use std::{
+    cell::OnceCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use bevy_utils_proc_macros::all_tuples;
+use common::{QueryComponent, QueryDescriptor, StableId};
+
+use crate::{
+    ecs::{
+        system::{system_param::Params, Entity, SystemParam},
+        Component,
+    },
+    runtime::{
+        deserialize, ffi_get_local_query_id, ffi_get_query, ffi_set_query_component, serialize,
+        LocalQueryId,
+    },
+};
+
+/// Similar to bevy_ecs::system::Query
+///
+/// Matching entities are fetched from the host the first time the query is iterated. Components accessed through
+/// [`Mut`] are written back to their entity when the query is dropped.
+pub struct Query<'w, D, F = ()>
+where
+    D: QueryData,
+    F: QueryFilter,
+{
+    query_id: &'w LocalQueryId,
+    rows: OnceCell<Vec<(u32, D::Fetch)>>,
+    _filter: PhantomData<F>,
+}
+
+impl<'a, D, F> SystemParam for Query<'a, D, F>
+where
+    D: QueryData + 'static,
+    F: QueryFilter + 'static,
+{
+    type State = LocalQueryId;
+    type Item<'state> = Query<'state, D, F>;
+
+    fn init_state() -> Self::State {
+        ffi_get_local_query_id(&descriptor::<D, F>())
+    }
+
+    fn get_param<'state>(state: &'state mut Self::State) -> Self::Item<'state> {
+        Query {
+            query_id: state,
+            rows: OnceCell::new(),
+            _filter: PhantomData,
+        }
+    }
+
+    fn get_metadata() -> Params {
+        vec![common::Param::Query(descriptor::<D, F>())]
+    }
+}
+
+fn descriptor<D, F>() -> QueryDescriptor<'static>
+where
+    D: QueryData,
+    F: QueryFilter,
+{
+    let mut descriptor = QueryDescriptor::default();
+    D::components(&mut descriptor.components);
+    F::filters(&mut descriptor.with, &mut descriptor.without);
+    descriptor
+}
+
+impl<'w, D, F> Query<'w, D, F>
+where
+    D: QueryData,
+    F: QueryFilter,
+{
+    fn rows(&self) -> &Vec<(u32, D::Fetch)> {
+        self.rows.get_or_init(|| {
+            ffi_get_query(self.query_id)
+                .into_iter()
+                .map(|(entity, components)| {
+                    let fetch = D::fetch(Entity(entity), &mut components.into_iter());
+                    (entity, fetch)
+                })
+                .collect()
+        })
+    }
+
+    /// Iterates over the data of every matching entity, without being able to change it
+    pub fn iter<'q>(&'q self) -> QueryIter<'q, D> {
+        let item: fn(&'q (u32, D::Fetch)) -> D::ReadOnlyItem<'q> =
+            |(_, fetch)| D::read_only_item(fetch);
+        self.rows().iter().map(item)
+    }
+
+    pub fn iter_mut<'q>(&'q mut self) -> QueryIterMut<'q, D> {
+        let item: fn(&'q mut (u32, D::Fetch)) -> D::Item<'q> = |(_, fetch)| D::item(fetch);
+        self.rows();
+        self.rows.get_mut().unwrap().iter_mut().map(item)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows().is_empty()
+    }
+}
+
+pub type QueryIter<'q, D> = std::iter::Map<
+    std::slice::Iter<'q, (u32, <D as QueryData>::Fetch)>,
+    fn(&'q (u32, <D as QueryData>::Fetch)) -> <D as QueryData>::ReadOnlyItem<'q>,
+>;
+
+pub type QueryIterMut<'q, D> = std::iter::Map<
+    std::slice::IterMut<'q, (u32, <D as QueryData>::Fetch)>,
+    fn(&'q mut (u32, <D as QueryData>::Fetch)) -> <D as QueryData>::Item<'q>,
+>;
+
+impl<'q, 'w, D, F> IntoIterator for &'q Query<'w, D, F>
+where
+    D: QueryData,
+    F: QueryFilter,
+{
+    type Item = D::ReadOnlyItem<'q>;
+    type IntoIter = QueryIter<'q, D>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'q, 'w, D, F> IntoIterator for &'q mut Query<'w, D, F>
+where
+    D: QueryData,
+    F: QueryFilter,
+{
+    type Item = D::Item<'q>;
+    type IntoIter = QueryIterMut<'q, D>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<'w, D, F> Drop for Query<'w, D, F>
+where
+    D: QueryData,
+    F: QueryFilter,
+{
+    fn drop(&mut self) {
+        let Some(rows) = self.rows.get() else {
+            return;
+        };
+        let mut changed = Vec::new();
+        for (entity, fetch) in rows.iter() {
+            D::changed(fetch, &mut 0, &mut changed);
+            for (index, buffer) in changed.drain(..) {
+                ffi_set_query_component(self.query_id, *entity, index, &buffer);
+            }
+        }
+    }
+}
+
+/// The data a [`Query`] fetches for every matching entity, such as `&T`, `&mut T`, [`Entity`] or tuples of them
+pub trait QueryData {
+    /// The values fetched for a single entity, owned by the query
+    type Fetch;
+    type Item<'q>;
+    /// Returned when iterating over a query without being able to change it, `&mut T` becomes `&T`
+    type ReadOnlyItem<'q>;
+
+    /// Appends the components this data fetches, in the order they are passed to [`QueryData::fetch`]
+    fn components(components: &mut Vec<QueryComponent<'static>>);
+
+    /// Takes the encoded components of this data, in order
+    fn fetch(entity: Entity, components: &mut std::vec::IntoIter<Vec<u8>>) -> Self::Fetch;
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_>;
+
+    fn read_only_item(fetch: &Self::Fetch) -> Self::ReadOnlyItem<'_>;
+
+    /// Appends the changed components, serialized with their index in [`QueryData::components`]
+    ///
+    /// `index` is the index of the first component of this data.
+    fn changed(fetch: &Self::Fetch, index: &mut u32, changed: &mut Vec<(u32, Vec<u8>)>);
+}
+
+fn next_component<T: Component>(components: &mut std::vec::IntoIter<Vec<u8>>) -> T {
+    let bytes = components
+        .next()
+        .expect("host sent fewer components than the query fetches");
+    deserialize(&bytes)
+}
+
+impl<T> QueryData for &T
+where
+    T: Component,
+{
+    type Fetch = T;
+    type Item<'q> = &'q T;
+    type ReadOnlyItem<'q> = &'q T;
+
+    fn components(components: &mut Vec<QueryComponent<'static>>) {
+        components.push(QueryComponent {
+            mutable: false,
+            id: StableId::from_typed::<T>(),
+        });
+    }
+
+    fn fetch(_entity: Entity, components: &mut std::vec::IntoIter<Vec<u8>>) -> Self::Fetch {
+        next_component(components)
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        fetch
+    }
+
+    fn read_only_item(fetch: &Self::Fetch) -> Self::ReadOnlyItem<'_> {
+        fetch
+    }
+
+    fn changed(_fetch: &Self::Fetch, index: &mut u32, _changed: &mut Vec<(u32, Vec<u8>)>) {
+        *index += 1;
+    }
+}
+
+impl<T> QueryData for &mut T
+where
+    T: Component,
+{
+    /// The component, and whether it was mutably dereferenced
+    type Fetch = (T, bool);
+    type Item<'q> = Mut<'q, T>;
+    type ReadOnlyItem<'q> = &'q T;
+
+    fn components(components: &mut Vec<QueryComponent<'static>>) {
+        components.push(QueryComponent {
+            mutable: true,
+            id: StableId::from_typed::<T>(),
+        });
+    }
+
+    fn fetch(_entity: Entity, components: &mut std::vec::IntoIter<Vec<u8>>) -> Self::Fetch {
+        (next_component(components), false)
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        let (value, changed) = fetch;
+        Mut { value, changed }
+    }
+
+    fn read_only_item(fetch: &Self::Fetch) -> Self::ReadOnlyItem<'_> {
+        &fetch.0
+    }
+
+    fn changed(fetch: &Self::Fetch, index: &mut u32, changed: &mut Vec<(u32, Vec<u8>)>) {
+        if fetch.1 {
+            changed.push((*index, serialize(&fetch.0)));
+        }
+        *index += 1;
+    }
+}
+
+impl QueryData for Entity {
+    type Fetch = Entity;
+    type Item<'q> = Entity;
+    type ReadOnlyItem<'q> = Entity;
+
+    fn components(_components: &mut Vec<QueryComponent<'static>>) {}
+
+    fn fetch(entity: Entity, _components: &mut std::vec::IntoIter<Vec<u8>>) -> Self::Fetch {
+        entity
+    }
+
+    fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+        *fetch
+    }
+
+    fn read_only_item(fetch: &Self::Fetch) -> Self::ReadOnlyItem<'_> {
+        *fetch
+    }
+
+    fn changed(_fetch: &Self::Fetch, _index: &mut u32, _changed: &mut Vec<(u32, Vec<u8>)>) {}
+}
+
+macro_rules! impl_query_data_tuple {
+    ($($data: ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($data: QueryData),*> QueryData for ($($data,)*) {
+            type Fetch = ($($data::Fetch,)*);
+            type Item<'q> = ($($data::Item<'q>,)*);
+            type ReadOnlyItem<'q> = ($($data::ReadOnlyItem<'q>,)*);
+
+            fn components(components: &mut Vec<QueryComponent<'static>>) {
+                $(
+                    $data::components(components);
+                )*
+            }
+
+            fn fetch(entity: Entity, components: &mut std::vec::IntoIter<Vec<u8>>) -> Self::Fetch {
+                ($($data::fetch(entity, components),)*)
+            }
+
+            fn item(fetch: &mut Self::Fetch) -> Self::Item<'_> {
+                let ($($data,)*) = fetch;
+                ($($data::item($data),)*)
+            }
+
+            fn read_only_item(fetch: &Self::Fetch) -> Self::ReadOnlyItem<'_> {
+                let ($($data,)*) = fetch;
+                ($($data::read_only_item($data),)*)
+            }
+
+            fn changed(fetch: &Self::Fetch, index: &mut u32, changed: &mut Vec<(u32, Vec<u8>)>) {
+                let ($($data,)*) = fetch;
+                $(
+                    $data::changed($data, index, changed);
+                )*
+            }
+        }
+    };
+}
+
+all_tuples!(impl_query_data_tuple, 1, 15, D);
+
+/// Mutable access to a component fetched by a [`Query`]
+///
+/// Dereferencing it mutably marks the component as changed, so it is written back when the query is dropped.
+pub struct Mut<'q, T> {
+    value: &'q mut T,
+    changed: &'q mut bool,
+}
+
+impl<'q, T> Deref for Mut<'q, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<'q, T> AsRef<T> for Mut<'q, T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self.deref()
+    }
+}
+
+impl<'q, T> DerefMut for Mut<'q, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        *self.changed = true;
+        self.value
+    }
+}
+
+impl<'q, T> AsMut<T> for Mut<'q, T> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut T {
+        self.deref_mut()
+    }
+}
+
+/// Restricts the entities a [`Query`] matches, such as [`With`], [`Without`] or tuples of them
+pub trait QueryFilter {
+    /// Appends the components matching entities must have, and those they must not have
+    fn filters(with: &mut Vec<StableId<'static>>, without: &mut Vec<StableId<'static>>);
+}
+
+/// Only matches entities with the component `T`, without fetching it
+pub struct With<T>(PhantomData<T>);
+
+impl<T> QueryFilter for With<T>
+where
+    T: Component,
+{
+    fn filters(with: &mut Vec<StableId<'static>>, _without: &mut Vec<StableId<'static>>) {
+        with.push(StableId::from_typed::<T>());
+    }
+}
+
+/// Only matches entities without the component `T`
+pub struct Without<T>(PhantomData<T>);
+
+impl<T> QueryFilter for Without<T>
+where
+    T: Component,
+{
+    fn filters(_with: &mut Vec<StableId<'static>>, without: &mut Vec<StableId<'static>>) {
+        without.push(StableId::from_typed::<T>());
+    }
+}
+
+macro_rules! impl_query_filter_tuple {
+    ($($filter: ident),*) => {
+        impl<$($filter: QueryFilter),*> QueryFilter for ($($filter,)*) {
+            #[allow(unused_variables)]
+            fn filters(with: &mut Vec<StableId<'static>>, without: &mut Vec<StableId<'static>>) {
+                $(
+                    $filter::filters(with, without);
+                )*
+            }
+        }
+    };
+}
+
+all_tuples!(impl_query_filter_tuple, 0, 15, F);
+
+#[cfg(test)]
+mod tests {
+    use bevy_reflect::Reflect;
+
+    use super::*;
+    use crate::ecs::system::IntoSystem;
+
+    fn into_metadata<T, Marker>(_system: T) -> common::System<'static>
+    where
+        T: IntoSystem<(), (), Marker>,
+    {
+        T::into_metadata()
+    }
+
+    #[test]
+    fn query_metadata() {
+        #[derive(Reflect)]
+        struct Position;
+        #[derive(Reflect)]
+        struct Velocity;
+        #[derive(Reflect)]
+        struct Player;
+        #[derive(Reflect)]
+        struct Frozen;
+
+        type Data<'q> = (Entity, &'q mut Position, &'q Velocity);
+        fn sys(_query: Query<Data, (With<Player>, Without<Frozen>)>) {}
+
+        let meta = into_metadata(sys);
+        assert_eq!(
+            meta.params,
+            vec![common::Param::Query(QueryDescriptor {
+                components: vec![
+                    QueryComponent {
+                        mutable: true,
+                        id: StableId::from_typed::<Position>(),
+                    },
+                    QueryComponent {
+                        mutable: false,
+                        id: StableId::from_typed::<Velocity>(),
+                    },
+                ],
+                with: vec![StableId::from_typed::<Player>()],
+                without: vec![StableId::from_typed::<Frozen>()],
+            })]
+        );
+    }
+}
@@ -16,6 +16,67 @@ use crate::{
     },
 };
 
+/// Reads a resource declared by this mod, or a resource of the host the mod was granted access to
+pub struct Res<'w, T>
+where
+    T: Resource,
+{
+    type_id: &'w LocalTypeId,
+    value: OnceCell<T>,
+}
+
+impl<'a, T> SystemParam for Res<'a, T>
+where
+    T: Resource,
+{
+    type State = LocalTypeId;
+    type Item<'state> = Res<'state, T>;
+
+    fn init_state() -> Self::State {
+        let id = StableId::from_typed::<T>();
+        ffi_get_local_type_id(&id)
+    }
+
+    fn get_param<'state>(state: &'state mut Self::State) -> Self::Item<'state> {
+        Res {
+            type_id: state,
+            value: OnceCell::new(),
+        }
+    }
+
+    fn get_metadata() -> Params {
+        vec![common::Param::Res {
+            mutable: false,
+            id: StableId::from_typed::<T>(),
+        }]
+    }
+}
+
+impl<'w, T> Deref for Res<'w, T>
+where
+    T: Resource,
+{
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.value.get_or_init(|| {
+            let bytes = ffi_get_resource(self.type_id);
+            deserialize(&bytes)
+        })
+    }
+}
+
+impl<'w, T> AsRef<T> for Res<'w, T>
+where
+    T: Resource,
+{
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self.deref()
+    }
+}
+
 pub struct ResMut<'w, T>
 where
     T: Resource,
@@ -47,7 +108,7 @@ where
 
     fn get_metadata() -> Params {
         vec![common::Param::Res {
-            mutable: false,
+            mutable: true,
             id: StableId::from_typed::<T>(),
         }]
     }
@@ -1,10 +1,17 @@
 use crate::ecs::Reflected;
 
+#[cfg(not(feature = "generate_manifest"))]
+use super::{
+    system_set::{conjure_zst, BoxedSystems},
+    BoxedSystem,
+};
 use super::{
     system_set::{SystemSet, Systems},
-    IntoSystemSet,
+    IntoSystem, IntoSystemSet,
 };
 use common::StableId;
+#[cfg(not(feature = "generate_manifest"))]
+use common::SystemId;
 use const_vec::ConstVec;
 
 /// Similar in role to bevy's IntoSystemConfigs trait
@@ -44,12 +51,24 @@ where
             .push(Constraint::Includes(stable_id_getter(named_system_set)));
         schedule
     }
+
+    /// Only runs the systems when the condition returns true
+    ///
+    /// The condition is evaluated by the modloader once per run of the schedule, before any of the systems.
+    fn run_if<Marker>(self, condition: impl IntoSystem<(), bool, Marker> + Copy) -> Schedule {
+        let mut schedule = self.into_schedule();
+        schedule.constraints.push(condition_constraint(condition));
+        schedule
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct Schedule {
     system_set_getter: fn() -> SystemSet,
     systems_getter: fn() -> Systems,
+    // Left out when generating the manifest, since running systems requires functions only the modloader provides
+    #[cfg(not(feature = "generate_manifest"))]
+    boxed_systems_getter: fn() -> BoxedSystems,
     constraints: ConstVec<Constraint, 16>,
 }
 
@@ -61,6 +80,7 @@ impl const IntoSchedule<()> for Schedule {
 
 impl Schedule {
     pub(crate) fn build(self) -> common::Schedule<'static> {
+        let mut systems = (self.systems_getter)().0;
         let mut constraints = Vec::new();
 
         for constraint in self.constraints.into_slice() {
@@ -107,22 +127,63 @@ impl Schedule {
                         });
                     }
                 }
+                Constraint::RunIf { metadata, .. } => {
+                    let condition = metadata();
+                    let sets = (self.system_set_getter)().into_min_sets();
+                    for set in sets {
+                        constraints.push(common::Constraint::Condition {
+                            set,
+                            condition: condition.id,
+                        });
+                    }
+
+                    // Conditions are exported like any other system
+                    if !systems.iter().any(|system| system.id == condition.id) {
+                        systems.push(condition);
+                    }
+                }
             }
         }
 
         common::Schedule {
-            systems: (self.systems_getter)().0,
+            systems,
             constraints,
         }
     }
+
+    #[cfg(not(feature = "generate_manifest"))]
+    pub(crate) fn boxed_systems(self) -> BoxedSystems {
+        (self.boxed_systems_getter)()
+    }
+
+    /// Instantiates the run conditions of this schedule so they can be evaluated
+    #[cfg(not(feature = "generate_manifest"))]
+    pub(crate) fn boxed_conditions(self) -> BoxedConditions {
+        self.constraints
+            .into_slice()
+            .iter()
+            .filter_map(|constraint| match constraint {
+                Constraint::RunIf { boxed, .. } => Some(boxed()),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
+#[cfg(not(feature = "generate_manifest"))]
+pub type BoxedConditions = Vec<(SystemId, BoxedSystem<(), bool>)>;
+
 #[derive(Clone, Copy, Debug)]
 enum Constraint {
     Chain,
     Before(fn() -> SystemSet),
     After(fn() -> SystemSet),
     Includes(fn() -> StableId<'static>),
+    RunIf {
+        metadata: fn() -> common::System<'static>,
+        #[cfg(not(feature = "generate_manifest"))]
+        boxed: fn() -> (SystemId, BoxedSystem<(), bool>),
+    },
 }
 
 #[inline]
@@ -141,6 +202,30 @@ where
     StableId::from_typed::<T>
 }
 
+#[inline]
+const fn condition_constraint<F, Marker>(_condition: F) -> Constraint
+where
+    F: IntoSystem<(), bool, Marker> + Copy,
+{
+    Constraint::RunIf {
+        metadata: F::into_metadata,
+        #[cfg(not(feature = "generate_manifest"))]
+        boxed: boxed_condition::<F, Marker>,
+    }
+}
+
+#[cfg(not(feature = "generate_manifest"))]
+fn boxed_condition<F, Marker>() -> (SystemId, BoxedSystem<(), bool>)
+where
+    F: IntoSystem<(), bool, Marker> + Copy,
+{
+    let condition: F = conjure_zst();
+    (
+        SystemId::of::<F::System>(),
+        Box::new(IntoSystem::into_system(condition)),
+    )
+}
+
 #[doc(hidden)]
 #[derive(Clone, Copy)]
 pub struct FunctionMarker;
@@ -153,6 +238,8 @@ where
         Schedule {
             system_set_getter: F::into_system_set,
             systems_getter: F::into_systems,
+            #[cfg(not(feature = "generate_manifest"))]
+            boxed_systems_getter: F::into_boxed_systems,
             constraints: ConstVec::new(),
         }
     }
@@ -283,6 +370,96 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(not(feature = "generate_manifest"))]
+    fn boxed_systems() {
+        static mut RAN: u32 = 0;
+
+        fn system1() {
+            unsafe {
+                RAN += 1;
+            }
+        }
+        fn system2() {
+            unsafe {
+                RAN += 10;
+            }
+        }
+        #[derive(Reflect, Clone, Copy)]
+        struct NamedSet;
+
+        const SCHEDULE: Schedule = (system1, NamedSet, system2).chain();
+
+        let mut systems = SCHEDULE.boxed_systems();
+        assert_eq!(
+            systems.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![get_system_id(system1), get_system_id(system2)]
+        );
+        for (_, system) in systems.iter_mut() {
+            system.run(());
+        }
+        assert_eq!(unsafe { RAN }, 11, "systems did not run");
+    }
+
+    #[test]
+    fn run_if() {
+        fn system1() {}
+        fn system2() {}
+        fn condition() -> bool {
+            true
+        }
+        #[derive(Reflect, Clone, Copy)]
+        struct NamedSet;
+
+        const SCHEDULE: Schedule = (system1, system2, NamedSet).run_if(condition);
+
+        let schedule = SCHEDULE.build();
+        let condition_id = SystemId::from_type(IntoSystem::get_type_id(&condition));
+        assert_eq!(
+            schedule
+                .systems
+                .iter()
+                .map(|system| system.id)
+                .collect::<Vec<_>>(),
+            vec![get_system_id(system1), get_system_id(system2), condition_id]
+        );
+        assert_eq!(
+            schedule.constraints,
+            vec![
+                common::Constraint::Condition {
+                    set: get_named_system_set::<NamedSet>(),
+                    condition: condition_id,
+                },
+                common::Constraint::Condition {
+                    set: common::SystemSet::Anonymous(vec![
+                        get_system_id(system1),
+                        get_system_id(system2)
+                    ]),
+                    condition: condition_id,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "generate_manifest"))]
+    fn boxed_conditions() {
+        fn system() {}
+        fn condition() -> bool {
+            true
+        }
+
+        const SCHEDULE: Schedule = system.run_if(condition).run_if(condition);
+
+        let mut conditions = SCHEDULE.boxed_conditions();
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(
+            conditions[0].0,
+            SystemId::from_type(IntoSystem::get_type_id(&condition))
+        );
+        assert!(conditions[0].1.run(()), "condition did not run");
+    }
+
     #[test]
     fn in_set() {
         fn system() {}
@@ -299,4 +476,81 @@ mod tests {
             },]
         );
     }
+
+    #[test]
+    fn ambiguities() {
+        #[derive(Reflect)]
+        struct TestResource;
+        fn system1() {}
+        fn system2() {}
+        fn system3() {}
+        fn system4() {}
+
+        const ORDERED: Schedule = system3.after((system1, system2));
+
+        let write = || Param::Res {
+            mutable: true,
+            id: StableId::from_typed::<TestResource>(),
+        };
+        let unordered = common::Schedule {
+            systems: vec![
+                make_system(system1, vec![write()]),
+                make_system(system2, vec![write()]),
+                make_system(system4, vec![Param::Command]),
+            ],
+            constraints: vec![],
+        };
+        let mut ordered = ORDERED.build();
+        ordered.systems = vec![make_system(system3, vec![write()])];
+
+        assert_eq!(
+            common::Schedule::ambiguities(&[&unordered, &ordered]),
+            vec![common::Ambiguity {
+                systems: [get_system_id(system1), get_system_id(system2)],
+                resources: vec![StableId::from_typed::<TestResource>()],
+                components: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn query_ambiguities() {
+        #[derive(Reflect)]
+        struct Position;
+        #[derive(Reflect)]
+        struct Player;
+        fn system1() {}
+        fn system2() {}
+        fn system3() {}
+
+        let query = |mutable: bool, with: Vec<StableId<'static>>, without| {
+            Param::Query(common::QueryDescriptor {
+                components: vec![common::QueryComponent {
+                    mutable,
+                    id: StableId::from_typed::<Position>(),
+                }],
+                with,
+                without,
+            })
+        };
+        let player = || vec![StableId::from_typed::<Player>()];
+        let schedule = common::Schedule {
+            systems: vec![
+                make_system(system1, vec![query(true, player(), vec![])]),
+                // Never matches the same entities as system1
+                make_system(system2, vec![query(false, vec![], player())]),
+                make_system(system3, vec![query(false, vec![], vec![])]),
+            ],
+            constraints: vec![],
+        };
+
+        assert_eq!(
+            common::Schedule::ambiguities(&[&schedule]),
+            vec![common::Ambiguity {
+                systems: [get_system_id(system1), get_system_id(system3)],
+                resources: vec![],
+                components: vec![StableId::from_typed::<Position>()],
+            }]
+        );
+    }
 }
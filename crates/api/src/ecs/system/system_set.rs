@@ -1,6 +1,6 @@
 use crate::ecs::Reflected;
 
-use super::IntoSystem;
+use super::{BoxedSystem, IntoSystem};
 use bevy_utils_proc_macros::all_tuples;
 use common::{StableId, System, SystemId};
 
@@ -14,12 +14,19 @@ where
     fn into_systems() -> Systems {
         Systems(Vec::new())
     }
+
+    /// Instantiates the systems of this set so they can be run
+    fn into_boxed_systems() -> BoxedSystems {
+        Vec::new()
+    }
 }
 
 pub struct SystemSet(Vec<Sys>);
 
 pub struct Systems(pub(crate) Vec<System<'static>>);
 
+pub type BoxedSystems = Vec<(SystemId, BoxedSystem)>;
+
 #[derive(PartialEq, Debug)]
 enum Sys {
     Anonymous(SystemId),
@@ -74,6 +81,29 @@ where
     fn into_systems() -> Systems {
         Systems(vec![F::into_metadata()])
     }
+
+    fn into_boxed_systems() -> BoxedSystems {
+        let system: F = conjure_zst();
+        vec![(
+            SystemId::of::<F::System>(),
+            Box::new(IntoSystem::into_system(system)),
+        )]
+    }
+}
+
+/// Creates the only value of a zero-sized type, such as a function item
+///
+/// Schedules are built in const contexts and only keep track of the types of their systems, so this is how
+/// those systems are instantiated at runtime.
+pub(super) fn conjure_zst<T>() -> T {
+    const {
+        assert!(
+            size_of::<T>() == 0,
+            "Systems must be zero-sized, such as function items or closures that capture nothing"
+        )
+    };
+    // SAFETY: A zero-sized type has no bytes, thus there is nothing to initialize
+    unsafe { std::mem::zeroed() }
 }
 
 impl<T> IntoSystemSet<()> for T
@@ -112,6 +142,14 @@ macro_rules! impl_system_collection {
                 )*
                 Systems(systems)
             }
+
+            fn into_boxed_systems() -> BoxedSystems {
+                let mut systems = Vec::new();
+                $(
+                    systems.extend($sys::into_boxed_systems());
+                )*
+                systems
+            }
         }
     }
 }
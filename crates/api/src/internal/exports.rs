@@ -0,0 +1,49 @@
+//! Implementations of the wasm exports generated by `#[api::main]`
+
+use crate::schema::Schema;
+
+#[cfg(feature = "generate_manifest")]
+pub fn generate_manifest(schema: Schema) {
+    let manifest = super::schema_to_manifest(schema);
+    let encoded = manifest.encode();
+    crate::runtime::ffi_submit_manifest(&encoded);
+}
+
+#[cfg(not(feature = "generate_manifest"))]
+pub fn run_system(schema: Schema, system_id: u64) {
+    use crate::ecs::system::BoxedSystem;
+    use common::SystemId;
+    use std::collections::HashMap;
+
+    // Kept apart from the runtime static, since systems borrow the runtime while they run
+    static mut SYSTEMS: Option<HashMap<SystemId, BoxedSystem>> = None;
+
+    // SAFETY: Mods are single threaded and systems never call back into run_system
+    #[allow(static_mut_refs)]
+    let systems = unsafe { SYSTEMS.get_or_insert_with(|| schema.into_boxed_systems()) };
+
+    let id = SystemId::from_raw(system_id);
+    let system = systems
+        .get_mut(&id)
+        .unwrap_or_else(|| panic!("Mod has no system with id {:?}", id));
+    system.run(());
+}
+
+#[cfg(not(feature = "generate_manifest"))]
+pub fn run_condition(schema: Schema, system_id: u64) -> bool {
+    use crate::ecs::system::BoxedSystem;
+    use common::SystemId;
+    use std::collections::HashMap;
+
+    static mut CONDITIONS: Option<HashMap<SystemId, BoxedSystem<(), bool>>> = None;
+
+    // SAFETY: Mods are single threaded and conditions never call back into run_condition
+    #[allow(static_mut_refs)]
+    let conditions = unsafe { CONDITIONS.get_or_insert_with(|| schema.into_boxed_conditions()) };
+
+    let id = SystemId::from_raw(system_id);
+    let condition = conditions
+        .get_mut(&id)
+        .unwrap_or_else(|| panic!("Mod has no run condition with id {:?}", id));
+    condition.run(())
+}
@@ -1,6 +1,6 @@
 use std::{any::TypeId, collections::HashMap};
 
-use common::{FeatureDescriptor, FileHash, ModManifest, ScheduleDescriptor, StableId};
+use common::{Dependency, FeatureDescriptor, FileHash, ModManifest, ScheduleDescriptor, StableId};
 
 use crate::schema::Schema;
 
@@ -51,8 +51,18 @@ pub fn schema_to_manifest(schema: Schema) -> ModManifest<'static> {
     }
     let schedules = schedules.into_values().collect();
 
+    let dependencies = schema
+        .dependencies
+        .into_slice()
+        .iter()
+        .map(|&(name, version)| Dependency { name, version })
+        .collect();
+
     ModManifest {
         wasm_hash: FileHash::empty(),
+        api_version: env!("CARGO_PKG_VERSION"),
+        version: schema.version.unwrap_or("0.0.0"),
+        dependencies,
         types: types.into_vec(),
         features: vec![FeatureDescriptor {
             name: schema.name.unwrap_or("unknown"),
@@ -67,7 +77,8 @@ pub fn schema_to_manifest(schema: Schema) -> ModManifest<'static> {
 mod tests {
     use bevy_reflect::Reflect;
     use common::{
-        FieldSignature, Param, Schedule, Start, System, SystemId, TypeSignature, VariantSignature,
+        FieldSignature, ManifestError, Param, Schedule, Start, System, SystemId, TypeSignature,
+        VariantSignature, ABI_VERSION,
     };
 
     use crate::{ecs::system::IntoSystem, schema::Mod};
@@ -85,6 +96,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn encode_and_decode() {
+        const SCHEMA: Schema = Mod::new("Encoded").version("1.0.0").into_schema();
+        let manifest = schema_to_manifest(SCHEMA);
+
+        let encoded = manifest.encode();
+        assert_eq!(ModManifest::decode(&encoded), Ok(manifest));
+
+        assert_eq!(
+            ModManifest::decode(&encoded[4..]),
+            Err(ManifestError::Unversioned)
+        );
+
+        let mut newer_abi = encoded.clone();
+        newer_abi[6..10].copy_from_slice(&(ABI_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            ModManifest::decode(&newer_abi),
+            Err(ManifestError::IncompatibleAbi(ABI_VERSION + 1))
+        );
+        assert_eq!(ModManifest::abi_version(&newer_abi), Ok(ABI_VERSION + 1));
+    }
+
+    #[test]
+    fn reencoding_keeps_the_abi_version() {
+        const SCHEMA: Schema = Mod::new("Reencoded").into_schema();
+        let manifest = schema_to_manifest(SCHEMA);
+
+        // Like the build tool does, which may have been built against another ABI than the mod
+        let encoded = manifest.encode();
+        let abi_version = ModManifest::abi_version(&encoded).unwrap();
+        let reencoded = ModManifest::decode(&encoded)
+            .unwrap()
+            .encode_with_abi(abi_version);
+        assert_eq!(ModManifest::abi_version(&reencoded), Ok(ABI_VERSION));
+        assert_eq!(reencoded, encoded);
+
+        // The ABI version of mods that can't be decoded is still read from the header, to report it
+        let older_abi = manifest.encode_with_abi(ABI_VERSION - 1);
+        assert_eq!(ModManifest::abi_version(&older_abi), Ok(ABI_VERSION - 1));
+        assert_eq!(
+            ModManifest::decode(&older_abi),
+            Err(ManifestError::IncompatibleAbi(ABI_VERSION - 1))
+        );
+    }
+
     #[test]
     fn manifest_from_schema() {
         #[derive(Reflect)]
@@ -113,6 +169,8 @@ mod tests {
         fn system2() {}
 
         const SCHEMA: Schema = Mod::new("A custom name")
+            .version("0.1.0")
+            .depends_on("Another mod", "^1")
             .add_resource::<MyStruct>()
             .add_systems(Start, system1)
             .add_systems(Start, system2)
@@ -121,9 +179,22 @@ mod tests {
         let ModManifest {
             types,
             features,
+            version,
+            dependencies,
+            api_version,
             wasm_hash: _wasm_hash,
         } = schema_to_manifest(SCHEMA);
 
+        assert_eq!(api_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(version, "0.1.0");
+        assert_eq!(
+            dependencies,
+            vec![Dependency {
+                name: "Another mod",
+                version: "^1"
+            }]
+        );
+
         assert_eq!(types.len(), 5);
         // In indeterminate order
         assert!(types.contains(&TypeSignature::Struct {
@@ -1,3 +1,6 @@
+mod exports;
+pub use exports::*;
+
 mod manifest;
 pub use manifest::*;
 
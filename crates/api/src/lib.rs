@@ -15,18 +15,22 @@ pub mod ecs;
 pub mod runtime;
 pub mod schema;
 
+pub use macros::main;
+
 pub mod prelude {
     pub use bevy_reflect::prelude::*;
     pub use bevy_reflect_derive::*;
 
     pub use crate::ecs::{
-        system::{Commands, IntoSchedule, IntoSystemSet, ResMut},
-        Reflected, Resource,
+        system::{
+            Commands, Entity, IntoSchedule, IntoSystemSet, Query, Res, ResMut, With, Without,
+        },
+        Component, Reflected, Resource,
     };
     pub use crate::schema::{Mod, Schema};
 
     // Schedules
-    pub use common::{Start, Update};
+    pub use common::{First, FixedUpdate, Last, PostUpdate, PreUpdate, Start, Update};
 
     // Native bevy types
     pub use bevy_math::prelude::*;
@@ -1,4 +1,6 @@
-use common::StableId;
+#[cfg(feature = "generate_manifest")]
+use common::WasmPointer;
+use common::{QueryDescriptor, StableId};
 
 pub(crate) fn ffi_spawn_empty() -> u32 {
     unsafe { spawn_empty() }
@@ -39,12 +41,59 @@ pub(crate) fn ffi_set_resource(type_id: &LocalTypeId, buffer: &Vec<u8>) {
 
 pub(crate) fn ffi_get_resource(type_id: &LocalTypeId) -> Vec<u8> {
     let size = unsafe { buffer_resource(type_id.0) };
+    read_buffer(size)
+}
+
+pub struct LocalQueryId(u32);
+
+pub(crate) fn ffi_get_local_query_id(query: &QueryDescriptor) -> LocalQueryId {
+    let encoded = bitcode::encode(query);
+    LocalQueryId(unsafe { get_local_query_id(encoded.as_ptr() as _, encoded.len() as _) })
+}
+
+/// Every entity matching the query, with the encoded components it fetches
+pub(crate) fn ffi_get_query(query_id: &LocalQueryId) -> Vec<(u32, Vec<Vec<u8>>)> {
+    let size = unsafe { buffer_query(query_id.0) };
+    let buffer = read_buffer(size);
+    bitcode::decode(&buffer).expect("host sent a malformed query buffer")
+}
+
+/// Writes back the component at the given index of the query's components
+pub(crate) fn ffi_set_query_component(
+    query_id: &LocalQueryId,
+    entity_id: u32,
+    index: u32,
+    buffer: &Vec<u8>,
+) {
+    unsafe {
+        set_query_component(
+            query_id.0,
+            entity_id,
+            index,
+            buffer.as_ptr() as _,
+            buffer.len() as _,
+        );
+    }
+}
 
+/// Copies the buffer the host just filled into the memory of the mod
+fn read_buffer(size: u32) -> Vec<u8> {
     let mut buffer = Vec::with_capacity(size as _);
-    unsafe { write_buffer_to(buffer.as_mut_ptr() as _) };
+    unsafe {
+        write_buffer_to(buffer.as_mut_ptr() as _);
+        // SAFETY: The host wrote exactly `size` bytes into the buffer
+        buffer.set_len(size as _);
+    }
     buffer
 }
 
+#[cfg(feature = "generate_manifest")]
+pub(crate) fn ffi_submit_manifest(encoded: &Vec<u8>) {
+    unsafe {
+        submit_manifest(WasmPointer::from_vec(encoded).into());
+    }
+}
+
 #[link(wasm_import_module = "bevy_harmonize")]
 extern "C" {
     fn spawn_empty() -> u32;
@@ -65,5 +114,16 @@ extern "C" {
     ) -> u32;
     fn set_resource(local_type_id: u32, buffer_ptr: u32, buffer_len: u32);
     fn buffer_resource(local_type_id: u32) -> u32;
+    fn get_local_query_id(encoded_query_ptr: u32, encoded_query_len: u32) -> u32;
+    fn buffer_query(local_query_id: u32) -> u32;
+    fn set_query_component(
+        local_query_id: u32,
+        entity_id: u32,
+        component_index: u32,
+        buffer_ptr: u32,
+        buffer_len: u32,
+    );
     fn write_buffer_to(ptr: u32);
+    #[cfg(feature = "generate_manifest")]
+    fn submit_manifest(encoded_manifest_ptr: u64);
 }
@@ -20,6 +20,20 @@ impl Mod {
         self.schema
     }
 
+    /// Sets the semver version of this mod, checked against the requirements of mods depending on it
+    pub const fn version(&mut self, version: &'static str) -> &mut Self {
+        self.schema.version = Some(version);
+        self
+    }
+
+    /// Declares that this mod needs another mod, whose version matches a semver requirement like "^1.2"
+    ///
+    /// This mod only starts once the other mod is loaded.
+    pub const fn depends_on(&mut self, name: &'static str, version: &'static str) -> &mut Self {
+        self.schema.dependencies.push((name, version));
+        self
+    }
+
     pub const fn register_type<T>(&mut self) -> &mut Self
     where
         T: Typed,
@@ -69,6 +83,21 @@ mod tests {
         assert_eq!(SCHEMA.name, Some("A custom name"));
     }
 
+    #[test]
+    fn version_and_dependencies() {
+        const SCHEMA: Schema = Mod::new("Test dependencies")
+            .version("1.2.3")
+            .depends_on("Core gameplay", "^2.0")
+            .depends_on("Extras", "*")
+            .into_schema();
+
+        assert_eq!(SCHEMA.version, Some("1.2.3"));
+        assert_eq!(
+            SCHEMA.dependencies.into_slice(),
+            [("Core gameplay", "^2.0"), ("Extras", "*")]
+        );
+    }
+
     #[test]
     fn add_resource() {
         #[derive(Reflect, Debug)]
@@ -1,15 +1,24 @@
 use const_vec::ConstVec;
 
 use bevy_reflect::TypeInfo;
+#[cfg(not(feature = "generate_manifest"))]
+use common::SystemId;
+#[cfg(not(feature = "generate_manifest"))]
+use std::collections::HashMap;
 
 mod a_mod;
 pub use a_mod::Mod;
 
+#[cfg(not(feature = "generate_manifest"))]
+use crate::ecs::system::BoxedSystem;
 use crate::ecs::system::Schedule;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Schema {
     pub(crate) name: Option<&'static str>,
+    pub(crate) version: Option<&'static str>,
+    /// Names of other mods and the semver version requirement they should match
+    pub(crate) dependencies: ConstVec<(&'static str, &'static str), 32>,
     pub(crate) types: ConstVec<fn() -> &'static TypeInfo, 1024>,
     pub(crate) resources: ConstVec<(fn() -> &'static TypeInfo, fn() -> Vec<u8>), 128>,
     pub(crate) schedules: ConstVec<(fn() -> &'static TypeInfo, Schedule), 128>,
@@ -19,11 +28,33 @@ impl Schema {
     pub const fn new() -> Self {
         Self {
             name: None,
+            version: None,
+            dependencies: ConstVec::new(),
             types: ConstVec::new(),
             resources: ConstVec::new(),
             schedules: ConstVec::new(),
         }
     }
+
+    /// Instantiates every system of this schema, keyed by id
+    #[cfg(not(feature = "generate_manifest"))]
+    pub(crate) fn into_boxed_systems(self) -> HashMap<SystemId, BoxedSystem> {
+        let mut systems = HashMap::new();
+        for (_, schedule) in self.schedules.into_slice() {
+            systems.extend(schedule.boxed_systems());
+        }
+        systems
+    }
+
+    /// Instantiates every run condition of this schema, keyed by id
+    #[cfg(not(feature = "generate_manifest"))]
+    pub(crate) fn into_boxed_conditions(self) -> HashMap<SystemId, BoxedSystem<(), bool>> {
+        let mut conditions = HashMap::new();
+        for (_, schedule) in self.schedules.into_slice() {
+            conditions.extend(schedule.boxed_conditions());
+        }
+        conditions
+    }
 }
 
 // Tests
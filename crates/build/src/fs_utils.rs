@@ -36,6 +36,25 @@ where
         .into_with_trace(|| format!("Failed to remove file: {:?}", path))
 }
 
+/// Like [`remove_file`], but a file that does not exist is not an error
+pub async fn remove_file_if_exists<P, E>(path: P) -> Result<(), E>
+where
+    P: AsRef<Path>,
+    E: rancor::Source,
+{
+    let path = path.as_ref();
+    match async_fs::remove_file(path).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                Ok(())
+            } else {
+                Err(e).into_with_trace(|| format!("Failed to remove file: {:?}", path))
+            }
+        }
+    }
+}
+
 pub async fn create_dir_all<P, E>(path: P) -> Result<(), E>
 where
     P: AsRef<Path>,
@@ -7,6 +7,7 @@ use async_std::{
     task::spawn,
 };
 use bevy_utils::tracing::{error, info};
+use ed25519_dalek::Signer;
 use futures_concurrency::prelude::*;
 use rancor::{fail, ResultExt};
 use sha2::{Digest, Sha256};
@@ -17,11 +18,15 @@ use command::CargoCommand;
 
 mod fs_utils;
 
+pub use ed25519_dalek::SigningKey;
+
 const TARGET_DIR: &str = "target";
 const BUILD_DIR: &str = "bevy-harmonize-build";
 const TEMP_DIR: &str = "temp";
 const CODEGEN_DIR: &str = "codegen/crates";
 const WASM_TARGET: &str = "wasm32-unknown-unknown";
+/// Generating the manifest only runs the mod's schema, so 64 MiB of memory is plenty
+const MANIFEST_MEMORY_MAX_PAGES: u32 = 1024;
 
 pub async fn build<E>(
     release: bool,
@@ -31,6 +36,59 @@ pub async fn build<E>(
 where
     E: rancor::Source,
 {
+    let options = BuildOptions {
+        release,
+        ..Default::default()
+    };
+    build_with(mods_directory, cargo_directory, options).await
+}
+
+/// Like [`build`], but also signs the manifest of every mod with a developer key
+///
+/// Signatures are bundled into each package, see [`common::ModSignature`].
+pub async fn build_signed<E>(
+    release: bool,
+    mods_directory: PathBuf,
+    cargo_directory: PathBuf,
+    signing_key: SigningKey,
+) -> Result<Vec<PathBuf>, E>
+where
+    E: rancor::Source,
+{
+    let options = BuildOptions {
+        release,
+        signing_key: Some(signing_key),
+        ..Default::default()
+    };
+    build_with(mods_directory, cargo_directory, options).await
+}
+
+/// How [`build_with`] builds mods
+#[derive(Clone, Default)]
+pub struct BuildOptions {
+    pub release: bool,
+    /// Signs the manifest of every mod, see [`build_signed`]
+    pub signing_key: Option<SigningKey>,
+    /// Fails the build of mods with systems that access the same resources or components without being ordered
+    ///
+    /// See [`common::Ambiguity`], the modloader only warns about them.
+    pub deny_ambiguities: bool,
+}
+
+pub async fn build_with<E>(
+    mods_directory: PathBuf,
+    cargo_directory: PathBuf,
+    options: BuildOptions,
+) -> Result<Vec<PathBuf>, E>
+where
+    E: rancor::Source,
+{
+    let BuildOptions {
+        release,
+        signing_key,
+        deny_ambiguities,
+    } = options;
+
     let start = Instant::now();
     println!("Building mods from {:?}", mods_directory);
 
@@ -99,14 +157,18 @@ where
         .clone()
         .into_iter()
         .zip(encoded_manifests)
+        .zip(sources.iter().map(ModSource::get_assets_dir))
         .collect::<Vec<_>>()
         .into_co_stream()
-        .map(|(package, encoded_manifest)| {
+        .map(|((package, encoded_manifest), assets_dir)| {
             final_processing(
                 package,
                 encoded_manifest,
+                assets_dir,
                 codegen_build_dir.clone(),
                 dest_dir.clone(),
+                signing_key.clone(),
+                deny_ambiguities,
             )
         })
         .collect::<Vec<Result<(), _>>>()
@@ -121,8 +183,9 @@ where
         .iter()
         .map(|source| {
             let package_name = source.get_package_name();
-            let wasm_file = dest_dir.join(format!("{}.wasm", package_name));
-            wasm_file
+            let package_file =
+                dest_dir.join(format!("{}.{}", package_name, common::PACKAGE_EXTENSION));
+            package_file
         })
         .collect();
     Ok(result)
@@ -173,6 +236,12 @@ impl ModSource {
         Ok(())
     }
 
+    /// Files in a directory next to the source file, named like it with an ".assets" extension, are bundled
+    /// with the mod. For example "my_mod.assets/" for "my_mod.rs".
+    fn get_assets_dir(&self) -> PathBuf {
+        self.0.with_extension("assets")
+    }
+
     fn get_package_name(&self) -> String {
         let path_hash: [u8; 32] = Sha256::digest(self.0.as_os_str().as_encoded_bytes()).into();
         let package_suffix: String = path_hash[..4]
@@ -230,8 +299,11 @@ where
 async fn final_processing<E>(
     package: String,
     encoded_manifest: Vec<u8>,
+    assets_dir: PathBuf,
     codegen_build_dir: PathBuf,
     dest_dir: PathBuf,
+    signing_key: Option<SigningKey>,
+    deny_ambiguities: bool,
 ) -> Result<(), E>
 where
     E: rancor::Source,
@@ -240,29 +312,73 @@ where
     let wasm_bytes = fs_utils::read(&wasm_path).await?;
     let wasm_hash = common::FileHash::from_sha256(Sha256::digest(&wasm_bytes).into());
 
-    let common::ModManifest {
-        wasm_hash: _,
-        types,
-        features,
-    } = bitcode::decode(&encoded_manifest).unwrap();
+    // The ABI version is the one of the api the mod was built against, not the one of this crate
+    let (abi_version, manifest) = match common::ModManifest::abi_version(&encoded_manifest)
+        .and_then(|abi_version| {
+            common::ModManifest::decode(&encoded_manifest).map(|manifest| (abi_version, manifest))
+        }) {
+        Ok(decoded) => decoded,
+        Err(error) => fail!(InvalidManifest { package, error }),
+    };
     let manifest = common::ModManifest {
         wasm_hash,
-        types,
-        features,
+        ..manifest
     };
 
+    if deny_ambiguities {
+        let ambiguities = ambiguities(&manifest);
+        if !ambiguities.is_empty() {
+            fail!(AmbiguousSystems {
+                package,
+                ambiguities
+            });
+        }
+    }
+
     let as_string = format!("{:#?}", manifest);
     let path = dest_dir.join(format!("{}.manifest.txt", package));
     fs_utils::write(&path, as_string).await?;
 
-    let encoded_manifest = bitcode::encode(&manifest);
-    let path = dest_dir.join(format!("{}.manifest", package));
-    fs_utils::write(&path, encoded_manifest).await?;
+    let encoded_manifest = manifest.encode_with_abi(abi_version);
+    let encoded_signature = signing_key.map(|signing_key| {
+        bitcode::encode(&common::ModSignature {
+            public_key: signing_key.verifying_key().to_bytes(),
+            signature: signing_key.sign(&encoded_manifest).to_bytes(),
+        })
+    });
 
-    // Move wasm file to target directory
-    let src = wasm_path;
-    let dst = dest_dir.join(format!("{}.wasm", package));
-    fs_utils::rename(src, dst).await?;
+    // Everything is in the package now, loose files left over from previous builds would be stale
+    for extension in ["manifest", "wasm", "sig"] {
+        let path = dest_dir.join(format!("{}.{}", package, extension));
+        fs_utils::remove_file_if_exists(&path).await?;
+    }
+
+    // Bundle everything into a single package
+    let mut assets = Vec::new();
+    if assets_dir.is_dir() {
+        for file in fs_utils::list_files_in_dir(&assets_dir).await? {
+            // Mods look up their assets by name, so it has to be a string
+            let Some(name) = file
+                .strip_prefix(&assets_dir)
+                .ok()
+                .and_then(|name| name.to_str())
+            else {
+                fail!(InvalidAssetName { package, file });
+            };
+            let name = name.replace("\\", "/");
+            assets.push((name, fs_utils::read(&file).await?));
+        }
+        assets.sort();
+    }
+    let package_bytes = common::ModPackage {
+        manifest: encoded_manifest,
+        wasm: wasm_bytes,
+        signature: encoded_signature,
+        assets,
+    }
+    .encode();
+    let path = dest_dir.join(format!("{}.{}", package, common::PACKAGE_EXTENSION));
+    fs_utils::write(&path, package_bytes).await?;
 
     Ok(())
 }
@@ -337,6 +453,112 @@ impl std::fmt::Display for UnsuccessfulExitStatus {
 
 impl Error for UnsuccessfulExitStatus {}
 
+/// Describes the systems of a mod that access the same resources or components without being ordered
+fn ambiguities(manifest: &common::ModManifest) -> Vec<String> {
+    let mut descriptions = Vec::new();
+    for feature in manifest.features.iter() {
+        let mut labels: Vec<common::StableId> = Vec::new();
+        for descriptor in feature.schedules.iter() {
+            if !labels.contains(&descriptor.id) {
+                labels.push(descriptor.id);
+            }
+        }
+
+        // Schedules with the same label are merged by the modloader
+        for label in labels {
+            let schedules: Vec<_> = feature
+                .schedules
+                .iter()
+                .filter(|descriptor| descriptor.id == label)
+                .map(|descriptor| &descriptor.schedule)
+                .collect();
+            let name = |id: common::SystemId| {
+                schedules
+                    .iter()
+                    .flat_map(|schedule| schedule.systems.iter())
+                    .find(|system| system.id == id)
+                    .map_or("unknown", |system| system.name)
+            };
+
+            for ambiguity in common::Schedule::ambiguities(&schedules) {
+                let [first, second] = ambiguity.systems.map(name);
+                let resources: Vec<_> = ambiguity
+                    .resources
+                    .iter()
+                    .chain(ambiguity.components.iter())
+                    .map(|resource| resource.name)
+                    .collect();
+                descriptions.push(format!(
+                    "{} and {} both access {} in schedule {}",
+                    first,
+                    second,
+                    resources.join(", "),
+                    label.name
+                ));
+            }
+        }
+    }
+    descriptions
+}
+
+#[derive(Debug)]
+struct AmbiguousSystems {
+    package: String,
+    ambiguities: Vec<String>,
+}
+
+impl std::fmt::Display for AmbiguousSystems {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Systems of mod {} access the same resources or components without being ordered",
+            self.package
+        )?;
+        for ambiguity in self.ambiguities.iter() {
+            write!(f, "\n  {}", ambiguity)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AmbiguousSystems {}
+
+#[derive(Debug)]
+struct InvalidManifest {
+    package: String,
+    error: common::ManifestError,
+}
+
+impl std::fmt::Display for InvalidManifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Mod {} exported an invalid manifest: {}",
+            self.package, self.error
+        )
+    }
+}
+
+impl Error for InvalidManifest {}
+
+#[derive(Debug)]
+struct InvalidAssetName {
+    package: String,
+    file: PathBuf,
+}
+
+impl std::fmt::Display for InvalidAssetName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Asset {:?} of mod {} does not have a valid UTF-8 name",
+            self.file, self.package
+        )
+    }
+}
+
+impl Error for InvalidAssetName {}
+
 async fn output_cargo_stderr(output: impl Read + Unpin) {
     let reader = BufReader::new(output);
     let mut lines = reader.lines();
@@ -393,8 +615,11 @@ where
     let module = wasmer::Module::new(&store, bytes).expect("invalid wasm");
 
     // Initiate shared memory pool
-    let memory = wasmer::Memory::new(&mut store, wasmer::MemoryType::new(17, None, false))
-        .expect("wasm memory allocation failed");
+    let memory = wasmer::Memory::new(
+        &mut store,
+        wasmer::MemoryType::new(17, Some(MANIFEST_MEMORY_MAX_PAGES), false),
+    )
+    .expect("wasm memory allocation failed");
 
     let state = State {
         component_id_counter: 0,
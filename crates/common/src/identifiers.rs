@@ -1,7 +1,24 @@
 use bevy_reflect::Reflect;
 
+/// Runs once, before the first [`Update`] of a mod
 #[derive(Reflect, Clone, Copy)]
 pub struct Start;
 
+#[derive(Reflect, Clone, Copy)]
+pub struct First;
+
+#[derive(Reflect, Clone, Copy)]
+pub struct PreUpdate;
+
 #[derive(Reflect, Clone, Copy)]
 pub struct Update;
+
+#[derive(Reflect, Clone, Copy)]
+pub struct PostUpdate;
+
+#[derive(Reflect, Clone, Copy)]
+pub struct Last;
+
+/// Runs at a fixed rate, possibly several times per frame
+#[derive(Reflect, Clone, Copy)]
+pub struct FixedUpdate;
@@ -13,6 +13,15 @@ pub use schedule::*;
 mod identifiers;
 pub use identifiers::*;
 
+mod manifest;
+pub use manifest::*;
+
+mod package;
+pub use package::*;
+
+mod query;
+pub use query::*;
+
 mod type_signature;
 pub use type_signature::*;
 
@@ -109,6 +118,10 @@ impl SystemId {
         Self(result)
     }
 
+    pub fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
     pub fn get_raw(&self) -> u64 {
         self.0
     }
@@ -120,11 +133,11 @@ impl fmt::Debug for SystemId {
     }
 }
 
-#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Hash, Copy)]
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Hash)]
 pub enum Param<'a> {
     Command,
     Res { mutable: bool, id: StableId<'a> },
-    // TODO: Query, etc
+    Query(QueryDescriptor<'a>),
 }
 
 impl Param<'_> {
@@ -135,6 +148,7 @@ impl Param<'_> {
                 mutable: *mutable,
                 id: id.to_owned(),
             },
+            Param::Query(query) => OwnedParam::Query(query.to_owned()),
         }
     }
 }
@@ -143,6 +157,7 @@ impl Param<'_> {
 pub enum OwnedParam {
     Command,
     Res { mutable: bool, id: OwnedStableId },
+    Query(OwnedQueryDescriptor),
 }
 
 #[derive(Encode, Decode, PartialEq, Debug)]
@@ -152,13 +167,39 @@ pub struct FeatureDescriptor<'a> {
     pub schedules: Vec<schedule::ScheduleDescriptor<'a>>,
 }
 
+/// Another mod that has to be loaded before a mod can start
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Dependency<'a> {
+    /// The name of the mod, as given to `Mod::new`
+    pub name: &'a str,
+    /// A semver version requirement, like "^1.2"
+    pub version: &'a str,
+}
+
 #[derive(Encode, Decode, PartialEq, Debug)]
 pub struct ModManifest<'a> {
     pub wasm_hash: FileHash,
+    /// The version of `bevy_harmonize_api` the mod was built with
+    ///
+    /// Only informational, whether the modloader can run the mod is decided by the ABI version in the header of the
+    /// encoded manifest, which is written by the same `bevy_harmonize_api`.
+    pub api_version: &'a str,
+    /// The semver version of the mod
+    pub version: &'a str,
+    pub dependencies: Vec<Dependency<'a>>,
     pub types: Vec<TypeSignature<'a>>,
     pub features: Vec<FeatureDescriptor<'a>>,
 }
 
+/// An ed25519 signature of an encoded [`ModManifest`], stored next to it in a ".sig" file
+///
+/// Since the manifest pins the hash of the wasm file, this authenticates the whole mod.
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct ModSignature {
+    pub public_key: [u8; 32],
+    pub signature: [u8; 64],
+}
+
 #[derive(Encode, Decode, PartialEq)]
 pub struct FileHash([u8; 16]);
 
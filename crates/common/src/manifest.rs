@@ -0,0 +1,120 @@
+use std::{error::Error, fmt, ops::RangeInclusive};
+
+use crate::ModManifest;
+
+/// The first bytes of every encoded manifest
+const MAGIC: &[u8; 4] = b"HMAN";
+
+/// Bumped whenever the layout of [`ModManifest`] changes
+pub const MANIFEST_VERSION: u16 = 2;
+
+/// Bumped whenever the imports or exports shared by the modloader and mods change in an incompatible way
+///
+/// 2: Mods export `bevy_harmonize_run_condition`
+/// 3: Mods may import the query functions
+pub const ABI_VERSION: u32 = 3;
+
+/// The ABI versions of mods the modloader can still run
+///
+/// Mods built against an older ABI may lack exports or call imports the modloader no longer provides, so only the
+/// current one is supported until the host can tell them apart.
+pub const SUPPORTED_ABI_VERSIONS: RangeInclusive<u32> = ABI_VERSION..=ABI_VERSION;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+impl<'a> ModManifest<'a> {
+    /// Encodes the manifest behind a header made of the bytes `HMAN`, the [`MANIFEST_VERSION`] and the
+    /// [`ABI_VERSION`] of this crate
+    ///
+    /// Mods encode their own manifest, so the header holds the ABI of the `bevy_harmonize_api` they were built
+    /// against.
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_with_abi(ABI_VERSION)
+    }
+
+    /// Encodes the manifest for the given ABI version, see [`Self::encode`]
+    ///
+    /// Tools changing the manifest of a mod keep the ABI version read by [`Self::abi_version`], since it is the
+    /// one of the mod and not their own.
+    pub fn encode_with_abi(&self, abi_version: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&MANIFEST_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&abi_version.to_le_bytes());
+        bytes.extend_from_slice(&bitcode::encode(self));
+        bytes
+    }
+
+    /// Reads the ABI version the mod was built with from the header of an encoded manifest
+    ///
+    /// Unlike [`Self::decode`], the ABI version does not have to be supported.
+    pub fn abi_version(bytes: &[u8]) -> Result<u32, ManifestError> {
+        if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(ManifestError::Unversioned);
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != MANIFEST_VERSION {
+            return Err(ManifestError::UnsupportedVersion(version));
+        }
+
+        Ok(u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]))
+    }
+
+    /// Decodes a manifest, checking its header first so incompatible mods are reported as such
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, ManifestError> {
+        let abi_version = Self::abi_version(bytes)?;
+        if !SUPPORTED_ABI_VERSIONS.contains(&abi_version) {
+            return Err(ManifestError::IncompatibleAbi(abi_version));
+        }
+
+        bitcode::decode(&bytes[HEADER_LEN..]).map_err(|_| ManifestError::Corrupted)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ManifestError {
+    /// The manifest has no header, it was built before manifests were versioned
+    Unversioned,
+    /// The manifest was written by an incompatible version of the build tool
+    UnsupportedVersion(u16),
+    /// The mod was built against an incompatible version of `bevy_harmonize_api`
+    IncompatibleAbi(u32),
+    Corrupted,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unversioned => write!(
+                f,
+                "Manifest has no version header, the mod needs to be rebuilt"
+            ),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported manifest version {}, expected {}",
+                version, MANIFEST_VERSION
+            ),
+            Self::IncompatibleAbi(version)
+                if SUPPORTED_ABI_VERSIONS.start() == SUPPORTED_ABI_VERSIONS.end() =>
+            {
+                write!(
+                    f,
+                    "Mod was built for ABI version {}, but only version {} is supported",
+                    version,
+                    SUPPORTED_ABI_VERSIONS.start()
+                )
+            }
+            Self::IncompatibleAbi(version) => write!(
+                f,
+                "Mod was built for ABI version {}, but only versions {} to {} are supported",
+                version,
+                SUPPORTED_ABI_VERSIONS.start(),
+                SUPPORTED_ABI_VERSIONS.end()
+            ),
+            Self::Corrupted => write!(f, "Corrupted manifest"),
+        }
+    }
+}
+
+impl Error for ManifestError {}
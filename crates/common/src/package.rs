@@ -0,0 +1,129 @@
+use std::{error::Error, fmt};
+
+use bitcode::{Decode, Encode};
+
+/// The file extension of mod packages
+pub const PACKAGE_EXTENSION: &str = "hmod";
+
+/// The first bytes of every mod package
+const MAGIC: &[u8; 4] = b"HMOD";
+
+/// Bumped whenever the layout of [`ModPackage`] changes
+pub const PACKAGE_VERSION: u16 = 1;
+
+/// Everything needed to load a mod, bundled into a single file
+///
+/// A package starts with a header made of the bytes `HMOD` and the [`PACKAGE_VERSION`] it was written with,
+/// followed by the encoded package.
+#[derive(Encode, Decode, PartialEq, Debug, Default, Clone)]
+pub struct ModPackage {
+    /// A [`crate::ModManifest`], encoded with [`crate::ModManifest::encode`]
+    pub manifest: Vec<u8>,
+    pub wasm: Vec<u8>,
+    /// An encoded [`crate::ModSignature`] of the manifest
+    pub signature: Option<Vec<u8>>,
+    /// Files shipped with the mod, by their path relative to the mod's asset directory
+    pub assets: Vec<(String, Vec<u8>)>,
+}
+
+impl ModPackage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&PACKAGE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&bitcode::encode(self));
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, PackageError> {
+        let header_len = MAGIC.len() + 2;
+        if bytes.len() < header_len || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(PackageError::NotAPackage);
+        }
+
+        let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+        if version != PACKAGE_VERSION {
+            return Err(PackageError::UnsupportedVersion(version));
+        }
+
+        bitcode::decode(&bytes[header_len..]).map_err(|_| PackageError::Corrupted)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PackageError {
+    NotAPackage,
+    /// The package was written by an incompatible version of the build tool
+    UnsupportedVersion(u16),
+    Corrupted,
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAPackage => write!(f, "Not a mod package"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported package version {}, expected {}",
+                version, PACKAGE_VERSION
+            ),
+            Self::Corrupted => write!(f, "Corrupted mod package"),
+        }
+    }
+}
+
+impl Error for PackageError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package() -> ModPackage {
+        ModPackage {
+            manifest: vec![1, 2, 3],
+            wasm: b"\0asm".to_vec(),
+            signature: Some(vec![4; 64]),
+            assets: vec![("textures/icon.png".into(), vec![5, 6])],
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let package = package();
+        assert_eq!(ModPackage::decode(&package.encode()), Ok(package));
+
+        let unsigned = ModPackage::default();
+        assert_eq!(ModPackage::decode(&unsigned.encode()), Ok(unsigned));
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut encoded = package().encode();
+        encoded[..MAGIC.len()].copy_from_slice(b"\0asm");
+        assert_eq!(ModPackage::decode(&encoded), Err(PackageError::NotAPackage));
+        assert_eq!(ModPackage::decode(b"HM"), Err(PackageError::NotAPackage));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut encoded = package().encode();
+        encoded[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(PACKAGE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            ModPackage::decode(&encoded),
+            Err(PackageError::UnsupportedVersion(PACKAGE_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn truncated_packages_are_rejected() {
+        let encoded = package().encode();
+        assert_eq!(
+            ModPackage::decode(&encoded[..encoded.len() - 1]),
+            Err(PackageError::Corrupted)
+        );
+        assert_eq!(
+            ModPackage::decode(&encoded[..MAGIC.len() + 2]),
+            Err(PackageError::Corrupted)
+        );
+    }
+}
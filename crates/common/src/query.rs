@@ -0,0 +1,62 @@
+use super::*;
+
+/// The components a query fetches, and the components matching entities must or must not have
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Hash, Default)]
+pub struct QueryDescriptor<'a> {
+    /// Fetched for every matching entity, in the order the mod expects them
+    pub components: Vec<QueryComponent<'a>>,
+    /// Required on matching entities, but not fetched
+    pub with: Vec<StableId<'a>>,
+    /// Matching entities must not have any of these
+    pub without: Vec<StableId<'a>>,
+}
+
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct QueryComponent<'a> {
+    /// Whether the mod may write the component back
+    pub mutable: bool,
+    pub id: StableId<'a>,
+}
+
+impl<'a> QueryDescriptor<'a> {
+    pub fn to_owned(&self) -> OwnedQueryDescriptor {
+        OwnedQueryDescriptor {
+            components: self
+                .components
+                .iter()
+                .map(|component| OwnedQueryComponent {
+                    mutable: component.mutable,
+                    id: component.id.to_owned(),
+                })
+                .collect(),
+            with: self.with.iter().map(StableId::to_owned).collect(),
+            without: self.without.iter().map(StableId::to_owned).collect(),
+        }
+    }
+
+    /// Whether no entity can match both queries, because one requires a component the other excludes
+    pub fn is_disjoint(&self, other: &QueryDescriptor<'a>) -> bool {
+        let excludes = |query: &QueryDescriptor<'a>, other: &QueryDescriptor<'a>| {
+            query
+                .components
+                .iter()
+                .map(|component| &component.id)
+                .chain(query.with.iter())
+                .any(|id| other.without.contains(id))
+        };
+        excludes(self, other) || excludes(other, self)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct OwnedQueryDescriptor {
+    pub components: Vec<OwnedQueryComponent>,
+    pub with: Vec<OwnedStableId>,
+    pub without: Vec<OwnedStableId>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct OwnedQueryComponent {
+    pub mutable: bool,
+    pub id: OwnedStableId,
+}
@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 use super::*;
 
 #[derive(Encode, Decode, PartialEq, Debug)]
@@ -47,3 +49,237 @@ pub enum SystemSet<'a> {
     Anonymous(Vec<SystemId>),
     Named(StableId<'a>),
 }
+
+/// Two systems of a schedule that access the same resources or components, at least one of them mutably,
+/// without any constraint ordering them
+///
+/// Which of the two runs first is up to the modloader, so the outcome may change between builds of the mod.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Ambiguity<'a> {
+    pub systems: [SystemId; 2],
+    /// The resources both systems access
+    pub resources: Vec<StableId<'a>>,
+    /// The components both systems query, on entities that may match both queries
+    pub components: Vec<StableId<'a>>,
+}
+
+impl<'a> Ambiguity<'a> {
+    pub fn to_owned(&self) -> OwnedAmbiguity {
+        OwnedAmbiguity {
+            systems: self.systems,
+            resources: self.resources.iter().map(StableId::to_owned).collect(),
+            components: self.components.iter().map(StableId::to_owned).collect(),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct OwnedAmbiguity {
+    pub systems: [SystemId; 2],
+    pub resources: Vec<OwnedStableId>,
+    pub components: Vec<OwnedStableId>,
+}
+
+impl<'a> Schedule<'a> {
+    /// Finds the ambiguities between the systems of schedules with the same label, in the order they are declared
+    ///
+    /// Systems are ordered if there is a path of constraints between them, like the modloader orders them.
+    pub fn ambiguities(schedules: &[&Schedule<'a>]) -> Vec<Ambiguity<'a>> {
+        let mut graph = OrderGraph::default();
+        for constraint in schedules
+            .iter()
+            .flat_map(|schedule| schedule.constraints.iter())
+        {
+            graph.add_constraint(constraint);
+        }
+
+        let mut systems: Vec<&System<'a>> = Vec::new();
+        for system in schedules
+            .iter()
+            .flat_map(|schedule| schedule.systems.iter())
+        {
+            if !systems.iter().any(|other| other.id == system.id) {
+                systems.push(system);
+            }
+        }
+        let reachable: Vec<HashSet<SystemId>> = systems
+            .iter()
+            .map(|system| graph.reachable(system.id))
+            .collect();
+
+        let mut ambiguities = Vec::new();
+        for (i, first) in systems.iter().enumerate() {
+            for (j, second) in systems.iter().enumerate().skip(i + 1) {
+                if reachable[i].contains(&second.id) || reachable[j].contains(&first.id) {
+                    continue;
+                }
+                let resources = first.resource_conflicts(second);
+                let components = first.component_conflicts(second);
+                if !resources.is_empty() || !components.is_empty() {
+                    ambiguities.push(Ambiguity {
+                        systems: [first.id, second.id],
+                        resources,
+                        components,
+                    });
+                }
+            }
+        }
+        ambiguities
+    }
+}
+
+impl<'a> System<'a> {
+    /// The resources both systems access, where at least one of them writes it
+    fn resource_conflicts(&self, other: &System<'a>) -> Vec<StableId<'a>> {
+        let mut resources = Vec::new();
+        for param in self.params.iter() {
+            let Param::Res { mutable, id } = param else {
+                continue;
+            };
+            let conflicts = other.params.iter().any(|other| match other {
+                Param::Res {
+                    mutable: other_mutable,
+                    id: other_id,
+                } => other_id == id && (*mutable || *other_mutable),
+                Param::Command | Param::Query(_) => false,
+            });
+            if conflicts && !resources.contains(id) {
+                resources.push(*id);
+            }
+        }
+        resources
+    }
+
+    /// The components both systems query, where at least one of them writes it
+    fn component_conflicts(&self, other: &System<'a>) -> Vec<StableId<'a>> {
+        let mut components = Vec::new();
+        for query in self.queries() {
+            for other_query in other.queries() {
+                if query.is_disjoint(other_query) {
+                    continue;
+                }
+                for component in query.components.iter() {
+                    let conflicts = other_query.components.iter().any(|other| {
+                        other.id == component.id && (component.mutable || other.mutable)
+                    });
+                    if conflicts && !components.contains(&component.id) {
+                        components.push(component.id);
+                    }
+                }
+            }
+        }
+        components
+    }
+
+    fn queries(&self) -> impl Iterator<Item = &QueryDescriptor<'a>> {
+        self.params.iter().filter_map(|param| match param {
+            Param::Query(query) => Some(query),
+            _ => None,
+        })
+    }
+}
+
+/// The order constraints put on systems, with a node where each set starts and one where it ends
+#[derive(Default)]
+struct OrderGraph<'a> {
+    edges: HashMap<OrderNode, Vec<OrderNode>>,
+    sets: HashMap<SetKey<'a>, usize>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum OrderNode {
+    System(SystemId),
+    SetStart(usize),
+    SetEnd(usize),
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum SetKey<'a> {
+    /// Sorted, so the same systems always make the same set
+    Anonymous(Vec<SystemId>),
+    Named(StableId<'a>),
+}
+
+impl<'a> OrderGraph<'a> {
+    fn add_constraint(&mut self, constraint: &Constraint<'a>) {
+        match constraint {
+            Constraint::Order { before, after } => {
+                if let (Some((_, before)), Some((after, _))) =
+                    (self.set_nodes(before), self.set_nodes(after))
+                {
+                    self.add_edge(before, after);
+                }
+            }
+            Constraint::Condition { set, condition } => {
+                if let Some((start, _)) = self.set_nodes(set) {
+                    self.add_edge(OrderNode::System(*condition), start);
+                }
+            }
+            Constraint::Includes { parent_name, set } => {
+                let (parent_start, parent_end) = self.set(SetKey::Named(*parent_name));
+                if let Some((start, end)) = self.set_nodes(set) {
+                    self.add_edge(parent_start, start);
+                    self.add_edge(end, parent_end);
+                }
+            }
+        }
+    }
+
+    /// Empty anonymous sets have no nodes
+    fn set_nodes(&mut self, set: &SystemSet<'a>) -> Option<(OrderNode, OrderNode)> {
+        match set {
+            SystemSet::Anonymous(systems) => match systems.as_slice() {
+                [] => None,
+                [system] => Some((OrderNode::System(*system), OrderNode::System(*system))),
+                systems => {
+                    let mut systems = systems.to_vec();
+                    systems.sort();
+                    Some(self.set(SetKey::Anonymous(systems)))
+                }
+            },
+            SystemSet::Named(id) => Some(self.set(SetKey::Named(*id))),
+        }
+    }
+
+    fn set(&mut self, key: SetKey<'a>) -> (OrderNode, OrderNode) {
+        let index = match self.sets.get(&key) {
+            Some(index) => *index,
+            None => {
+                let index = self.sets.len();
+                if let SetKey::Anonymous(systems) = &key {
+                    for system in systems.clone() {
+                        self.add_edge(OrderNode::SetStart(index), OrderNode::System(system));
+                        self.add_edge(OrderNode::System(system), OrderNode::SetEnd(index));
+                    }
+                }
+                self.sets.insert(key, index);
+                index
+            }
+        };
+        (OrderNode::SetStart(index), OrderNode::SetEnd(index))
+    }
+
+    fn add_edge(&mut self, from: OrderNode, to: OrderNode) {
+        self.edges.entry(from).or_default().push(to);
+    }
+
+    /// The systems that have to run after the given system
+    fn reachable(&self, system: SystemId) -> HashSet<SystemId> {
+        let mut visited = HashSet::new();
+        let mut stack = vec![OrderNode::System(system)];
+        while let Some(node) = stack.pop() {
+            for next in self.edges.get(&node).into_iter().flatten() {
+                if visited.insert(*next) {
+                    stack.push(*next);
+                }
+            }
+        }
+        visited
+            .into_iter()
+            .filter_map(|node| match node {
+                OrderNode::System(system) => Some(system),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -82,3 +82,19 @@ pub enum VariantSignature<'a> {
         name: &'a str,
     },
 }
+
+impl<'a> TypeSignature<'a> {
+    pub fn ty(&self) -> &StableId<'a> {
+        match self {
+            Self::Struct { ty, .. }
+            | Self::TupleStruct { ty, .. }
+            | Self::Tuple { ty, .. }
+            | Self::List { ty, .. }
+            | Self::Array { ty, .. }
+            | Self::Map { ty, .. }
+            | Self::Set { ty, .. }
+            | Self::Enum { ty, .. }
+            | Self::Opaque { ty, .. } => ty,
+        }
+    }
+}
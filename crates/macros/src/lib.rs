@@ -0,0 +1,55 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Error, ItemConst};
+
+/// Marks the [`Schema`] of a mod, generating the wasm exports used by the modloader
+///
+/// ```ignore
+/// #[api::main]
+/// pub const SCHEMA: Schema = Mod::new("My mod").into_schema();
+/// ```
+///
+/// - With the `generate_manifest` feature, exports `bevy_harmonize_generate_manifest` which submits the
+///   encoded manifest to the build tool
+/// - With the `wasm_runtime` feature, exports `bevy_harmonize_run_system` which runs a system by its id, and
+///   `bevy_harmonize_run_condition` which evaluates a run condition by its id
+///
+/// Mods depend on the api under the name `api` (see `crates/build/templates/mod.toml`), so the generated
+/// code refers to it by that name.
+///
+/// [`Schema`]: https://docs.rs/bevy_harmonize_api
+#[proc_macro_attribute]
+pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+        return Error::new(attr.span(), "#[main] does not take any arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let item = parse_macro_input!(item as ItemConst);
+    let ident = &item.ident;
+
+    quote! {
+        #item
+
+        #[cfg(feature = "generate_manifest")]
+        #[no_mangle]
+        pub extern "C" fn bevy_harmonize_generate_manifest() {
+            api::__internal::generate_manifest(#ident);
+        }
+
+        #[cfg(feature = "wasm_runtime")]
+        #[no_mangle]
+        pub extern "C" fn bevy_harmonize_run_system(system_id: u64) {
+            api::__internal::run_system(#ident, system_id);
+        }
+
+        #[cfg(feature = "wasm_runtime")]
+        #[no_mangle]
+        pub extern "C" fn bevy_harmonize_run_condition(system_id: u64) -> u32 {
+            api::__internal::run_condition(#ident, system_id) as u32
+        }
+    }
+    .into()
+}
@@ -1,5 +1,6 @@
 use api::prelude::*;
 
+#[api::main]
 pub const SCHEMA: Schema = Mod::new("My cube")
     .add_resource::<CountFrames>()
     .add_systems(Start, spawn_cube)
@@ -39,10 +40,12 @@ fn update_frame_count(mut resource: ResMut<CountFrames>) {
 }
 
 // From bevy's `examples\3d\3d_shapes.rs`
-fn rotate_cube(// mut query: Query<&mut Transform, With<MyCube>>,
+fn rotate_cube(
+    mut query: Query<&mut Transform, With<MyCube>>,
     // time: Res<Time>
 ) {
-    // for mut transform in &mut query {
-    //     transform.rotate_y(time.delta_seconds() / 2.);
-    // }
+    for mut transform in &mut query {
+        // transform.rotate_y(time.delta_seconds() / 2.);
+        transform.rotate_y(1. / 120.);
+    }
 }
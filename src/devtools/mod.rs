@@ -2,28 +2,42 @@ use std::path::{Path, PathBuf};
 
 use async_channel::Receiver;
 use bevy_app::{App, Plugin, PostUpdate, PreStartup};
-use bevy_ecs::system::ResMut;
+use bevy_ecs::{
+    event::EventReader,
+    system::{Res, ResMut},
+};
 use bevy_ecs_macros::Resource;
-use bevy_harmonize_build::build;
+use bevy_harmonize_build::{build_with, BuildOptions};
 use bevy_tasks::{block_on, poll_once, AsyncComputeTaskPool, Task};
 use bevy_utils::tracing::*;
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 
-use crate::mods::Mods;
+use crate::mods::{DotGraph, ModLoaded, ModReloaded, Mods};
 
 const MOD_DIR: &str = "./mods";
 const CARGO_DIR: &str = ".";
+/// Outside of [`MOD_DIR`], so writing the graphs does not trigger a rebuild
+const SCHEDULE_GRAPH_DIR: &str = "./target/bevy-harmonize-build/schedules";
 
 pub(crate) struct DevtoolsPlugin;
 
 impl Plugin for DevtoolsPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<BuildTask>()
+        app.init_resource::<DevtoolsSettings>()
+            .init_resource::<BuildTask>()
             .add_systems(PreStartup, update_build)
-            .add_systems(PostUpdate, update_build);
+            .add_systems(PostUpdate, (update_build, write_schedule_graphs));
     }
 }
 
+/// How the devtools build the mods in `./mods` whenever they change
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct DevtoolsSettings {
+    /// Fails the build of mods with unordered systems whose accesses conflict, instead of only warning when they
+    /// load, see [`BuildOptions::deny_ambiguities`]
+    pub deny_ambiguities: bool,
+}
+
 #[derive(Resource)]
 struct BuildTask {
     compute: Option<Task<Result<Vec<PathBuf>, rancor::Error>>>,
@@ -69,7 +83,11 @@ impl Default for BuildTask {
     }
 }
 
-fn update_build(mut task: ResMut<BuildTask>, mut mods: ResMut<Mods>) {
+fn update_build(
+    mut task: ResMut<BuildTask>,
+    mut mods: ResMut<Mods>,
+    settings: Res<DevtoolsSettings>,
+) {
     // Check on the active build task
     if let Some(compute) = &mut task.compute {
         match block_on(poll_once(compute)) {
@@ -92,8 +110,82 @@ fn update_build(mut task: ResMut<BuildTask>, mut mods: ResMut<Mods>) {
         let mods_directory = Path::new(MOD_DIR).to_path_buf();
         let cargo_directory = Path::new(CARGO_DIR).to_path_buf();
 
-        let future = build::<rancor::Error>(!debug, mods_directory, cargo_directory);
+        let options = BuildOptions {
+            release: !debug,
+            deny_ambiguities: settings.deny_ambiguities,
+            ..Default::default()
+        };
+        let future = build_with::<rancor::Error>(mods_directory, cargo_directory, options);
         task.compute
             .replace(AsyncComputeTaskPool::get().spawn(future));
     }
 }
+
+/// Writes the schedules of every loaded or reloaded mod as Graphviz DOT files, one per feature and graph
+fn write_schedule_graphs(
+    mods: Res<Mods>,
+    mut loaded_events: EventReader<ModLoaded>,
+    mut reloaded_events: EventReader<ModReloaded>,
+) {
+    let handles = loaded_events
+        .read()
+        .map(|event| event.handle)
+        .chain(reloaded_events.read().map(|event| event.handle));
+    for handle in handles {
+        let Some(loaded) = mods.get(handle) else {
+            continue;
+        };
+
+        // Every mod gets its own directory, so mods with features of the same name do not overwrite each other
+        let directory = Path::new(SCHEDULE_GRAPH_DIR).join(file_name(loaded.name()));
+        if let Err(err) = std::fs::create_dir_all(&directory) {
+            error!(
+                "Could not create schedule graph directory {:?}: {}",
+                directory, err
+            );
+            continue;
+        }
+        for feature in loaded.features() {
+            for (id, schedule) in feature.schedules.iter() {
+                for (graph, extension) in
+                    [(DotGraph::Sets, "sets"), (DotGraph::Flattened, "flattened")]
+                {
+                    let path = directory.join(format!(
+                        "{}.{}.{}.dot",
+                        file_name(&feature.name),
+                        file_name(&format!("{}::{}", id.crate_name, id.name)),
+                        extension
+                    ));
+                    if let Err(err) = std::fs::write(&path, schedule.to_dot(graph)) {
+                        error!("Could not write schedule graph {:?}: {}", path, err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Replaces every character of a name chosen by a mod that could escape [`SCHEDULE_GRAPH_DIR`], such as `/` or `..`
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_stay_in_their_directory() {
+        assert_eq!(file_name("physics-mod_2"), "physics-mod_2");
+        assert_eq!(file_name("../../etc/passwd"), "______etc_passwd");
+        assert_eq!(file_name("bevy_app::Update"), "bevy_app__Update");
+        assert_eq!(file_name(r"C:\mods"), "C__mods");
+    }
+}
@@ -1,9 +1,22 @@
 use bevy_app::{App, Plugin};
 
 mod devtools;
+pub use devtools::DevtoolsSettings;
 
 mod mods;
+pub use mods::{
+    Capability, ConstraintDescription, Cycle, DotGraph, ExecutionBudget, LoadedDependency,
+    LoadedFeature, LoadedMod, LoadedSchedule, LoadedSchedules, LoadedSystem, LoadingError,
+    MemoryGrowth, MemoryLimits, ModAccess, ModAsset, ModAssetError, ModAssetLoader, ModCycle,
+    ModExecutor, ModHandle, ModHealth, ModLoadFailed, ModLoaded, ModLoading, ModQuarantined,
+    ModReloaded, ModResources, ModSchedule, ModScheduleApp, ModSchedules, ModStatus, ModSystem,
+    ModUnloaded, Mods, PermissionPolicy, QuarantinePolicy, RunModSystems, SchedulingError,
+    SetDescription, SystemError, SystemFailure, Trust, TrustPolicy,
+};
 
+/// Adds the modloader to an app
+///
+/// Requires the `AssetPlugin`, since mods can be loaded as assets.
 pub struct ModloaderPlugin;
 
 impl Plugin for ModloaderPlugin {
@@ -13,5 +26,8 @@ impl Plugin for ModloaderPlugin {
 }
 
 pub mod prelude {
-    pub use crate::ModloaderPlugin;
+    pub use crate::{
+        ModLoadFailed, ModLoaded, ModLoading, ModQuarantined, ModReloaded, ModScheduleApp,
+        ModUnloaded, ModloaderPlugin, Mods,
+    };
 }
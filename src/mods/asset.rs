@@ -0,0 +1,198 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+use bevy_asset::{
+    io::Reader, Asset, AssetEvent, AssetLoadFailedEvent, AssetLoader, Assets, Handle, LoadContext,
+};
+use bevy_ecs::{
+    event::{EventReader, EventWriter},
+    system::{Res, ResMut},
+};
+use bevy_reflect::TypePath;
+use bevy_utils::tracing::{error, info};
+
+use super::{LoadedMod, LoadingError, ModHandle, ModLoadFailed, ModStatus, Mods, Slot};
+
+/// A mod package loaded through the [`bevy_asset::AssetServer`], see [`Mods::load_asset`]
+///
+/// Works with any asset source, so mods can be embedded in the game with `embedded_asset!`.
+#[derive(Asset, TypePath, Debug)]
+pub struct ModAsset {
+    package: common::ModPackage,
+}
+
+impl ModAsset {
+    pub fn new(package: common::ModPackage) -> Self {
+        Self { package }
+    }
+
+    pub fn package(&self) -> &common::ModPackage {
+        &self.package
+    }
+}
+
+/// Loads ".hmod" packages as [`ModAsset`]s
+#[derive(Default)]
+pub struct ModAssetLoader;
+
+impl AssetLoader for ModAssetLoader {
+    type Asset = ModAsset;
+    type Settings = ();
+    type Error = ModAssetError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let package = common::ModPackage::decode(&bytes)?;
+
+        // Fail early, the rest is validated when the mod loads
+        common::ModManifest::decode(&package.manifest)?;
+
+        Ok(ModAsset { package })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[common::PACKAGE_EXTENSION]
+    }
+}
+
+#[derive(Debug)]
+pub enum ModAssetError {
+    Io(io::Error),
+    InvalidPackage(common::PackageError),
+    InvalidManifest(common::ManifestError),
+}
+
+impl From<io::Error> for ModAssetError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<common::PackageError> for ModAssetError {
+    fn from(err: common::PackageError) -> Self {
+        Self::InvalidPackage(err)
+    }
+}
+
+impl From<common::ManifestError> for ModAssetError {
+    fn from(err: common::ManifestError) -> Self {
+        Self::InvalidManifest(err)
+    }
+}
+
+impl fmt::Display for ModAssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Could not read mod asset: {}", err),
+            Self::InvalidPackage(err) => write!(f, "Invalid mod package: {}", err),
+            Self::InvalidManifest(err) => write!(f, "Invalid mod manifest: {}", err),
+        }
+    }
+}
+
+impl Error for ModAssetError {}
+
+impl Mods {
+    /// Loads a mod from a [`ModAsset`] once the asset server finished loading it
+    ///
+    /// The mod is reloaded whenever the asset is modified, like mods loaded with [`Mods::load_from_path`].
+    pub fn load_asset(&mut self, asset: Handle<ModAsset>) -> ModHandle {
+        let handle = self.reserve_slot();
+        self.started_loading.push((handle, asset_path(&asset)));
+        self.pending_assets.push((handle, asset));
+        handle
+    }
+
+    fn load_package(&mut self, handle: ModHandle, asset: &ModAsset, path: PathBuf) {
+        let package = asset.package.clone();
+        let limits = self.memory_limits;
+        let trust_policy = self.trust_policy.clone();
+        let schedule_labels = self.schedule_labels.clone();
+        self.spawn_loading(handle, async move {
+            let mut loaded =
+                LoadedMod::try_from_package(package, &limits, &trust_policy, &schedule_labels)
+                    .await?;
+            loaded.path = Some(path);
+            Ok(loaded)
+        });
+    }
+}
+
+/// Identifies mods loaded from an asset, so a modified asset reloads the mod loaded from it
+fn asset_path(asset: &Handle<ModAsset>) -> PathBuf {
+    match asset.path() {
+        Some(path) => PathBuf::from(path.to_string()),
+        // Added directly to `Assets<ModAsset>`
+        None => PathBuf::from(format!("{:?}", asset.id())),
+    }
+}
+
+/// Starts loading mods from their assets once they are available, and reloads them when they are modified
+pub(super) fn handle_mod_assets(
+    mut mods: ResMut<Mods>,
+    assets: Res<Assets<ModAsset>>,
+    mut asset_events: EventReader<AssetEvent<ModAsset>>,
+    mut asset_failed_events: EventReader<AssetLoadFailedEvent<ModAsset>>,
+    mut failed_events: EventWriter<ModLoadFailed>,
+) {
+    for event in asset_failed_events.read() {
+        let failed: Vec<_> = mods
+            .pending_assets
+            .iter()
+            .filter(|(_, asset)| asset.id() == event.id)
+            .map(|(handle, _)| *handle)
+            .collect();
+        mods.pending_assets
+            .retain(|(_, asset)| asset.id() != event.id);
+
+        for handle in failed {
+            error!("Failed to load mod asset {}: {}", event.path, event.error);
+            mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Failed);
+            failed_events.send(ModLoadFailed {
+                handle,
+                error: LoadingError::AssetLoadFailed(event.error.to_string()),
+            });
+        }
+    }
+
+    for (handle, asset) in std::mem::take(&mut mods.pending_assets) {
+        match assets.get(&asset) {
+            Some(loaded) => {
+                mods.load_package(handle, loaded, asset_path(&asset));
+                mods.watched_assets.push((handle, asset));
+            }
+            None => mods.pending_assets.push((handle, asset)),
+        }
+    }
+
+    for event in asset_events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+
+        let modified: Vec<_> = mods
+            .watched_assets
+            .iter()
+            .filter(|(_, asset)| asset.id() == *id)
+            .map(|(_, asset)| asset.clone())
+            .collect();
+        for asset in modified {
+            let Some(loaded) = assets.get(&asset) else {
+                continue;
+            };
+
+            // Loads into a new slot, then replaces the mod loaded from the same asset
+            let path = asset_path(&asset);
+            info!("Mod asset modified: {:?}", path);
+            let handle = mods.reserve_slot();
+            mods.started_loading.push((handle, path.clone()));
+            mods.load_package(handle, loaded, path);
+        }
+    }
+}
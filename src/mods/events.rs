@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use bevy_ecs_macros::Event;
+
+use super::{LoadingError, ModHandle};
+
+/// Sent when a mod starts loading
+#[derive(Event, Debug)]
+pub struct ModLoading {
+    pub handle: ModHandle,
+    pub path: PathBuf,
+}
+
+/// Sent when a mod finished loading, before any of its systems run
+#[derive(Event, Debug)]
+pub struct ModLoaded {
+    pub handle: ModHandle,
+    pub name: String,
+}
+
+/// Sent when a mod could not be loaded, its handle is not used anymore
+#[derive(Event, Debug)]
+pub struct ModLoadFailed {
+    pub handle: ModHandle,
+    pub error: LoadingError,
+}
+
+/// Sent when a new build of a loaded mod replaced it
+///
+/// The mod keeps its handle, while the handle returned when loading the new build is not used anymore.
+#[derive(Event, Debug)]
+pub struct ModReloaded {
+    pub handle: ModHandle,
+    pub name: String,
+}
+
+/// Sent when a mod was unloaded, once the entities it spawned are despawned
+#[derive(Event, Debug)]
+pub struct ModUnloaded {
+    pub handle: ModHandle,
+    pub name: String,
+}
+
+/// Sent when a mod failed too often and its systems stopped running, see [`super::ModHealth`]
+#[derive(Event, Debug)]
+pub struct ModQuarantined {
+    pub handle: ModHandle,
+    pub name: String,
+}
@@ -0,0 +1,975 @@
+use bevy_app::App;
+use bevy_ecs::{
+    schedule::{IntoSystemConfigs, ScheduleLabel},
+    world::{Mut, World},
+};
+use bevy_ecs_macros::{Resource, SystemSet};
+use bevy_reflect::Typed;
+use bevy_tasks::ComputeTaskPool;
+use bevy_utils::{tracing::warn, HashMap, HashSet};
+use common::{OwnedParam, OwnedStableId, SystemId};
+
+use super::{
+    loaded::{LaneResult, SystemLane},
+    schedule::{ModSchedule, ModSystem},
+    ExecutionBudget, LoadedMod, LoadedSystem, ModHandle, ModHealth, ModQuarantined, Mods,
+    PermissionPolicy, QuarantinePolicy,
+};
+
+/// How the systems of mods are run each frame
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ModExecutor {
+    /// Systems whose access does not conflict run at the same time on the [`ComputeTaskPool`], even when they
+    /// belong to the same mod
+    ///
+    /// A wasm instance can't be entered by two threads at once, so the systems of a mod running at the same time
+    /// each run on their own instance of the mod, up to one per thread of the pool. The instances share the
+    /// resources, entities and budget of the mod, but not their linear memory: state a mod keeps in statics is
+    /// not seen by its systems running on other instances.
+    #[default]
+    Parallel,
+    /// Systems run one after the other, in the order of the schedule merged across every mod
+    SingleThreaded,
+}
+
+/// The resources and components the systems of a mod read and write, see [`LoadedMod::access`]
+#[derive(Debug, Default, Clone)]
+pub struct ModAccess {
+    reads: HashSet<OwnedStableId>,
+    writes: HashSet<OwnedStableId>,
+    component_reads: HashSet<OwnedStableId>,
+    component_writes: HashSet<OwnedStableId>,
+}
+
+impl ModAccess {
+    /// Commands are applied once the mods are done running, so they never conflict
+    pub fn add_param(&mut self, param: &OwnedParam) {
+        match param {
+            OwnedParam::Command => {}
+            OwnedParam::Res { mutable: false, id } => {
+                self.reads.insert(id.clone());
+            }
+            OwnedParam::Res { mutable: true, id } => {
+                self.writes.insert(id.clone());
+            }
+            // Filters are ignored, so queries over disjoint entities still conflict
+            OwnedParam::Query(query) => {
+                for component in query.components.iter() {
+                    match component.mutable {
+                        false => self.component_reads.insert(component.id.clone()),
+                        true => self.component_writes.insert(component.id.clone()),
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn extend(&mut self, other: &ModAccess) {
+        self.reads.extend(other.reads.iter().cloned());
+        self.writes.extend(other.writes.iter().cloned());
+        self.component_reads
+            .extend(other.component_reads.iter().cloned());
+        self.component_writes
+            .extend(other.component_writes.iter().cloned());
+    }
+
+    pub fn reads(&self) -> impl Iterator<Item = &OwnedStableId> {
+        self.reads.iter()
+    }
+
+    pub fn writes(&self) -> impl Iterator<Item = &OwnedStableId> {
+        self.writes.iter()
+    }
+
+    /// The components queried without being written back
+    pub fn component_reads(&self) -> impl Iterator<Item = &OwnedStableId> {
+        self.component_reads.iter()
+    }
+
+    pub fn component_writes(&self) -> impl Iterator<Item = &OwnedStableId> {
+        self.component_writes.iter()
+    }
+
+    /// Whether two mods with these accesses can run at the same time
+    pub fn is_compatible(&self, other: &ModAccess) -> bool {
+        self.writes.is_disjoint(&other.writes)
+            && self.writes.is_disjoint(&other.reads)
+            && self.reads.is_disjoint(&other.writes)
+            && self.component_writes.is_disjoint(&other.component_writes)
+            && self.component_writes.is_disjoint(&other.component_reads)
+            && self.component_reads.is_disjoint(&other.component_writes)
+    }
+}
+
+/// Systems grouped by mod and lane, every lane of a batch runs at the same time while the systems of each lane
+/// run in order
+type Batch = Vec<(ModHandle, Vec<Vec<SystemId>>)>;
+
+/// Groups the systems of a schedule into batches, following the schedule merged across every mod
+///
+/// Systems of quarantined mods are left out. See [`plan_batches`].
+fn batches(
+    mods: &Mods,
+    schedule_id: &OwnedStableId,
+    executor: ModExecutor,
+    max_lanes: usize,
+    include: impl Fn(&LoadedMod) -> bool,
+) -> Vec<Batch> {
+    let Some(schedule) = mods.schedules.get(schedule_id) else {
+        return Vec::new();
+    };
+
+    let handles: HashMap<&str, ModHandle> = mods
+        .iter_ordered()
+        .map(|(handle, loaded)| (loaded.name(), handle))
+        .collect();
+
+    plan_batches(schedule, executor, max_lanes, |node| {
+        let loaded = mods.get(node.handle)?;
+        if loaded.health().is_quarantined() || !include(loaded) {
+            return None;
+        }
+        let access = loaded
+            .system(schedule_id, node.system)
+            .map(LoadedSystem::access)
+            .unwrap_or_default();
+        let dependencies = loaded
+            .dependencies()
+            .iter()
+            .filter_map(|dependency| handles.get(dependency.name.as_str()).copied())
+            .collect();
+        Some((access, dependencies))
+    })
+}
+
+/// Groups the systems of a schedule into batches of lanes
+///
+/// `system` returns the access of a system and the mods its mod depends on, or `None` if it should not run.
+///
+/// A system runs in a later batch than the systems of other mods it depends on, than the systems of the mods its
+/// mod depends on, and than the systems of other mods sorted before it whose access conflicts with its own.
+/// Systems gated by run conditions always run in a later batch than their conditions, so they can be skipped.
+///
+/// Within a batch, the systems of a mod are split into at most `max_lanes` lanes, each running on its own
+/// instance of the mod. A system joins the lane of the systems of its mod it depends on or conflicts with, and
+/// waits for a later batch if those are in different lanes.
+fn plan_batches(
+    schedule: &ModSchedule,
+    executor: ModExecutor,
+    max_lanes: usize,
+    system: impl Fn(ModSystem) -> Option<(ModAccess, Vec<ModHandle>)>,
+) -> Vec<Batch> {
+    /// The lanes of a mod in a batch, with the access of the systems of each lane
+    type PlannedLanes = Vec<(Vec<SystemId>, ModAccess)>;
+
+    let mut batches: Vec<Vec<(ModHandle, PlannedLanes)>> = Vec::new();
+    let mut lane_of: HashMap<ModSystem, (usize, usize)> = HashMap::new();
+    let mut last_batch_of: HashMap<ModHandle, usize> = HashMap::new();
+
+    for (node, (access, mod_dependencies)) in sorted_by_mod_dependencies(schedule, system) {
+        let after_dependencies = schedule
+            .dependencies(node)
+            .filter_map(|dependency| {
+                let (index, _) = lane_of.get(&dependency)?;
+                Some(index + usize::from(dependency.handle != node.handle))
+            })
+            .max();
+        let after_mod_dependencies = mod_dependencies
+            .iter()
+            .filter_map(|dependency| last_batch_of.get(dependency))
+            .map(|index| index + 1)
+            .max();
+        let after_conditions = schedule
+            .conditions(node)
+            .iter()
+            .filter_map(|condition| lane_of.get(condition))
+            .map(|(index, _)| index + 1)
+            .max();
+        // Systems of a mod are never moved before the systems sorted before them
+        let after_own_systems = last_batch_of.get(&node.handle).copied();
+        let after_conflicts = batches
+            .iter()
+            .rposition(|batch| {
+                batch.iter().any(|(handle, lanes)| {
+                    *handle != node.handle
+                        && lanes
+                            .iter()
+                            .any(|(_, lane_access)| !lane_access.is_compatible(&access))
+                })
+            })
+            .map(|index| index + 1);
+
+        let (mut index, max_lanes) = match executor {
+            ModExecutor::Parallel => (
+                [
+                    after_dependencies,
+                    after_conditions,
+                    after_mod_dependencies,
+                    after_own_systems,
+                    after_conflicts,
+                ]
+                .into_iter()
+                .flatten()
+                .max()
+                .unwrap_or(0),
+                max_lanes.max(1),
+            ),
+            ModExecutor::SingleThreaded => (batches.len(), 1),
+        };
+
+        let lane = loop {
+            if index == batches.len() {
+                batches.push(Vec::new());
+            }
+            let lanes = match batches[index]
+                .iter()
+                .position(|(handle, _)| *handle == node.handle)
+            {
+                Some(position) => &batches[index][position].1,
+                None => break 0,
+            };
+
+            // The lanes holding systems of the mod this system depends on or conflicts with
+            let mut joined: Vec<usize> = schedule
+                .dependencies(node)
+                .filter(|dependency| dependency.handle == node.handle)
+                .filter_map(|dependency| lane_of.get(&dependency))
+                .filter(|(batch, _)| *batch == index)
+                .map(|(_, lane)| *lane)
+                .chain(
+                    lanes
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, (_, lane_access))| !lane_access.is_compatible(&access))
+                        .map(|(lane, _)| lane),
+                )
+                .collect();
+            joined.sort_unstable();
+            joined.dedup();
+
+            match joined.as_slice() {
+                [] if lanes.len() < max_lanes => break lanes.len(),
+                [] => {
+                    break (0..lanes.len())
+                        .min_by_key(|&lane| lanes[lane].0.len())
+                        .unwrap_or(0)
+                }
+                [lane] => break *lane,
+                _ => index += 1,
+            }
+        };
+
+        let batch = &mut batches[index];
+        let lanes = match batch
+            .iter_mut()
+            .position(|(handle, _)| *handle == node.handle)
+        {
+            Some(position) => &mut batch[position].1,
+            None => {
+                batch.push((node.handle, Vec::new()));
+                &mut batch.last_mut().unwrap().1
+            }
+        };
+        if lane == lanes.len() {
+            lanes.push((Vec::new(), ModAccess::default()));
+        }
+        lanes[lane].0.push(node.system);
+        lanes[lane].1.extend(&access);
+        lane_of.insert(node, (index, lane));
+        last_batch_of.insert(node.handle, index);
+    }
+
+    batches
+        .into_iter()
+        .map(|batch| {
+            batch
+                .into_iter()
+                .map(|(handle, lanes)| {
+                    let lanes = lanes.into_iter().map(|(systems, _)| systems).collect();
+                    (handle, lanes)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The systems of a schedule that should run, sorted such that they come after their dependencies and, unless
+/// constraints say otherwise, after the systems of the mods their mod depends on
+///
+/// The merged schedule only orders systems by their constraints, so systems of a mod may be sorted before the
+/// systems of a mod it depends on.
+fn sorted_by_mod_dependencies(
+    schedule: &ModSchedule,
+    system: impl Fn(ModSystem) -> Option<(ModAccess, Vec<ModHandle>)>,
+) -> Vec<(ModSystem, (ModAccess, Vec<ModHandle>))> {
+    let mut pending: Vec<_> = schedule
+        .order()
+        .iter()
+        .filter_map(|&node| Some((node, system(node)?)))
+        .collect();
+    let mut remaining: HashMap<ModHandle, usize> = HashMap::new();
+    for (node, _) in pending.iter() {
+        *remaining.entry(node.handle).or_default() += 1;
+    }
+
+    let mut sorted = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let ready = |node: ModSystem| {
+            schedule
+                .dependencies(node)
+                .all(|dependency| pending.iter().all(|(other, _)| *other != dependency))
+        };
+        let unblocked = |mod_dependencies: &[ModHandle]| {
+            mod_dependencies
+                .iter()
+                .all(|dependency| remaining.get(dependency).copied().unwrap_or(0) == 0)
+        };
+        let position = pending
+            .iter()
+            .position(|(node, (_, mod_dependencies))| ready(*node) && unblocked(mod_dependencies))
+            // Constraints ordering a mod before the mods it depends on win
+            .or_else(|| pending.iter().position(|(node, _)| ready(*node)))
+            .expect("the merged schedule has no cycles");
+        let (node, info) = pending.remove(position);
+        *remaining.get_mut(&node.handle).unwrap() -= 1;
+        sorted.push((node, info));
+    }
+    sorted
+}
+
+/// Runs the systems of a schedule for the included mods, see [`ModExecutor`]
+///
+/// The commands of each batch are applied once the whole batch is done, in the order the mods were activated.
+/// Systems are skipped unless every run condition gating them ran and returned true.
+fn run_schedule(
+    world: &mut World,
+    mods: &mut Mods,
+    schedule_id: &OwnedStableId,
+    executor: ModExecutor,
+    budget: &ExecutionBudget,
+    policy: &QuarantinePolicy,
+    include: impl Fn(&LoadedMod) -> bool,
+) {
+    let max_lanes = ComputeTaskPool::try_get().map_or(1, |pool| pool.thread_num());
+    let mut conditions: HashMap<ModSystem, bool> = HashMap::new();
+    for batch in batches(mods, schedule_id, executor, max_lanes, include) {
+        let batch: Batch = match mods.schedules.get(schedule_id) {
+            Some(schedule) => batch
+                .into_iter()
+                .map(|(handle, lanes)| {
+                    let lanes: Vec<Vec<SystemId>> = lanes
+                        .into_iter()
+                        .map(|systems| {
+                            systems
+                                .into_iter()
+                                .filter(|&system| {
+                                    schedule
+                                        .conditions(ModSystem { handle, system })
+                                        .iter()
+                                        .all(|condition| conditions.get(condition) == Some(&true))
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                        .filter(|systems| !systems.is_empty())
+                        .collect();
+                    (handle, lanes)
+                })
+                .filter(|(_, lanes)| !lanes.is_empty())
+                .collect(),
+            None => batch,
+        };
+
+        // Mods quarantined by an earlier batch were already reported
+        let quarantined: Vec<ModHandle> = batch
+            .iter()
+            .map(|(handle, _)| *handle)
+            .filter(|handle| mods.health(*handle).is_some_and(ModHealth::is_quarantined))
+            .collect();
+
+        let mut running: Vec<(ModHandle, &mut LoadedMod, Vec<Vec<SystemId>>)> = mods
+            .loaded
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let (handle, lanes) = batch.iter().find(|(handle, _)| handle.0 == index)?;
+                Some((*handle, slot.get_mut()?, lanes.clone()))
+            })
+            .collect();
+
+        // Mods can't access the world while they run, so what their queries and resources read is fetched up front
+        for (_, loaded, lanes) in running.iter_mut() {
+            *lanes = loaded.prepare_systems(world, schedule_id, std::mem::take(lanes));
+        }
+
+        let mut lanes: Vec<(ModHandle, SystemLane)> = running
+            .iter_mut()
+            .flat_map(|(handle, loaded, lanes)| {
+                let handle = *handle;
+                loaded
+                    .lanes(schedule_id, lanes, budget, policy)
+                    .into_iter()
+                    .map(move |lane| (handle, lane))
+            })
+            .collect();
+        let results: Vec<(ModHandle, LaneResult)> = match lanes.len() {
+            0 | 1 => lanes
+                .drain(..)
+                .map(|(handle, lane)| (handle, lane.run()))
+                .collect(),
+            _ => ComputeTaskPool::get().scope(|scope| {
+                for (handle, lane) in lanes.drain(..) {
+                    scope.spawn(async move { (handle, lane.run()) });
+                }
+            }),
+        };
+        drop(lanes);
+
+        // The results of the lanes of each mod are recorded in the order of its lanes
+        let mut results_of: HashMap<ModHandle, Vec<LaneResult>> = HashMap::new();
+        for (handle, result) in results {
+            results_of.entry(handle).or_default().push(result);
+        }
+        for (handle, loaded, _) in running.iter_mut() {
+            let results = results_of.remove(handle).unwrap_or_default();
+            for (system, result) in loaded.finish_lanes(results, policy) {
+                conditions.insert(
+                    ModSystem {
+                        handle: *handle,
+                        system,
+                    },
+                    result,
+                );
+            }
+        }
+        drop(running);
+
+        let ran: Vec<_> = mods
+            .iter_ordered()
+            .map(|(handle, _)| handle)
+            .filter(|handle| batch.iter().any(|(batched, _)| batched == handle))
+            .collect();
+        for handle in ran {
+            let Some(loaded) = mods.loaded[handle.0].get_mut() else {
+                continue;
+            };
+            loaded.apply_commands(world);
+
+            if loaded.health().is_quarantined() && !quarantined.contains(&handle) {
+                world.send_event(ModQuarantined {
+                    handle,
+                    name: loaded.name().to_owned(),
+                });
+            }
+        }
+    }
+}
+
+/// Resets the budget and permissions of every mod at the start of a frame
+pub(super) fn start_mod_frame(world: &mut World) {
+    let budget = *world.resource::<ExecutionBudget>();
+    let permissions = world.resource::<PermissionPolicy>().clone();
+
+    let mut mods = world.resource_mut::<Mods>();
+    for slot in mods.loaded.iter_mut() {
+        let Some(loaded) = slot.get_mut() else {
+            continue;
+        };
+        if !loaded.health().is_quarantined() {
+            loaded.start_frame(&budget, &permissions);
+        }
+    }
+}
+
+/// Runs the [`common::Start`] systems of newly loaded mods, which then take part in every other schedule
+pub(super) fn run_start_schedule(world: &mut World) {
+    let start = OwnedStableId::from_typed::<common::Start>();
+
+    let executor = *world.resource::<ModExecutor>();
+    let budget = *world.resource::<ExecutionBudget>();
+    let policy = *world.resource::<QuarantinePolicy>();
+    let permissions = world.resource::<PermissionPolicy>().clone();
+
+    world.resource_scope(|world, mut mods: Mut<Mods>| {
+        // Mods activated since the start of the frame did not get a budget yet
+        let mut starting = Vec::new();
+        for (index, slot) in mods.loaded.iter_mut().enumerate() {
+            let Some(loaded) = slot.get_mut() else {
+                continue;
+            };
+            if !loaded.started && !loaded.health().is_quarantined() {
+                loaded.start_frame(&budget, &permissions);
+                starting.push(ModHandle(index));
+            }
+        }
+
+        run_schedule(
+            world,
+            &mut mods,
+            &start,
+            executor,
+            &budget,
+            &policy,
+            |loaded| !loaded.started,
+        );
+        for handle in starting {
+            if let Some(loaded) = mods.loaded[handle.0].get_mut() {
+                loaded.started = true;
+            }
+        }
+    });
+}
+
+/// Runs the systems mods added to a schedule, for every mod that already started
+fn run_mod_schedule(world: &mut World, schedule_id: &OwnedStableId) {
+    let executor = *world.resource::<ModExecutor>();
+    let budget = *world.resource::<ExecutionBudget>();
+    let policy = *world.resource::<QuarantinePolicy>();
+
+    world.resource_scope(|world, mut mods: Mut<Mods>| {
+        run_schedule(
+            world,
+            &mut mods,
+            schedule_id,
+            executor,
+            &budget,
+            &policy,
+            |loaded| loaded.started,
+        );
+    });
+}
+
+/// The systems that run the mod schedules, use it to order host systems relative to mod systems
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RunModSystems;
+
+/// Lets the host run mod systems in schedules of its own
+pub trait ModScheduleApp {
+    /// Runs the systems mods add to the schedule labelled `T` in the given Bevy schedule
+    ///
+    /// Mods may only use the labels registered before they are loaded. `T` is matched by its type path, so the
+    /// host and the mods can share it through a common crate.
+    fn add_mod_schedule<T: Typed>(&mut self, schedule: impl ScheduleLabel) -> &mut Self;
+}
+
+impl ModScheduleApp for App {
+    fn add_mod_schedule<T: Typed>(&mut self, schedule: impl ScheduleLabel) -> &mut Self {
+        let id = OwnedStableId::from_typed::<T>();
+        let mut mods = self.world_mut().get_resource_or_insert_with(Mods::default);
+        if !mods.schedule_labels.insert(id.clone()) {
+            warn!("Mod schedule {:?} is already registered", id);
+            return self;
+        }
+
+        let run = move |world: &mut World| run_mod_schedule(world, &id);
+        self.add_systems(schedule, run.in_set(RunModSystems))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        Constraint, FeatureDescriptor, FileHash, ModManifest, Schedule, ScheduleDescriptor,
+        StableId, System, SystemSet,
+    };
+
+    use bevy_ecs::{event::Events, reflect::AppTypeRegistry};
+
+    use super::{
+        super::{MemoryGrowth, MemoryLimits, ModSchedules, Slot, SystemError},
+        *,
+    };
+
+    const UPDATE: StableId = StableId {
+        crate_name: "bevy_harmonize_api",
+        name: "Update",
+    };
+
+    fn manifest<'a>(
+        name: &'a str,
+        systems: &[u64],
+        constraints: Vec<Constraint<'a>>,
+    ) -> ModManifest<'a> {
+        ModManifest {
+            wasm_hash: FileHash::empty(),
+            api_version: "0.0.0",
+            version: "0.1.0",
+            dependencies: Vec::new(),
+            types: Vec::new(),
+            features: vec![FeatureDescriptor {
+                name,
+                resources: Vec::new(),
+                schedules: vec![ScheduleDescriptor {
+                    id: UPDATE,
+                    schedule: Schedule {
+                        systems: systems
+                            .iter()
+                            .map(|&id| System {
+                                id: SystemId::from_raw(id),
+                                name: "system",
+                                params: Vec::new(),
+                            })
+                            .collect(),
+                        constraints,
+                    },
+                }],
+            }],
+        }
+    }
+
+    fn access(reads: &[&str], writes: &[&str]) -> ModAccess {
+        let mut access = ModAccess::default();
+        for (names, mutable) in [(reads, false), (writes, true)] {
+            for name in names {
+                access.add_param(&OwnedParam::Res {
+                    mutable,
+                    id: OwnedStableId {
+                        crate_name: "test".into(),
+                        name: name.to_string(),
+                    },
+                });
+            }
+        }
+        access
+    }
+
+    fn system(handle: usize, id: u64) -> ModSystem {
+        ModSystem {
+            handle: ModHandle(handle),
+            system: SystemId::from_raw(id),
+        }
+    }
+
+    /// Plans the batches of the given mods, whose handles are their index
+    fn plan(
+        manifests: Vec<ModManifest>,
+        accesses: &[(ModSystem, ModAccess)],
+        mod_dependencies: &[(usize, usize)],
+        executor: ModExecutor,
+        max_lanes: usize,
+    ) -> Vec<Vec<(usize, Vec<Vec<u64>>)>> {
+        let mods: Vec<_> = manifests
+            .into_iter()
+            .enumerate()
+            .map(|(index, manifest)| (ModHandle(index), manifest.features[0].name, manifest))
+            .collect();
+        let schedules = ModSchedules::from_manifests(&mods).unwrap();
+        let schedule = schedules.get(&UPDATE.to_owned()).unwrap();
+
+        let batches = plan_batches(schedule, executor, max_lanes, |node| {
+            let access = accesses
+                .iter()
+                .find(|(system, _)| *system == node)
+                .map(|(_, access)| access.clone())
+                .unwrap_or_default();
+            let dependencies = mod_dependencies
+                .iter()
+                .filter(|(dependent, _)| *dependent == node.handle.0)
+                .map(|(_, dependency)| ModHandle(*dependency))
+                .collect();
+            Some((access, dependencies))
+        });
+        batches
+            .into_iter()
+            .map(|batch| {
+                let mut batch: Vec<_> = batch
+                    .into_iter()
+                    .map(|(handle, lanes)| {
+                        // Systems without constraints are in no particular order
+                        let mut lanes: Vec<Vec<u64>> = lanes
+                            .into_iter()
+                            .map(|systems| {
+                                let mut systems: Vec<_> =
+                                    systems.iter().map(SystemId::get_raw).collect();
+                                systems.sort();
+                                systems
+                            })
+                            .collect();
+                        lanes.sort();
+                        (handle.0, lanes)
+                    })
+                    .collect();
+                batch.sort();
+                batch
+            })
+            .collect()
+    }
+
+    #[test]
+    fn conflicting_mods_run_in_separate_batches() {
+        let manifests = vec![
+            manifest("a", &[1], Vec::new()),
+            manifest("b", &[2], Vec::new()),
+            manifest("c", &[3], Vec::new()),
+        ];
+        let accesses = [
+            (system(0, 1), access(&[], &["Score"])),
+            (system(1, 2), access(&["Score"], &[])),
+            (system(2, 3), access(&["Time"], &[])),
+        ];
+        let batches = plan(manifests, &accesses, &[], ModExecutor::Parallel, 4);
+
+        // Whichever of the conflicting mods is sorted first runs alongside the compatible one
+        let [first, second] = batches.as_slice() else {
+            panic!("expected two batches, got {:?}", batches);
+        };
+        assert_eq!(first.len(), 2);
+        assert!(first.contains(&(2, vec![vec![3]])));
+        assert_eq!(second.len(), 1);
+        let mut conflicting: Vec<_> = [&first[0], &second[0]]
+            .into_iter()
+            .filter(|(handle, _)| *handle != 2)
+            .collect();
+        conflicting.sort();
+        assert_eq!(conflicting, [&(0, vec![vec![1]]), &(1, vec![vec![2]])]);
+    }
+
+    #[test]
+    fn compatible_systems_of_a_mod_run_in_separate_lanes() {
+        let accesses = [
+            (system(0, 1), access(&["Time"], &["Score"])),
+            (system(0, 2), access(&["Time"], &["Health"])),
+            (system(0, 3), access(&["Score"], &[])),
+        ];
+        let manifests = vec![manifest("a", &[1, 2, 3], Vec::new())];
+        let batches = plan(manifests, &accesses, &[], ModExecutor::Parallel, 4);
+        assert_eq!(batches, vec![vec![(0, vec![vec![1, 3], vec![2]])]]);
+
+        // Without threads to spare, the systems share a single lane
+        let manifests = vec![manifest("a", &[1, 2, 3], Vec::new())];
+        let batches = plan(manifests, &accesses, &[], ModExecutor::Parallel, 1);
+        assert_eq!(batches, vec![vec![(0, vec![vec![1, 2, 3]])]]);
+
+        let manifests = vec![manifest("a", &[1, 2, 3], Vec::new())];
+        let batches = plan(manifests, &accesses, &[], ModExecutor::SingleThreaded, 4);
+        let mut systems: Vec<_> = batches
+            .iter()
+            .map(|batch| match batch.as_slice() {
+                [(0, lanes)] if lanes.len() == 1 && lanes[0].len() == 1 => lanes[0][0],
+                _ => panic!("expected a single system per batch, got {:?}", batch),
+            })
+            .collect();
+        systems.sort();
+        assert_eq!(systems, [1, 2, 3]);
+    }
+
+    #[test]
+    fn systems_depending_on_two_lanes_wait_for_the_next_batch() {
+        let manifests = vec![manifest(
+            "a",
+            &[1, 2, 3],
+            vec![Constraint::Order {
+                before: SystemSet::Anonymous(vec![SystemId::from_raw(1), SystemId::from_raw(2)]),
+                after: SystemSet::Anonymous(vec![SystemId::from_raw(3)]),
+            }],
+        )];
+        let accesses = [
+            (system(0, 1), access(&[], &["Score"])),
+            (system(0, 2), access(&[], &["Health"])),
+        ];
+        let batches = plan(manifests, &accesses, &[], ModExecutor::Parallel, 4);
+        assert_eq!(
+            batches,
+            vec![vec![(0, vec![vec![1], vec![2]])], vec![(0, vec![vec![3]])]]
+        );
+    }
+
+    #[test]
+    fn dependencies_between_mods_run_in_later_batches() {
+        let physics = StableId {
+            crate_name: "physics",
+            name: "PhysicsSet",
+        };
+        let manifests = vec![
+            manifest(
+                "physics",
+                &[1],
+                vec![Constraint::Includes {
+                    parent_name: physics,
+                    set: SystemSet::Anonymous(vec![SystemId::from_raw(1)]),
+                }],
+            ),
+            manifest(
+                "render",
+                &[2],
+                vec![Constraint::Order {
+                    before: SystemSet::Named(physics),
+                    after: SystemSet::Anonymous(vec![SystemId::from_raw(2)]),
+                }],
+            ),
+            manifest("audio", &[3], Vec::new()),
+        ];
+        // Audio depends on the physics mod, without ordering its systems
+        let batches = plan(manifests, &[], &[(2, 0)], ModExecutor::Parallel, 4);
+        assert_eq!(
+            batches,
+            vec![
+                vec![(0, vec![vec![1]])],
+                vec![(1, vec![vec![2]]), (2, vec![vec![3]])],
+            ]
+        );
+    }
+
+    #[test]
+    fn systems_gated_by_a_condition_run_in_a_later_batch() {
+        let manifests = vec![manifest(
+            "a",
+            &[1, 2, 3],
+            vec![Constraint::Condition {
+                set: SystemSet::Anonymous(vec![SystemId::from_raw(2)]),
+                condition: SystemId::from_raw(1),
+            }],
+        )];
+        let batches = plan(manifests, &[], &[], ModExecutor::Parallel, 4);
+        assert_eq!(
+            batches,
+            vec![vec![(0, vec![vec![1], vec![3]])], vec![(0, vec![vec![2]])]]
+        );
+    }
+
+    /// A system that never returns
+    const SPIN: &str = r#"
+        (module
+            (import "env" "memory" (memory 1))
+            (func (export "bevy_harmonize_run_system") (param i64)
+                (loop $spin (br $spin))))
+    "#;
+
+    /// A system that returns right away
+    const IDLE: &str = r#"
+        (module
+            (import "env" "memory" (memory 1))
+            (func (export "bevy_harmonize_run_system") (param i64)))
+    "#;
+
+    /// A system that grows the memory one page at a time until it can't, then traps
+    const GROW: &str = r#"
+        (module
+            (import "env" "memory" (memory 1))
+            (func (export "bevy_harmonize_run_system") (param i64)
+                (loop $grow
+                    (br_if $grow (i32.ne (memory.grow (i32.const 1)) (i32.const -1))))
+                unreachable))
+    "#;
+
+    /// A mod whose only system, with id 1, is added to [`common::Update`] and runs the given WebAssembly text
+    fn wat_mod(name: &str, system: &str, wat: &str, limits: &MemoryLimits) -> LoadedMod {
+        let mut manifest = manifest(name, &[1], Vec::new());
+        let descriptor = &mut manifest.features[0].schedules[0];
+        descriptor.id = StableId::from_typed::<common::Update>();
+        descriptor.schedule.systems[0].name = system;
+        LoadedMod::from_wat(manifest, wat, limits)
+    }
+
+    /// Activates the given mods, whose handles are their index
+    fn activate(loaded: Vec<LoadedMod>) -> Mods {
+        let mut mods = Mods::default();
+        for loaded in loaded {
+            let handle = mods.reserve_slot();
+            mods.loaded[handle.0] = Slot::Loaded(Box::new(loaded));
+            mods.order.push(handle);
+        }
+        mods.schedules = ModSchedules::try_build(mods.iter_ordered()).unwrap();
+        mods
+    }
+
+    /// Runs the [`common::Update`] systems of every mod, without starting a new frame
+    fn run_update(world: &mut World, mods: &mut Mods, budget: &ExecutionBudget) {
+        run_schedule(
+            world,
+            mods,
+            &OwnedStableId::from_typed::<common::Update>(),
+            ModExecutor::Parallel,
+            budget,
+            &QuarantinePolicy::default(),
+            |_| true,
+        );
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<AppTypeRegistry>();
+        world.init_resource::<Events<ModQuarantined>>();
+        world
+    }
+
+    #[test]
+    fn systems_running_out_of_fuel_are_stopped_and_recorded() {
+        let budget = ExecutionBudget {
+            per_system: 1_000,
+            per_frame: 1_500,
+        };
+        let limits = MemoryLimits::default();
+        let mut mods = activate(vec![
+            wat_mod("spinner", "spin", SPIN, &limits),
+            wat_mod("idler", "idle", IDLE, &limits),
+        ]);
+        let [spinner, idler] = [ModHandle(0), ModHandle(1)];
+        for slot in mods.loaded.iter_mut() {
+            slot.get_mut()
+                .unwrap()
+                .start_frame(&budget, &PermissionPolicy::default());
+        }
+        let mut world = world();
+
+        // The system is stopped once it used up the budget of a single run
+        run_update(&mut world, &mut mods, &budget);
+        let health = mods.health(spinner).unwrap();
+        let [failure] = health.failures() else {
+            panic!("expected a single failure, got {:?}", health.failures());
+        };
+        assert_eq!(failure.system, SystemId::from_raw(1));
+        assert_eq!(failure.system_name, "spin");
+        assert!(
+            matches!(failure.error, SystemError::OutOfFuel { budget: 1_000 }),
+            "{:?}",
+            failure.error
+        );
+        assert!(!health.is_quarantined());
+        assert!(mods.health(idler).unwrap().failures().is_empty());
+
+        // The next run only gets what is left of the frame, running out of it stops the mod until the next frame
+        // but is not held against it
+        run_update(&mut world, &mut mods, &budget);
+        run_update(&mut world, &mut mods, &budget);
+        assert_eq!(mods.health(spinner).unwrap().failures().len(), 1);
+        assert!(mods.health(idler).unwrap().failures().is_empty());
+    }
+
+    #[test]
+    fn systems_growing_memory_past_the_limit_run_out_of_memory() {
+        let budget = ExecutionBudget::default();
+        let limits = MemoryLimits {
+            max_pages: 2,
+            growth: MemoryGrowth::OnDemand,
+        };
+        let mut mods = activate(vec![wat_mod("leaker", "leak", GROW, &limits)]);
+        let leaker = ModHandle(0);
+        mods.loaded[leaker.0]
+            .get_mut()
+            .unwrap()
+            .start_frame(&budget, &PermissionPolicy::default());
+        let mut world = world();
+
+        // The memory grew to its limit during the run, so the trap that followed is blamed on it
+        run_update(&mut world, &mut mods, &budget);
+        let [failure] = mods.health(leaker).unwrap().failures() else {
+            panic!("expected a single failure");
+        };
+        assert_eq!(failure.system_name, "leak");
+        assert!(
+            matches!(failure.error, SystemError::OutOfMemory { max_pages: 2 }),
+            "{:?}",
+            failure.error
+        );
+
+        // Already at its limit, the memory does not grow anymore and the mod merely traps
+        run_update(&mut world, &mut mods, &budget);
+        let failures = mods.health(leaker).unwrap().failures();
+        assert_eq!(failures.len(), 2);
+        assert!(
+            matches!(failures[1].error, SystemError::Trapped(_)),
+            "{:?}",
+            failures[1].error
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use bevy_ecs_macros::Resource;
+
+use super::SystemError;
+
+/// Decides when a misbehaving mod is quarantined, see [`ModHealth`]
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct QuarantinePolicy {
+    /// How many times the systems of a mod may fail before the mod is quarantined
+    pub max_failures: usize,
+}
+
+impl Default for QuarantinePolicy {
+    fn default() -> Self {
+        Self { max_failures: 3 }
+    }
+}
+
+/// A run of a mod system that trapped or ran out of fuel
+#[derive(Debug)]
+pub struct SystemFailure {
+    pub system: common::SystemId,
+    pub system_name: String,
+    pub error: SystemError,
+}
+
+/// Keeps track of the failures of a mod's systems
+///
+/// Once a mod failed [`QuarantinePolicy::max_failures`] times it is quarantined: it stays loaded, but none of
+/// its systems run anymore. Reloading or unloading the mod is the only way out.
+#[derive(Debug, Default)]
+pub struct ModHealth {
+    failures: Vec<SystemFailure>,
+    quarantined: bool,
+}
+
+impl ModHealth {
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined
+    }
+
+    /// Every failure since the mod was loaded, oldest first
+    pub fn failures(&self) -> &[SystemFailure] {
+        &self.failures
+    }
+
+    pub fn last_failure(&self) -> Option<&SystemFailure> {
+        self.failures.last()
+    }
+
+    /// Records a failure, quarantining the mod if it failed too often
+    pub(crate) fn record(&mut self, failure: SystemFailure, policy: &QuarantinePolicy) {
+        self.failures.push(failure);
+        if self.failures.len() >= policy.max_failures {
+            self.quarantined = true;
+        }
+    }
+}
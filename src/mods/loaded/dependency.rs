@@ -0,0 +1,21 @@
+use super::LoadingError;
+
+/// Another mod that has to be active before a mod can start, see [`super::LoadedMod::dependencies`]
+#[derive(Debug, Clone)]
+pub struct LoadedDependency {
+    /// The name of the mod, as given to `Mod::new`
+    pub name: String,
+    pub version: semver::VersionReq,
+}
+
+impl LoadedDependency {
+    pub fn try_from_descriptor(descriptor: &common::Dependency) -> Result<Self, LoadingError> {
+        let version = semver::VersionReq::parse(descriptor.version)
+            .map_err(|err| LoadingError::InvalidVersion(err.to_string()))?;
+
+        Ok(Self {
+            name: descriptor.name.to_owned(),
+            version,
+        })
+    }
+}
@@ -1,4 +1,4 @@
-use bevy_utils::HashMap;
+use bevy_utils::{HashMap, HashSet};
 
 use super::{schedule::LoadedSchedules, LoadingError};
 
@@ -14,8 +14,10 @@ pub struct LoadedFeature {
 impl LoadedFeature {
     pub fn try_from_descriptor<'a>(
         descriptor: &common::FeatureDescriptor<'a>,
+        schedule_labels: &HashSet<common::OwnedStableId>,
     ) -> Result<Self, LoadingError> {
-        let schedules = LoadedSchedules::try_from_schedule_descriptors(&descriptor.schedules)?;
+        let schedules =
+            LoadedSchedules::try_from_schedule_descriptors(&descriptor.schedules, schedule_labels)?;
 
         Ok(Self {
             name: descriptor.name.to_owned(),
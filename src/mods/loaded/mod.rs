@@ -1,25 +1,77 @@
 use std::{
-    io,
+    fmt, io,
     path::{Path, PathBuf},
 };
 
-use bevy_utils::tracing::info;
+use bevy_ecs::world::World;
+use bevy_utils::{
+    tracing::{error, info, warn},
+    HashSet,
+};
 use sha2::{Digest, Sha256};
 
+mod dependency;
+pub use dependency::LoadedDependency;
+
 mod feature;
 pub use feature::LoadedFeature;
 
-use super::SchedulingError;
+use super::{
+    executor::ModAccess,
+    health::{ModHealth, QuarantinePolicy, SystemFailure},
+    migration::Migration,
+    permissions::{Capability, PermissionPolicy},
+    runtime::{
+        metered_store, ExecutionBudget, Lane, MemoryLimits, ModResources, ModRuntime, SystemError,
+        RUN_CONDITION_EXPORT,
+    },
+    SchedulingError,
+};
 
 pub mod schedule;
+pub use schedule::{DotGraph, LoadedSchedule, LoadedSchedules, LoadedSystem};
+
+mod trust;
+pub use trust::{Trust, TrustPolicy};
 
-// These fields are read by a debug macro
-#[allow(dead_code)]
-#[derive(Debug)]
 pub struct LoadedMod {
     pub(super) manifest_hash: common::FileHash,
+    /// Kept encoded, since the decoded manifest borrows from it
+    manifest: Vec<u8>,
+    /// Where the mod was loaded from, new builds found at the same path replace this one
+    pub(super) path: Option<PathBuf>,
     module: wasmer::Module,
+    trust: Trust,
+    /// Files bundled with the mod, by their path
+    assets: Vec<(String, Vec<u8>)>,
+    version: semver::Version,
+    dependencies: Vec<LoadedDependency>,
     features: Vec<LoadedFeature>,
+    runtime: ModRuntime,
+    /// Whether the systems of the [`common::Start`] schedule already ran
+    pub(super) started: bool,
+    health: ModHealth,
+}
+
+impl fmt::Debug for LoadedMod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadedMod")
+            .field("manifest_hash", &self.manifest_hash)
+            .field("path", &self.path)
+            .field("module", &self.module)
+            .field("trust", &self.trust)
+            .field("version", &self.version)
+            .field("dependencies", &self.dependencies)
+            .field(
+                "assets",
+                &self.assets.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .field("features", &self.features)
+            .field("runtime", &self.runtime)
+            .field("started", &self.started)
+            .field("health", &self.health)
+            .finish()
+    }
 }
 
 impl PartialEq for LoadedMod {
@@ -30,15 +82,45 @@ impl PartialEq for LoadedMod {
 
 impl LoadedMod {
     /// Load a mod from a path. The path can be either:
-    /// - a directory containing ".wasm" and ".manifest" files
+    /// - a ".hmod" package, see [`common::ModPackage`]
+    /// - a directory containing ".wasm" and ".manifest" files, the build tool only writes packages but these can be
+    ///   assembled by hand
     /// - any mod file as long as it has siblings with matching names
-    pub async fn try_from_path<P>(path: P) -> LoadedModResult
+    ///
+    /// A signature, if any, is verified against the trust policy. Its linear memory is created within the
+    /// given limits. Its systems may only be added to schedules with one of the given labels.
+    pub async fn try_from_path<P>(
+        path: P,
+        limits: MemoryLimits,
+        trust_policy: TrustPolicy,
+        schedule_labels: HashSet<common::OwnedStableId>,
+    ) -> LoadedModResult
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
         info!("Loading mod from path: {:?}", path);
 
+        let package = if path
+            .extension()
+            .is_some_and(|extension| extension == common::PACKAGE_EXTENSION)
+        {
+            let bytes = async_fs::read(path)
+                .await
+                .map_err(|err| LoadingError::FileNotFound(path.to_owned(), Some(err)))?;
+            common::ModPackage::decode(&bytes).map_err(LoadingError::InvalidPackage)?
+        } else {
+            Self::read_loose_files(path).await?
+        };
+
+        let mut loaded =
+            Self::try_from_package(package, &limits, &trust_policy, &schedule_labels).await?;
+        loaded.path = Some(path.to_owned());
+        Ok(loaded)
+    }
+
+    /// Gathers the files of a mod that is not packaged, it has no assets
+    async fn read_loose_files(path: &Path) -> Result<common::ModPackage, LoadingError> {
         // Either files are like this: "modname/.wasm" or "modname.wasm"
         let file_name = path
             .file_name()
@@ -57,43 +139,464 @@ impl LoadedMod {
         let package_name = file_name.split('.').next().unwrap().to_owned();
 
         let manifest_path = directory.join(format!("{}.manifest", package_name));
-        let manifest_bytes = async_fs::read(&manifest_path)
+        let manifest = async_fs::read(&manifest_path)
             .await
             .map_err(|err| LoadingError::FileNotFound(manifest_path, Some(err)))?;
 
         let wasm_path = directory.join(format!("{}.wasm", package_name));
-        let wasm_bytes = async_fs::read(&wasm_path)
+        let wasm = async_fs::read(&wasm_path)
             .await
             .map_err(|err| LoadingError::FileNotFound(wasm_path, Some(err)))?;
 
-        Self::try_from_bytes(manifest_bytes, wasm_bytes).await
+        let signature_path = directory.join(format!("{}.sig", package_name));
+        let signature = match async_fs::read(&signature_path).await {
+            Ok(bytes) => Some(bytes),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(LoadingError::FileNotFound(signature_path, Some(err))),
+        };
+
+        Ok(common::ModPackage {
+            manifest,
+            wasm,
+            signature,
+            assets: Vec::new(),
+        })
     }
 
-    async fn try_from_bytes(manifest_bytes: Vec<u8>, wasm_bytes: Vec<u8>) -> LoadedModResult {
-        let manifest: common::ModManifest =
-            bitcode::decode(&manifest_bytes).map_err(|_| LoadingError::InvalidManifest)?;
+    pub(super) async fn try_from_package(
+        package: common::ModPackage,
+        limits: &MemoryLimits,
+        trust_policy: &TrustPolicy,
+        schedule_labels: &HashSet<common::OwnedStableId>,
+    ) -> LoadedModResult {
+        let common::ModPackage {
+            manifest: manifest_bytes,
+            wasm: wasm_bytes,
+            signature: signature_bytes,
+            assets,
+        } = package;
+
+        let manifest =
+            common::ModManifest::decode(&manifest_bytes).map_err(LoadingError::InvalidManifest)?;
 
         let wasm_hash = common::FileHash::from_sha256(Sha256::digest(&wasm_bytes).into());
         if wasm_hash != manifest.wasm_hash {
             return Err(LoadingError::MissmatchingDependencies);
         }
 
+        // The manifest pins the hash of the wasm, so its signature covers the whole mod
+        let trust = trust_policy.verify(&manifest_bytes, signature_bytes.as_deref());
+        if !trust_policy.allows(trust) {
+            return Err(match trust {
+                Trust::Invalid => LoadingError::InvalidSignature,
+                Trust::Trusted | Trust::Untrusted => LoadingError::Untrusted,
+            });
+        }
+
         let manifest_hash = common::FileHash::from_sha256(Sha256::digest(&manifest_bytes).into());
 
-        let store = wasmer::Store::default();
+        let store = metered_store();
         let module = wasmer::Module::new(&store, wasm_bytes).map_err(LoadingError::InvalidWasm)?;
 
+        let version = semver::Version::parse(manifest.version)
+            .map_err(|err| LoadingError::InvalidVersion(err.to_string()))?;
+        let dependencies = manifest
+            .dependencies
+            .iter()
+            .map(LoadedDependency::try_from_descriptor)
+            .collect::<Result<_, _>>()?;
+
         let mut features = Vec::with_capacity(manifest.features.len());
         for feature in manifest.features.iter() {
-            features.push(LoadedFeature::try_from_descriptor(feature)?);
+            features.push(LoadedFeature::try_from_descriptor(
+                feature,
+                schedule_labels,
+            )?);
+        }
+
+        warn_ambiguities(&features);
+
+        let resources = ModResources::from_features(&features);
+        let runtime = ModRuntime::try_new(store, &module, resources, limits)?;
+
+        let has_conditions = features
+            .iter()
+            .flat_map(|feature| feature.schedules.iter())
+            .flat_map(|(_, schedule)| schedule.systems())
+            .any(|(_, system)| system.is_condition());
+        if has_conditions && !runtime.has_run_conditions() {
+            return Err(LoadingError::MissingExport(RUN_CONDITION_EXPORT));
         }
 
         Ok(Self {
             manifest_hash,
+            manifest: manifest_bytes,
+            path: None,
             module,
+            trust,
+            assets,
+            version,
+            dependencies,
             features,
+            runtime,
+            started: false,
+            health: ModHealth::default(),
         })
     }
+
+    /// The name of this mod, as given to `Mod::new` in its schema
+    pub fn name(&self) -> &str {
+        self.features
+            .first()
+            .map(|feature| feature.name.as_str())
+            .unwrap_or("unknown")
+    }
+
+    pub fn version(&self) -> &semver::Version {
+        &self.version
+    }
+
+    /// The mods that have to be active before this one can start
+    pub fn dependencies(&self) -> &[LoadedDependency] {
+        &self.dependencies
+    }
+
+    pub fn manifest_hash(&self) -> &common::FileHash {
+        &self.manifest_hash
+    }
+
+    pub fn trust(&self) -> Trust {
+        self.trust
+    }
+
+    /// Returns the contents of a file bundled with the mod's package
+    pub fn asset(&self, name: &str) -> Option<&[u8]> {
+        self.assets
+            .iter()
+            .find(|(asset, _)| asset == name)
+            .map(|(_, bytes)| bytes.as_slice())
+    }
+
+    /// Iterates over the names of the files bundled with the mod's package
+    pub fn asset_names(&self) -> impl Iterator<Item = &str> {
+        self.assets.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn features(&self) -> &[LoadedFeature] {
+        &self.features
+    }
+
+    /// The failures of this mod's systems, and whether it is quarantined because of them
+    pub fn health(&self) -> &ModHealth {
+        &self.health
+    }
+
+    /// Everything the systems of this mod need to be allowed to do, based on their parameters
+    pub fn requested_capabilities(&self) -> HashSet<Capability> {
+        self.features
+            .iter()
+            .flat_map(|feature| feature.schedules.iter())
+            .flat_map(|(_, schedule)| schedule.systems())
+            .flat_map(|(_, system)| system.params())
+            .flat_map(Capability::from_param)
+            .collect()
+    }
+
+    /// The resources the systems of this mod read and write, based on their parameters
+    pub fn access(&self) -> ModAccess {
+        let mut access = ModAccess::default();
+        for feature in self.features.iter() {
+            for (_, schedule) in feature.schedules.iter() {
+                for (_, system) in schedule.systems() {
+                    access.extend(&system.access());
+                }
+            }
+        }
+        access
+    }
+
+    /// Looks up a system in the schedules of every feature
+    pub fn system(
+        &self,
+        schedule_id: &common::OwnedStableId,
+        id: common::SystemId,
+    ) -> Option<&LoadedSystem> {
+        self.features
+            .iter()
+            .filter_map(|feature| feature.schedules.get(schedule_id))
+            .find_map(|schedule| schedule.system(id))
+    }
+
+    /// The current value of every resource of this mod
+    pub fn resources(&self) -> &ModResources {
+        self.runtime.resources()
+    }
+
+    /// Takes over the state of a previous build of this mod
+    ///
+    /// Resources are migrated to their new types, while entities it spawned are kept and its
+    /// [`common::Start`] systems are not run again. The new build starts out healthy.
+    pub(super) fn reload_from(&mut self, previous: LoadedMod) {
+        let old_manifest = common::ModManifest::decode(&previous.manifest)
+            .expect("manifest was decoded when loading");
+        let new_manifest =
+            common::ModManifest::decode(&self.manifest).expect("manifest was decoded when loading");
+        let migration = Migration::new(&old_manifest.types, &new_manifest.types);
+
+        for (id, value) in previous.resources().iter() {
+            // Resources that no longer exist are dropped
+            let Some(default) = self.resources().get(id) else {
+                continue;
+            };
+
+            match migration.migrate(id, value, default) {
+                Ok(migrated) => self.runtime.resources_mut().insert(id.clone(), migrated),
+                Err(err) => warn!(
+                    "Could not migrate resource {:?}, resetting it to its default: {}",
+                    id, err
+                ),
+            }
+        }
+
+        self.started = previous.started;
+        self.runtime.take_entities(previous.runtime);
+    }
+
+    /// Despawns every entity this mod spawned, consuming it
+    pub(super) fn despawn_entities(self, world: &mut World) {
+        self.runtime.despawn_entities(world);
+    }
+
+    pub(super) fn start_frame(&mut self, budget: &ExecutionBudget, policy: &PermissionPolicy) {
+        let grants = policy.grants_for(self);
+        self.runtime.start_frame(budget, grants);
+    }
+
+    /// Decodes the manifest this mod was loaded from
+    pub(crate) fn manifest(&self) -> common::ModManifest<'_> {
+        common::ModManifest::decode(&self.manifest).expect("manifest was decoded when loading")
+    }
+
+    /// Prepares an instance of this mod for each lane of the next batch, see [`ModRuntime::prepare_lanes`]
+    ///
+    /// Returns the lanes the systems will run in. If the mod could not create an instance for every lane, the
+    /// extra lanes run after the last lane it has an instance for.
+    pub(super) fn prepare_systems(
+        &mut self,
+        world: &World,
+        schedule_id: &common::OwnedStableId,
+        mut lanes: Vec<Vec<common::SystemId>>,
+    ) -> Vec<Vec<common::SystemId>> {
+        let count = self.runtime.ensure_instances(lanes.len());
+        let extra: Vec<_> = lanes.drain(count..).flatten().collect();
+        lanes[count - 1].extend(extra);
+
+        let features = &self.features;
+        let params = |systems: &[common::SystemId]| -> Vec<&common::OwnedParam> {
+            features
+                .iter()
+                .filter_map(|feature| feature.schedules.get(schedule_id))
+                .flat_map(|schedule| systems.iter().filter_map(|&id| schedule.system(id)))
+                .flat_map(LoadedSystem::params)
+                .collect()
+        };
+        let lane_params = lanes.iter().map(|systems| {
+            let params = params(systems);
+            let queries = params
+                .iter()
+                .filter_map(|param| match param {
+                    common::OwnedParam::Query(query) => Some(query),
+                    _ => None,
+                })
+                .collect();
+            let resources = params
+                .iter()
+                .filter_map(|param| match param {
+                    common::OwnedParam::Res { id, .. } => Some(id),
+                    _ => None,
+                })
+                .collect();
+            (queries, resources)
+        });
+        self.runtime.prepare_lanes(world, lane_params);
+        lanes
+    }
+
+    /// Applies the commands queued by the systems of this mod to the world
+    pub(super) fn apply_commands(&mut self, world: &mut World) {
+        self.runtime.apply_commands(world);
+    }
+
+    /// Lends out the instances prepared by [`Self::prepare_systems`] to run their lanes at the same time
+    ///
+    /// The results are handed back to [`Self::finish_lanes`].
+    pub(super) fn lanes<'a>(
+        &'a mut self,
+        schedule_id: &'a common::OwnedStableId,
+        lanes: &'a [Vec<common::SystemId>],
+        budget: &'a ExecutionBudget,
+        policy: &QuarantinePolicy,
+    ) -> Vec<SystemLane<'a>> {
+        // Quarantined mods don't run, and lanes stop once their own failures would quarantine the mod
+        let failures_left = match self.health.is_quarantined() {
+            true => 0,
+            false => policy
+                .max_failures
+                .saturating_sub(self.health.failures().len()),
+        };
+        let features = &self.features;
+        self.runtime
+            .lanes()
+            .into_iter()
+            .zip(lanes)
+            .map(|(runtime, systems)| SystemLane {
+                runtime,
+                features,
+                schedule_id,
+                systems,
+                budget,
+                failures_left,
+            })
+            .collect()
+    }
+
+    /// Records the failures of the lanes that ran, in the order of the lanes, returning what each of the run
+    /// conditions returned
+    ///
+    /// Failing systems are recorded in the [`ModHealth`] of this mod.
+    pub(super) fn finish_lanes(
+        &mut self,
+        results: Vec<LaneResult>,
+        policy: &QuarantinePolicy,
+    ) -> Vec<(common::SystemId, bool)> {
+        let mut conditions = Vec::new();
+        let mut exhausted = false;
+        for result in results {
+            conditions.extend(result.conditions);
+            exhausted |= result.exhausted;
+
+            for failure in result.failures {
+                if self.health.is_quarantined() {
+                    break;
+                }
+                error!(
+                    "System {} of mod {} failed: {}",
+                    failure.system_name,
+                    self.name(),
+                    failure.error
+                );
+                self.health.record(failure, policy);
+                if self.health.is_quarantined() {
+                    error!(
+                        "Mod {} failed {} times, quarantining it",
+                        self.name(),
+                        self.health.failures().len()
+                    );
+                }
+            }
+        }
+        if exhausted {
+            warn!(
+                "Mod {} exhausted its budget for this frame, skipping its remaining systems",
+                self.name()
+            );
+        }
+        conditions
+    }
+}
+
+/// Systems of a mod running in order on one of its instances, see [`LoadedMod::lanes`]
+pub(super) struct SystemLane<'a> {
+    runtime: Lane<'a>,
+    features: &'a [LoadedFeature],
+    schedule_id: &'a common::OwnedStableId,
+    systems: &'a [common::SystemId],
+    budget: &'a ExecutionBudget,
+    /// How many systems of the lane may fail before the mod is quarantined
+    failures_left: usize,
+}
+
+/// What happened to the systems of a [`SystemLane`]
+#[derive(Debug, Default)]
+pub(super) struct LaneResult {
+    /// What each of the run conditions of the lane returned, a failing condition counts as returning false
+    pub conditions: Vec<(common::SystemId, bool)>,
+    pub failures: Vec<SystemFailure>,
+    /// Whether the mod ran out of budget for this frame before the lane was done
+    pub exhausted: bool,
+}
+
+impl SystemLane<'_> {
+    /// Runs the systems of the lane in order
+    ///
+    /// No more systems run once the mod is out of budget for this frame, or would be quarantined.
+    pub fn run(mut self) -> LaneResult {
+        let mut result = LaneResult::default();
+        for &system in self.systems {
+            if result.failures.len() >= self.failures_left || self.runtime.frame_budget() == 0 {
+                break;
+            }
+
+            let loaded_system = self
+                .features
+                .iter()
+                .filter_map(|feature| feature.schedules.get(self.schedule_id))
+                .find_map(|schedule| schedule.system(system));
+            let outcome = if loaded_system.is_some_and(LoadedSystem::is_condition) {
+                let outcome = self.runtime.run_condition(system, self.budget);
+                result
+                    .conditions
+                    .push((system, *outcome.as_ref().unwrap_or(&false)));
+                outcome.map(|_| ())
+            } else {
+                self.runtime.run_system(system, self.budget)
+            };
+
+            match outcome {
+                Ok(()) => {}
+                Err(SystemError::FrameBudgetExhausted) => {
+                    result.exhausted = true;
+                    break;
+                }
+                Err(error) => result.failures.push(SystemFailure {
+                    system,
+                    system_name: loaded_system
+                        .map_or("unknown", LoadedSystem::name)
+                        .to_owned(),
+                    error,
+                }),
+            }
+        }
+        result
+    }
+}
+
+/// Systems of a mod accessing the same resources or components without being ordered may run in a different order
+/// in its next build, but still load
+fn warn_ambiguities(features: &[LoadedFeature]) {
+    for feature in features {
+        for (id, schedule) in feature.schedules.iter() {
+            for ambiguity in schedule.ambiguities() {
+                let [first, second] = ambiguity
+                    .systems
+                    .map(|system| schedule.system_name(system).unwrap_or("unknown"));
+                let resources: Vec<_> = ambiguity
+                    .resources
+                    .iter()
+                    .chain(ambiguity.components.iter())
+                    .map(|resource| resource.name.as_str())
+                    .collect();
+                warn!(
+                    "Systems {} and {} of mod {} both access {} in schedule {} without being ordered",
+                    first,
+                    second,
+                    feature.name,
+                    resources.join(", "),
+                    id.name
+                );
+            }
+        }
+    }
 }
 
 pub type LoadedModResult = Result<LoadedMod, LoadingError>;
@@ -103,9 +606,83 @@ pub type LoadedModResult = Result<LoadedMod, LoadingError>;
 #[derive(Debug)]
 pub enum LoadingError {
     FileNotFound(PathBuf, Option<io::Error>),
-    InvalidManifest,
+    /// The manifest is corrupted, or the mod was built with an incompatible version of the api
+    InvalidManifest(common::ManifestError),
+    /// The version of the mod or one of its dependencies is not valid semver
+    InvalidVersion(String),
+    InvalidPackage(common::PackageError),
     InvalidWasm(wasmer::CompileError),
+    MemoryAllocationFailed(wasmer::MemoryError),
+    /// The module needs more memory than [`MemoryLimits::max_pages`] allows
+    MemoryLimitExceeded {
+        required: u32,
+        max_pages: u32,
+    },
+    /// The mod imports a function the modloader does not provide, it was likely built with a newer api
+    MissingImport {
+        module: String,
+        name: String,
+    },
+    InstantiationFailed(wasmer::InstantiationError),
+    MissingExport(&'static str),
     MissmatchingDependencies,
+    /// The signature of the mod is malformed or does not match its manifest
+    InvalidSignature,
+    /// The mod is not signed by a trusted key, and the [`TrustPolicy`] does not allow untrusted mods
+    Untrusted,
     InvalidSchedule(common::OwnedStableId),
     SchedulingError(SchedulingError),
+    /// The asset server could not load the mod's [`super::ModAsset`]
+    AssetLoadFailed(String),
+    /// The mod was unloaded before it finished loading
+    Cancelled,
+    /// The exact same build of this mod is already loaded
+    AlreadyLoaded(super::ModHandle),
+    /// No mod with this name is loaded, and no other mod is still loading
+    MissingDependency(String),
+    /// The mod depended on is loaded, but its version does not match the requirement
+    IncompatibleDependency {
+        name: String,
+        required: semver::VersionReq,
+        found: semver::Version,
+    },
+    /// The mod depends on itself through the mods with these names, starting with its own
+    DependencyCycle(Vec<String>),
+    /// The new build of a loaded mod would leave the active mod with this name without its dependencies, the
+    /// previous build stays loaded
+    BreaksDependent {
+        name: String,
+        error: Box<LoadingError>,
+    },
+}
+
+#[cfg(test)]
+impl LoadedMod {
+    /// Loads a mod from its manifest and the WebAssembly text of its module, as if the build tool packaged it
+    ///
+    /// Its systems may be added to [`common::Update`].
+    pub(crate) fn from_wat(
+        mut manifest: common::ModManifest<'_>,
+        wat: &str,
+        limits: &MemoryLimits,
+    ) -> Self {
+        let wasm = wasmer::wat2wasm(wat.as_bytes()).unwrap().into_owned();
+        manifest.wasm_hash = common::FileHash::from_sha256(Sha256::digest(&wasm).into());
+        let package = common::ModPackage {
+            manifest: manifest.encode(),
+            wasm,
+            signature: None,
+            assets: Vec::new(),
+        };
+        let schedule_labels =
+            HashSet::from_iter([common::OwnedStableId::from_typed::<common::Update>()]);
+
+        bevy_tasks::block_on(Self::try_from_package(
+            package,
+            limits,
+            &TrustPolicy::default(),
+            &schedule_labels,
+        ))
+        .unwrap()
+    }
 }
@@ -1,13 +1,20 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
 
 use bevy_utils::{HashMap, HashSet};
-use common::{OwnedStableId, Start, Update};
-use petgraph::{algo::TarjanScc, prelude::*};
+use common::{OwnedStableId, Start};
+use petgraph::{
+    algo::TarjanScc,
+    dot::{Config, Dot},
+    prelude::*,
+};
 
 use super::LoadingError;
-use crate::mods::{Cycle, SchedulingError};
+use crate::mods::{ConstraintDescription, Cycle, ModAccess, SchedulingError};
 
-type Dag<T> = DiGraphMap<T, ()>;
+pub(crate) type Dag<T> = DiGraphMap<T, ()>;
 
 // These fields are read by a debug macro
 #[allow(dead_code)]
@@ -15,14 +22,17 @@ type Dag<T> = DiGraphMap<T, ()>;
 pub struct LoadedSchedules(HashMap<common::OwnedStableId, LoadedSchedule>);
 
 impl LoadedSchedules {
+    /// Only schedules with one of the given labels are allowed, besides [`Start`]
     pub fn try_from_schedule_descriptors<'a>(
         descriptors: &Vec<common::ScheduleDescriptor<'a>>,
+        labels: &HashSet<OwnedStableId>,
     ) -> Result<Self, LoadingError> {
         let mut schedules = HashMap::default();
 
-        // Allow only the default schedules for now
         schedules.insert(OwnedStableId::from_typed::<Start>(), Vec::new());
-        schedules.insert(OwnedStableId::from_typed::<Update>(), Vec::new());
+        for label in labels {
+            schedules.insert(label.clone(), Vec::new());
+        }
 
         // Group together schedules with the same schedule id
         for descriptor in descriptors {
@@ -36,7 +46,7 @@ impl LoadedSchedules {
         let mut inner = HashMap::default();
         for (id, schedules) in schedules.into_iter() {
             if !schedules.is_empty() {
-                let loaded = LoadedSchedule::try_from_schedules(&schedules[..])
+                let loaded = LoadedSchedule::try_from_schedules(&id, &schedules[..])
                     .map_err(LoadingError::SchedulingError)?;
                 inner.insert(id, loaded);
             }
@@ -44,6 +54,15 @@ impl LoadedSchedules {
 
         Ok(Self(inner))
     }
+
+    pub fn get(&self, id: &OwnedStableId) -> Option<&LoadedSchedule> {
+        self.0.get(id)
+    }
+
+    /// Iterates over every schedule with at least one system, by schedule label
+    pub fn iter(&self) -> impl Iterator<Item = (&OwnedStableId, &LoadedSchedule)> {
+        self.0.iter()
+    }
 }
 
 // These fields are read by a debug macro
@@ -52,80 +71,367 @@ impl LoadedSchedules {
 pub struct LoadedSchedule {
     systems: HashMap<common::SystemId, LoadedSystem>,
     dependency: Dag<common::SystemId>,
+    /// Every system of the schedule, sorted such that systems always come after their dependencies
+    order: Vec<common::SystemId>,
+    sets: SetGraph<common::SystemId>,
+    /// Systems accessing the same resources without being ordered
+    ambiguities: Vec<common::OwnedAmbiguity>,
+}
+
+/// Which graph of a schedule to render as Graphviz DOT, see [`LoadedSchedule::to_dot`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotGraph {
+    /// The graph built from the constraints, where every set has a node where it starts and one where it ends
+    Sets,
+    /// The dependencies between systems once the sets are flattened away
+    Flattened,
 }
 
 // These fields are read by a debug macro
 #[allow(dead_code)]
 #[derive(Debug)]
-struct LoadedSystem {
+pub struct LoadedSystem {
     /// Whether or not this system depends on any other system
     /// In the case this is false, the scheduler can run this system first
     is_dependent: bool,
+    /// Whether this system is a run condition, which returns whether the systems it gates should run
+    is_condition: bool,
     name: String,
     params: Vec<common::OwnedParam>,
 }
 
 impl LoadedSchedule {
-    pub fn try_from_schedules(schedules: &[&common::Schedule]) -> Result<Self, SchedulingError> {
+    pub fn try_from_schedules(
+        id: &OwnedStableId,
+        schedules: &[&common::Schedule],
+    ) -> Result<Self, SchedulingError> {
+        let mut declared: HashMap<common::SystemId, &common::System> = HashMap::new();
+        for system in schedules
+            .iter()
+            .flat_map(|schedule| schedule.systems.iter())
+        {
+            // Each group of systems added together is declared separately, so systems may be declared again
+            match declared.insert(system.id, system) {
+                Some(other) if other != system => {
+                    return Err(SchedulingError::SystemDeclaredTwice {
+                        schedule: id.clone(),
+                        system: system.name.to_owned(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        let name = |system: common::SystemId| {
+            declared
+                .get(&system)
+                .map_or("unknown", |system| system.name)
+                .to_owned()
+        };
+
         let mut builder = Builder::default();
 
         // Add constraints to the dependency graph
         for schedule in schedules {
             for constraint in schedule.constraints.iter() {
-                builder.add_constraint(constraint)?;
+                builder
+                    .add_constraint(constraint, constraint, |system| system)
+                    .map_err(|_| SchedulingError::EmptyAnonymousSet {
+                        schedule: id.clone(),
+                        constraint: ConstraintDescription::new(constraint, name),
+                    })?;
             }
         }
 
-        let mut loaded_schedules = builder.build()?;
+        let flattened = builder.build().map_err(|cycles| SchedulingError::Cycles {
+            schedule: id.clone(),
+            cycles: cycles
+                .into_iter()
+                .map(|cycle| Cycle {
+                    systems: cycle.systems.into_iter().map(name).collect(),
+                    constraints: cycle
+                        .constraints
+                        .into_iter()
+                        .map(|constraint| ConstraintDescription::new(constraint, name))
+                        .collect(),
+                })
+                .collect(),
+        })?;
+
+        let mut systems = HashMap::new();
+        let mut order = Vec::new();
+        let mut is_dependent = false;
+        for (id, has_dependencies) in flattened.systems {
+            is_dependent |= has_dependencies;
+            systems.insert(
+                id,
+                LoadedSystem {
+                    is_dependent,
+                    is_condition: false,
+                    name: String::new(),
+                    params: Vec::new(),
+                },
+            );
+            order.push(id);
+        }
+        let flattened_conditions = flattened.conditions;
+        let mut loaded_schedules = Self {
+            systems,
+            dependency: flattened.dependency,
+            order,
+            sets: flattened.sets,
+            ambiguities: common::Schedule::ambiguities(schedules)
+                .iter()
+                .map(common::Ambiguity::to_owned)
+                .collect(),
+        };
 
         // Add missing parameters to the systems
         for schedule in schedules {
             for common::System { id, name, params } in schedule.systems.iter() {
-                let system = loaded_schedules.systems.entry(*id).or_insert(LoadedSystem {
-                    is_dependent: false,
-                    name: String::new(),
-                    params: Vec::new(),
+                let system = loaded_schedules.systems.entry(*id).or_insert_with(|| {
+                    // Systems without constraints can run at any point
+                    loaded_schedules.order.push(*id);
+                    LoadedSystem {
+                        is_dependent: false,
+                        is_condition: false,
+                        name: String::new(),
+                        params: Vec::new(),
+                    }
                 });
                 system.name = String::from(*name);
                 system.params = params.iter().map(common::Param::to_owned).collect();
             }
         }
 
+        for (condition, _) in flattened_conditions {
+            if let Some(system) = loaded_schedules.systems.get_mut(&condition) {
+                system.is_condition = true;
+            }
+        }
+
         Ok(loaded_schedules)
     }
+
+    /// Systems in the order they should be run
+    pub fn order(&self) -> &[common::SystemId] {
+        &self.order
+    }
+
+    pub fn system_name(&self, id: common::SystemId) -> Option<&str> {
+        self.systems.get(&id).map(|system| system.name.as_str())
+    }
+
+    pub fn system(&self, id: common::SystemId) -> Option<&LoadedSystem> {
+        self.systems.get(&id)
+    }
+
+    /// Iterates over every system in the order they run
+    pub fn systems(&self) -> impl Iterator<Item = (common::SystemId, &LoadedSystem)> {
+        self.order.iter().map(|id| (*id, &self.systems[id]))
+    }
+
+    /// Pairs of systems that access the same resources, at least one of them mutably, without being ordered
+    ///
+    /// The order they run in may change between builds of the mod.
+    pub fn ambiguities(&self) -> &[common::OwnedAmbiguity] {
+        &self.ambiguities
+    }
+
+    /// Renders a graph of this schedule as Graphviz DOT, labelled with the names of its systems and sets
+    ///
+    /// Run conditions are drawn as diamonds, and the start and end of sets as dashed boxes.
+    pub fn to_dot(&self, graph: DotGraph) -> String {
+        let name = |id: common::SystemId| self.system_name(id).unwrap_or("unknown");
+        let system_attributes = |id: common::SystemId| {
+            let shape = match self.system(id).is_some_and(LoadedSystem::is_condition) {
+                true => "diamond",
+                false => "ellipse",
+            };
+            format!("label = {:?}, shape = {}", name(id), shape)
+        };
+        let config = [Config::NodeNoLabel, Config::EdgeNoLabel];
+
+        match graph {
+            DotGraph::Sets => {
+                let set_name = |index: usize| match self.sets.sets.get(&index) {
+                    Some(SetLabel::Anonymous(systems)) => {
+                        let systems: Vec<_> = systems.iter().map(|id| name(*id)).collect();
+                        format!("({})", systems.join(", "))
+                    }
+                    Some(SetLabel::Named(id)) => format!("{}::{}", id.crate_name, id.name),
+                    None => String::from("unknown"),
+                };
+                format!(
+                    "{:?}",
+                    Dot::with_attr_getters(
+                        &self.sets.dependency,
+                        &config,
+                        &|_, _| String::new(),
+                        &|_, (node, _)| match node {
+                            Node::System(id) => system_attributes(id),
+                            Node::SetStart(index) => format!(
+                                "label = {:?}, shape = box, style = dashed",
+                                format!("start of {}", set_name(index))
+                            ),
+                            Node::SetEnd(index) => format!(
+                                "label = {:?}, shape = box, style = dashed",
+                                format!("end of {}", set_name(index))
+                            ),
+                        },
+                    )
+                )
+            }
+            DotGraph::Flattened => {
+                format!(
+                    "{:?}",
+                    Dot::with_attr_getters(
+                        &self.dependency,
+                        &config,
+                        &|_, _| String::new(),
+                        &|_, (id, _)| system_attributes(id),
+                    )
+                )
+            }
+        }
+    }
+}
+
+impl LoadedSystem {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn params(&self) -> &[common::OwnedParam] {
+        &self.params
+    }
+
+    pub fn is_condition(&self) -> bool {
+        self.is_condition
+    }
+
+    /// The resources this system reads and writes, based on its parameters
+    pub fn access(&self) -> ModAccess {
+        let mut access = ModAccess::default();
+        for param in self.params.iter() {
+            access.add_param(param);
+        }
+        access
+    }
+}
+
+/// Builds a dependency graph of systems from the constraints of a schedule
+///
+/// Systems are identified by `S`, so the same builder can merge the schedules of several mods. Every edge
+/// remembers the constraint `C` it comes from, to explain the cycles it finds.
+pub(crate) struct Builder<S: Eq + Hash, C> {
+    dependency: DiGraphMap<Node<S>, C>,
+    sets: HashMap<SystemSet<S>, usize>,
+    /// The systems and sets directly within each set
+    members: HashMap<usize, Vec<Node<S>>>,
+    /// Run conditions, with the first node of the set they gate
+    conditions: Vec<(S, Node<S>)>,
+}
+
+impl<S: Copy + Ord + Hash, C> Default for Builder<S, C> {
+    fn default() -> Self {
+        Self {
+            dependency: DiGraphMap::new(),
+            sets: HashMap::new(),
+            members: HashMap::new(),
+            conditions: Vec::new(),
+        }
+    }
 }
 
-#[derive(Default)]
-struct Builder {
-    dependency: Dag<Node>,
-    sets: HashMap<SystemSet, usize>,
+/// The dependency graph of a schedule, with its sets flattened away
+pub(crate) struct Flattened<S> {
+    /// Every system with an ordering constraint, sorted such that systems always come after their dependencies.
+    /// Also tells whether the system has dependencies of its own.
+    pub systems: Vec<(S, bool)>,
+    pub dependency: Dag<S>,
+    /// Every run condition, with the systems it gates
+    pub conditions: Vec<(S, Vec<S>)>,
+    pub sets: SetGraph<S>,
 }
 
-impl Builder {
-    fn add_constraint(&mut self, constraint: &common::Constraint) -> Result<(), SchedulingError> {
+/// The dependency graph of a schedule before its sets are flattened away, kept to render it
+pub(crate) struct SetGraph<S> {
+    dependency: Dag<Node<S>>,
+    /// The systems of anonymous sets, or the id of named sets, by set index
+    sets: HashMap<usize, SetLabel<S>>,
+}
+
+impl<S: Copy + Ord + Hash + fmt::Debug> fmt::Debug for SetGraph<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SetGraph")
+            .field("dependency", &self.dependency)
+            .field("sets", &self.sets)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+enum SetLabel<S> {
+    Anonymous(Vec<S>),
+    Named(OwnedStableId),
+}
+
+/// A cycle in the dependency graph, with the constraints that form it in order
+pub(crate) struct CyclePath<S, C> {
+    /// The systems along the cycle, empty if it only goes through sets
+    pub systems: Vec<S>,
+    pub constraints: Vec<C>,
+}
+
+/// A constraint refers to an anonymous set without systems
+pub(crate) struct EmptyAnonymousSet;
+
+impl<S, C> Builder<S, C>
+where
+    S: Copy + Ord + Hash,
+    C: Copy + PartialEq,
+{
+    /// Adds a system, even if no constraint refers to it
+    pub fn add_system(&mut self, system: S) {
+        self.dependency.add_node(Node::System(system));
+    }
+
+    /// Adds a constraint, `system` maps the ids of the systems it refers to
+    pub fn add_constraint(
+        &mut self,
+        constraint: &common::Constraint,
+        origin: C,
+        system: impl Fn(common::SystemId) -> S,
+    ) -> Result<(), EmptyAnonymousSet> {
         match constraint {
             common::Constraint::Order { before, after } => {
-                let (_, before) = self.populate_set_nodes(before)?;
-                let (after, _) = self.populate_set_nodes(after)?;
-                self.dependency.add_edge(before, after, ());
+                let (_, before) = self.populate_set_nodes(before, origin, &system)?;
+                let (after, _) = self.populate_set_nodes(after, origin, &system)?;
+                self.dependency.add_edge(before, after, origin);
             }
             common::Constraint::Condition { set, condition } => {
-                let condition = Node::System(*condition);
-                let (after, _) = self.populate_set_nodes(set)?;
+                let condition = system(*condition);
+                let (after, _) = self.populate_set_nodes(set, origin, &system)?;
 
                 // The condition must run before the first node of the set
-                self.dependency.add_edge(condition, after, ());
+                self.dependency
+                    .add_edge(Node::System(condition), after, origin);
+                self.conditions.push((condition, after));
             }
             common::Constraint::Includes { parent_name, set } => {
                 let parent = SystemSet::Named(parent_name.to_owned());
-                let (start_parent, end_parent) = self.populate_set_nodes_inner(parent);
-                let (start_set, end_set) = self.populate_set_nodes(set)?;
+                let (start_parent, end_parent) = self.populate_set_nodes_inner(parent, origin);
+                let (start_set, end_set) = self.populate_set_nodes(set, origin, &system)?;
 
                 // The child set must run within the parent set
                 // So the first node of the child set must run after the first node of the parent set
                 // And the last node of the child set must run before the last node of the parent set
-                self.dependency.add_edge(start_parent, start_set, ());
-                self.dependency.add_edge(end_set, end_parent, ());
+                self.dependency.add_edge(start_parent, start_set, origin);
+                self.dependency.add_edge(end_set, end_parent, origin);
+
+                if let Node::SetStart(parent) = start_parent {
+                    self.members.entry(parent).or_default().push(start_set);
+                }
             }
         }
         Ok(())
@@ -135,31 +441,31 @@ impl Builder {
     fn populate_set_nodes(
         &mut self,
         set: &common::SystemSet,
-    ) -> Result<(Node, Node), SchedulingError> {
+        origin: C,
+        system: impl Fn(common::SystemId) -> S,
+    ) -> Result<(Node<S>, Node<S>), EmptyAnonymousSet> {
         match set {
             common::SystemSet::Anonymous(systems) => match systems.len() {
-                0 => Err(SchedulingError::EmptyAnonymousSet),
+                0 => Err(EmptyAnonymousSet),
                 1 => {
-                    let id = Node::System(systems[0]);
+                    let id = Node::System(system(systems[0]));
                     Ok((id, id))
                 }
                 _ => {
-                    let mut set = HashSet::new();
-                    for system in systems {
-                        set.insert(*system);
-                    }
+                    let set = systems.iter().map(|id| system(*id)).collect();
                     let set = SystemSet::Anonymous(set);
-                    Ok(self.populate_set_nodes_inner(set))
+                    Ok(self.populate_set_nodes_inner(set, origin))
                 }
             },
             common::SystemSet::Named(name) => {
                 let set = SystemSet::Named(name.to_owned());
-                Ok(self.populate_set_nodes_inner(set))
+                Ok(self.populate_set_nodes_inner(set, origin))
             }
         }
     }
 
-    fn populate_set_nodes_inner(&mut self, set: SystemSet) -> (Node, Node) {
+    /// The systems of an anonymous set are linked to it by the constraint that first refers to it
+    fn populate_set_nodes_inner(&mut self, set: SystemSet<S>, origin: C) -> (Node<S>, Node<S>) {
         let id = self.sets.get(&set).map(|id| *id).unwrap_or_else(|| {
             let id = self.sets.len();
 
@@ -167,9 +473,13 @@ impl Builder {
             if let SystemSet::Anonymous(systems) = &set {
                 for system in systems {
                     self.dependency
-                        .add_edge(Node::SetStart(id), Node::System(*system), ());
+                        .add_edge(Node::SetStart(id), Node::System(*system), origin);
                     self.dependency
-                        .add_edge(Node::System(*system), Node::SetEnd(id), ());
+                        .add_edge(Node::System(*system), Node::SetEnd(id), origin);
+                    self.members
+                        .entry(id)
+                        .or_default()
+                        .push(Node::System(*system));
                 }
             }
 
@@ -179,34 +489,47 @@ impl Builder {
         (Node::SetStart(id), Node::SetEnd(id))
     }
 
-    fn build(self) -> Result<LoadedSchedule, SchedulingError> {
+    /// Sorts the systems, or returns a cycle for every strongly connected part of the graph
+    pub fn build(self) -> Result<Flattened<S>, Vec<CyclePath<S, C>>> {
         let mut cycles = Vec::new();
         let mut reverse_nodes = Vec::with_capacity(self.dependency.node_count());
         TarjanScc::new().run(&self.dependency, |scc| {
-            if scc.len() == 1 {
+            // A system ordered relative to itself forms a cycle on its own
+            if scc.len() == 1 && !self.dependency.contains_edge(scc[0], scc[0]) {
                 reverse_nodes.push(scc[0]);
             } else {
-                cycles.push(Cycle(
-                    scc.iter()
-                        .filter_map(|node| match node {
-                            Node::System(system) => Some(*system),
-                            _ => None,
-                        })
-                        .collect(),
-                ));
+                cycles.push(self.find_cycle(scc));
             }
         });
 
         if !cycles.is_empty() {
-            return Err(SchedulingError::Cycles {
-                named_set: None,
-                cycles,
-            });
+            return Err(cycles);
         }
 
-        let mut systems = HashMap::new();
+        let mut sets = SetGraph {
+            dependency: Dag::new(),
+            sets: HashMap::new(),
+        };
+        for node in self.dependency.nodes() {
+            sets.dependency.add_node(node);
+        }
+        for (from, to, _) in self.dependency.all_edges() {
+            sets.dependency.add_edge(from, to, ());
+        }
+        for (set, index) in self.sets.iter() {
+            let label = match set {
+                SystemSet::Anonymous(systems) => {
+                    let mut systems: Vec<_> = systems.iter().copied().collect();
+                    systems.sort();
+                    SetLabel::Anonymous(systems)
+                }
+                SystemSet::Named(id) => SetLabel::Named(id.clone()),
+            };
+            sets.sets.insert(*index, label);
+        }
+
+        let mut systems = Vec::new();
         let mut dependency = Dag::new();
-        let mut is_dependent = false;
         for id in reverse_nodes
             .into_iter()
             .rev()
@@ -215,34 +538,107 @@ impl Builder {
                 _ => None,
             })
         {
-            is_dependent |= self
+            let has_dependencies = self
                 .dependency
                 .neighbors_directed(Node::System(id), Direction::Incoming)
                 .next()
                 .is_some();
-            systems.insert(
-                id,
-                LoadedSystem {
-                    is_dependent,
-                    name: String::new(),
-                    params: Vec::new(),
-                },
-            );
+            systems.push((id, has_dependencies));
+            dependency.add_node(id);
             self.add_node_dependents_to_flattened(&mut dependency, id, Node::System(id));
         }
 
-        Ok(LoadedSchedule {
+        let conditions = self
+            .conditions
+            .iter()
+            .map(|(condition, node)| {
+                let mut gated = Vec::new();
+                self.collect_systems(*node, &mut gated);
+                (*condition, gated)
+            })
+            .collect();
+
+        Ok(Flattened {
             systems,
             dependency,
+            conditions,
+            sets,
         })
     }
 
-    fn add_node_dependents_to_flattened(
-        &self,
-        dependency: &mut Dag<common::SystemId>,
-        parent: common::SystemId,
-        node: Node,
-    ) {
+    /// Finds the shortest cycle through the smallest node of a strongly connected part of the graph
+    fn find_cycle(&self, scc: &[Node<S>]) -> CyclePath<S, C> {
+        let start = *scc
+            .iter()
+            .min()
+            .expect("strongly connected parts are never empty");
+
+        // Breadth first search from the start back to itself, staying within the strongly connected part
+        let mut previous: HashMap<Node<S>, Node<S>> = HashMap::new();
+        let mut queue = std::collections::VecDeque::from([start]);
+        'search: while let Some(node) = queue.pop_front() {
+            for next in self
+                .dependency
+                .neighbors_directed(node, Direction::Outgoing)
+            {
+                if !scc.contains(&next) || previous.contains_key(&next) {
+                    continue;
+                }
+                previous.insert(next, node);
+                if next == start {
+                    break 'search;
+                }
+                queue.push_back(next);
+            }
+        }
+
+        let mut path = vec![start];
+        let mut node = previous[&start];
+        while node != start {
+            path.push(node);
+            node = previous[&node];
+        }
+        path.push(start);
+        path.reverse();
+
+        let mut cycle = CyclePath {
+            systems: Vec::new(),
+            constraints: Vec::new(),
+        };
+        for edge in path.windows(2) {
+            if let Node::System(system) = edge[0] {
+                cycle.systems.push(system);
+            }
+            let origin = self.dependency[(edge[0], edge[1])];
+            if cycle.constraints.last() != Some(&origin) {
+                cycle.constraints.push(origin);
+            }
+        }
+        // The cycle may start in the middle of a constraint
+        if cycle.constraints.len() > 1 && cycle.constraints.first() == cycle.constraints.last() {
+            cycle.constraints.pop();
+        }
+        cycle
+    }
+
+    /// Collects the systems within a set, including the systems of the sets it includes
+    fn collect_systems(&self, node: Node<S>, systems: &mut Vec<S>) {
+        match node {
+            Node::System(system) => {
+                if !systems.contains(&system) {
+                    systems.push(system);
+                }
+            }
+            Node::SetStart(id) => {
+                for member in self.members.get(&id).into_iter().flatten() {
+                    self.collect_systems(*member, systems);
+                }
+            }
+            Node::SetEnd(_) => {}
+        }
+    }
+
+    fn add_node_dependents_to_flattened(&self, dependency: &mut Dag<S>, parent: S, node: Node<S>) {
         for child in self
             .dependency
             .neighbors_directed(node, Direction::Outgoing)
@@ -258,12 +654,12 @@ impl Builder {
 }
 
 #[derive(PartialEq, Eq)]
-pub enum SystemSet {
-    Anonymous(HashSet<common::SystemId>),
+pub enum SystemSet<S: Eq + Hash> {
+    Anonymous(HashSet<S>),
     Named(common::OwnedStableId),
 }
 
-impl Hash for SystemSet {
+impl<S: Eq + Hash> Hash for SystemSet<S> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
             Self::Anonymous(systems) => {
@@ -278,8 +674,82 @@ impl Hash for SystemSet {
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
-enum Node {
-    System(common::SystemId),
+enum Node<S> {
+    System(S),
     SetStart(usize),
     SetEnd(usize),
 }
+
+#[cfg(test)]
+mod tests {
+    use common::{Constraint, Schedule, StableId, System, SystemId, SystemSet};
+
+    use super::*;
+
+    const PHYSICS: StableId = StableId {
+        crate_name: "physics",
+        name: "PhysicsSet",
+    };
+
+    /// `read_input` runs before `step` in `PhysicsSet`, which only runs if `is_running`
+    fn schedule() -> LoadedSchedule {
+        let [read_input, step, is_running] = [1, 2, 3].map(SystemId::from_raw);
+        let system = |id, name| System {
+            id,
+            name,
+            params: Vec::new(),
+        };
+        let schedule = Schedule {
+            systems: vec![
+                system(read_input, "read_input"),
+                system(step, "step"),
+                system(is_running, "is_running"),
+            ],
+            constraints: vec![
+                Constraint::Includes {
+                    parent_name: PHYSICS,
+                    set: SystemSet::Anonymous(vec![step]),
+                },
+                Constraint::Order {
+                    before: SystemSet::Anonymous(vec![read_input]),
+                    after: SystemSet::Named(PHYSICS),
+                },
+                Constraint::Condition {
+                    set: SystemSet::Named(PHYSICS),
+                    condition: is_running,
+                },
+            ],
+        };
+        let id = OwnedStableId::from_typed::<Start>();
+        LoadedSchedule::try_from_schedules(&id, &[&schedule]).unwrap()
+    }
+
+    #[test]
+    fn set_graphs_show_where_sets_start_and_end() {
+        let dot = schedule().to_dot(DotGraph::Sets);
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains(r#"label = "read_input", shape = ellipse"#));
+        assert!(dot.contains(r#"label = "step", shape = ellipse"#));
+        assert!(dot.contains(r#"label = "is_running", shape = diamond"#));
+        assert!(
+            dot.contains(r#"label = "start of physics::PhysicsSet", shape = box, style = dashed"#)
+        );
+        assert!(
+            dot.contains(r#"label = "end of physics::PhysicsSet", shape = box, style = dashed"#)
+        );
+        // Into and out of the set, plus the order and condition constraints into its start
+        assert_eq!(dot.matches("->").count(), 4);
+    }
+
+    #[test]
+    fn flattened_graphs_only_show_systems() {
+        let dot = schedule().to_dot(DotGraph::Flattened);
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains(r#"label = "read_input", shape = ellipse"#));
+        assert!(dot.contains(r#"label = "step", shape = ellipse"#));
+        assert!(dot.contains(r#"label = "is_running", shape = diamond"#));
+        assert!(!dot.contains("PhysicsSet"));
+        // `read_input` and `is_running` both run before `step`
+        assert_eq!(dot.matches("->").count(), 2);
+    }
+}
@@ -0,0 +1,139 @@
+use bevy_utils::HashSet;
+use ed25519_dalek::{Signature, VerifyingKey};
+
+/// Whether a mod was signed by a trusted developer, see [`TrustPolicy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// The manifest was signed by one of the [`TrustPolicy`]'s keys
+    Trusted,
+    /// The manifest is not signed, or signed by a key that is not trusted
+    Untrusted,
+    /// The signature is malformed or does not match the manifest
+    Invalid,
+}
+
+/// Decides which mods are trusted, and whether untrusted mods may load
+///
+/// Mods with an invalid signature never load.
+#[derive(Debug, Clone)]
+pub struct TrustPolicy {
+    trusted_keys: HashSet<VerifyingKey>,
+    /// Whether mods that are unsigned or signed by an unknown key may load
+    pub allow_untrusted: bool,
+}
+
+impl Default for TrustPolicy {
+    fn default() -> Self {
+        Self {
+            trusted_keys: HashSet::new(),
+            // Mods built by the devtools are not signed
+            allow_untrusted: true,
+        }
+    }
+}
+
+impl TrustPolicy {
+    /// Trusts mods whose manifest is signed with the private half of this key
+    pub fn trust(&mut self, key: VerifyingKey) -> &mut Self {
+        self.trusted_keys.insert(key);
+        self
+    }
+
+    pub fn trusted_keys(&self) -> impl Iterator<Item = &VerifyingKey> {
+        self.trusted_keys.iter()
+    }
+
+    /// Checks the signature of an encoded manifest, if there is one
+    pub fn verify(&self, manifest_bytes: &[u8], signature_bytes: Option<&[u8]>) -> Trust {
+        let Some(signature_bytes) = signature_bytes else {
+            return Trust::Untrusted;
+        };
+        let Ok(signature) = bitcode::decode::<common::ModSignature>(signature_bytes) else {
+            return Trust::Invalid;
+        };
+        let Ok(key) = VerifyingKey::from_bytes(&signature.public_key) else {
+            return Trust::Invalid;
+        };
+
+        let signature = Signature::from_bytes(&signature.signature);
+        if key.verify_strict(manifest_bytes, &signature).is_err() {
+            Trust::Invalid
+        } else if self.trusted_keys.contains(&key) {
+            Trust::Trusted
+        } else {
+            Trust::Untrusted
+        }
+    }
+
+    /// Whether a mod with this trust may load
+    pub fn allows(&self, trust: Trust) -> bool {
+        match trust {
+            Trust::Trusted => true,
+            Trust::Untrusted => self.allow_untrusted,
+            Trust::Invalid => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    const MANIFEST: &[u8] = b"HMAN manifest";
+
+    fn sign(key: &SigningKey, manifest: &[u8]) -> Vec<u8> {
+        bitcode::encode(&common::ModSignature {
+            public_key: key.verifying_key().to_bytes(),
+            signature: key.sign(manifest).to_bytes(),
+        })
+    }
+
+    fn trusting(key: &SigningKey) -> TrustPolicy {
+        let mut policy = TrustPolicy::default();
+        policy.trust(key.verifying_key());
+        policy
+    }
+
+    #[test]
+    fn trusted_key() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let policy = trusting(&key);
+        let signature = sign(&key, MANIFEST);
+        assert_eq!(policy.verify(MANIFEST, Some(&signature)), Trust::Trusted);
+        assert!(policy.allows(Trust::Trusted));
+    }
+
+    #[test]
+    fn untrusted_key() {
+        let policy = trusting(&SigningKey::from_bytes(&[1; 32]));
+        let signature = sign(&SigningKey::from_bytes(&[2; 32]), MANIFEST);
+        assert_eq!(policy.verify(MANIFEST, Some(&signature)), Trust::Untrusted);
+        assert_eq!(policy.verify(MANIFEST, None), Trust::Untrusted);
+    }
+
+    #[test]
+    fn tampered_manifest() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let policy = trusting(&key);
+        let signature = sign(&key, MANIFEST);
+        assert_eq!(
+            policy.verify(b"HMAN tampered", Some(&signature)),
+            Trust::Invalid
+        );
+        assert_eq!(policy.verify(MANIFEST, Some(&[1, 2, 3])), Trust::Invalid);
+        assert!(!policy.allows(Trust::Invalid));
+    }
+
+    #[test]
+    fn untrusted_mods_may_be_refused() {
+        let mut policy = TrustPolicy::default();
+        assert!(policy.allows(Trust::Untrusted));
+
+        policy.allow_untrusted = false;
+        assert!(!policy.allows(Trust::Untrusted));
+        assert!(!policy.allows(Trust::Invalid));
+        assert!(policy.allows(Trust::Trusted));
+    }
+}
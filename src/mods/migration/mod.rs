@@ -0,0 +1,562 @@
+use std::{collections::HashSet, error::Error, fmt};
+
+use common::{OwnedStableId, StableId, TypeSignature, VariantSignature};
+use serde::de::DeserializeSeed;
+
+mod value;
+use value::{is_option, Shape, Signatures, TypedValue, Value, ValueSeed};
+
+/// Carries resource values over from one build of a mod to the next
+///
+/// Values whose types are unchanged are copied as is. Otherwise they are decoded using the type signatures of
+/// the old build, and every field that still exists in the new build is copied over the new default value.
+pub(crate) struct Migration<'a> {
+    old: Signatures<'a>,
+    new: Signatures<'a>,
+}
+
+impl<'a> Migration<'a> {
+    pub fn new(old: &'a [TypeSignature<'a>], new: &'a [TypeSignature<'a>]) -> Self {
+        Self {
+            old: Signatures::new(old),
+            new: Signatures::new(new),
+        }
+    }
+
+    /// Migrates the value of a resource, given the default value declared by the new build
+    pub fn migrate(
+        &self,
+        id: &OwnedStableId,
+        old: &[u8],
+        new_default: &[u8],
+    ) -> Result<Vec<u8>, MigrationError> {
+        let borrowed = StableId {
+            crate_name: &id.crate_name,
+            name: &id.name,
+        };
+        let old_ty = *self
+            .old
+            .get(&borrowed)
+            .ok_or_else(|| MigrationError::UnknownType(id.clone()))?
+            .ty();
+        let new_ty = *self
+            .new
+            .get(&borrowed)
+            .ok_or_else(|| MigrationError::UnknownType(id.clone()))?
+            .ty();
+
+        if self.same_type(old_ty, new_ty, &mut HashSet::new()) {
+            return Ok(old.to_vec());
+        }
+
+        let old = decode(old, old_ty, &self.old)?;
+        let new_default = decode(new_default, new_ty, &self.new)?;
+        let migrated = self.merge(old, old_ty, new_default, new_ty);
+        encode(&migrated, new_ty, &self.new)
+    }
+
+    /// Whether values of the old type are encoded exactly like values of the new type
+    fn same_type(
+        &self,
+        old: StableId<'a>,
+        new: StableId<'a>,
+        visited: &mut HashSet<(StableId<'a>, StableId<'a>)>,
+    ) -> bool {
+        // Recursive types are assumed to match, any difference will be found elsewhere
+        if !visited.insert((old, new)) {
+            return true;
+        }
+
+        match (self.old.shape(&old), self.new.shape(&new)) {
+            (Ok(Shape::Primitive(old)), Ok(Shape::Primitive(new))) => old == new,
+            (Ok(Shape::Signature(old)), Ok(Shape::Signature(new))) => {
+                self.same_signature(old, new, visited)
+            }
+            _ => false,
+        }
+    }
+
+    fn same_types(
+        &self,
+        old: &[StableId<'a>],
+        new: &[StableId<'a>],
+        visited: &mut HashSet<(StableId<'a>, StableId<'a>)>,
+    ) -> bool {
+        old.len() == new.len()
+            && old
+                .iter()
+                .zip(new)
+                .all(|(old, new)| self.same_type(*old, *new, visited))
+    }
+
+    fn same_signature(
+        &self,
+        old: &'a TypeSignature<'a>,
+        new: &'a TypeSignature<'a>,
+        visited: &mut HashSet<(StableId<'a>, StableId<'a>)>,
+    ) -> bool {
+        match (old, new) {
+            (
+                TypeSignature::Struct { fields: old, .. },
+                TypeSignature::Struct { fields: new, .. },
+            ) => {
+                old.len() == new.len()
+                    && old.iter().zip(new).all(|(old, new)| {
+                        old.name == new.name && self.same_type(old.ty, new.ty, visited)
+                    })
+            }
+            (
+                TypeSignature::TupleStruct { fields: old, .. },
+                TypeSignature::TupleStruct { fields: new, .. },
+            )
+            | (
+                TypeSignature::Tuple { fields: old, .. },
+                TypeSignature::Tuple { fields: new, .. },
+            ) => self.same_types(old, new, visited),
+            (
+                TypeSignature::List { item_ty: old, .. },
+                TypeSignature::List { item_ty: new, .. },
+            )
+            | (
+                TypeSignature::Set { value_ty: old, .. },
+                TypeSignature::Set { value_ty: new, .. },
+            ) => self.same_type(*old, *new, visited),
+            (
+                TypeSignature::Array {
+                    item_ty: old,
+                    capacity: old_capacity,
+                    ..
+                },
+                TypeSignature::Array {
+                    item_ty: new,
+                    capacity: new_capacity,
+                    ..
+                },
+            ) => old_capacity == new_capacity && self.same_type(*old, *new, visited),
+            (
+                TypeSignature::Map {
+                    key_ty: old_key,
+                    value_ty: old_value,
+                    ..
+                },
+                TypeSignature::Map {
+                    key_ty: new_key,
+                    value_ty: new_value,
+                    ..
+                },
+            ) => {
+                self.same_type(*old_key, *new_key, visited)
+                    && self.same_type(*old_value, *new_value, visited)
+            }
+            (
+                TypeSignature::Enum {
+                    ty: old_ty,
+                    variants: old,
+                    ..
+                },
+                TypeSignature::Enum {
+                    ty: new_ty,
+                    variants: new,
+                    ..
+                },
+            ) => {
+                is_option(old_ty) == is_option(new_ty)
+                    && old.len() == new.len()
+                    && old
+                        .iter()
+                        .zip(new)
+                        .all(|(old, new)| self.same_variant(old, new, visited))
+            }
+            (TypeSignature::Opaque { ty: old, .. }, TypeSignature::Opaque { ty: new, .. }) => {
+                old == new
+            }
+            _ => false,
+        }
+    }
+
+    fn same_variant(
+        &self,
+        old: &'a VariantSignature<'a>,
+        new: &'a VariantSignature<'a>,
+        visited: &mut HashSet<(StableId<'a>, StableId<'a>)>,
+    ) -> bool {
+        match (old, new) {
+            (
+                VariantSignature::Struct {
+                    name: old_name,
+                    fields: old,
+                },
+                VariantSignature::Struct {
+                    name: new_name,
+                    fields: new,
+                },
+            ) => {
+                old_name == new_name
+                    && old.len() == new.len()
+                    && old.iter().zip(new).all(|(old, new)| {
+                        old.name == new.name && self.same_type(old.ty, new.ty, visited)
+                    })
+            }
+            (
+                VariantSignature::Tuple {
+                    name: old_name,
+                    fields: old,
+                },
+                VariantSignature::Tuple {
+                    name: new_name,
+                    fields: new,
+                },
+            ) => old_name == new_name && self.same_types(old, new, visited),
+            (
+                VariantSignature::Unit { name: old_name },
+                VariantSignature::Unit { name: new_name },
+            ) => old_name == new_name,
+            _ => false,
+        }
+    }
+
+    /// Copies as much of the old value as possible over the new default value
+    fn merge(&self, old: Value, old_ty: StableId<'a>, new: Value, new_ty: StableId<'a>) -> Value {
+        if self.same_type(old_ty, new_ty, &mut HashSet::new()) {
+            return old;
+        }
+
+        let (Ok(Shape::Signature(old_signature)), Ok(Shape::Signature(new_signature))) =
+            (self.old.shape(&old_ty), self.new.shape(&new_ty))
+        else {
+            return new;
+        };
+
+        match (old_signature, new_signature, old, new) {
+            // Fields are matched by name, so they can be added, removed or reordered
+            (
+                TypeSignature::Struct {
+                    fields: old_fields, ..
+                },
+                TypeSignature::Struct {
+                    fields: new_fields, ..
+                },
+                Value::Fields(old_values),
+                Value::Fields(new_values),
+            ) => {
+                let mut old: Vec<_> = old_fields.iter().zip(old_values).collect();
+                let values = new_fields
+                    .iter()
+                    .zip(new_values)
+                    .map(|(field, new)| {
+                        match old.iter().position(|(old, _)| old.name == field.name) {
+                            Some(index) => {
+                                let (old_field, old) = old.swap_remove(index);
+                                self.merge(old, old_field.ty, new, field.ty)
+                            }
+                            None => new,
+                        }
+                    })
+                    .collect();
+                Value::Fields(values)
+            }
+            (
+                TypeSignature::TupleStruct {
+                    fields: old_fields, ..
+                },
+                TypeSignature::TupleStruct {
+                    fields: new_fields, ..
+                },
+                Value::Fields(old_values),
+                Value::Fields(new_values),
+            )
+            | (
+                TypeSignature::Tuple {
+                    fields: old_fields, ..
+                },
+                TypeSignature::Tuple {
+                    fields: new_fields, ..
+                },
+                Value::Fields(old_values),
+                Value::Fields(new_values),
+            ) if old_fields.len() == new_fields.len() => {
+                let values = old_fields
+                    .iter()
+                    .zip(old_values)
+                    .zip(new_fields.iter().zip(new_values))
+                    .map(|((old_ty, old), (new_ty, new))| self.merge(old, *old_ty, new, *new_ty))
+                    .collect();
+                Value::Fields(values)
+            }
+            // Variants are matched by name and kept only if their fields are unchanged
+            (
+                TypeSignature::Enum {
+                    variants: old_variants,
+                    ..
+                },
+                TypeSignature::Enum {
+                    variants: new_variants,
+                    ..
+                },
+                Value::Variant { index, fields },
+                new,
+            ) => {
+                let Some(old_variant) = old_variants.get(index as usize) else {
+                    return new;
+                };
+                let new_index = new_variants.iter().position(|new_variant| {
+                    self.same_variant(old_variant, new_variant, &mut HashSet::new())
+                });
+                match new_index {
+                    Some(index) => Value::Variant {
+                        index: index as u32,
+                        fields,
+                    },
+                    None => new,
+                }
+            }
+            (_, _, _, new) => new,
+        }
+    }
+}
+
+fn decode<'a>(
+    mut bytes: &[u8],
+    ty: StableId<'a>,
+    types: &Signatures<'a>,
+) -> Result<Value, MigrationError> {
+    let mut decoder = bitcode::SerdeDecoder::Unspecified { length: 1 };
+    let bitcode_deserializer = bitcode::DecoderWrapper {
+        decoder: &mut decoder,
+        input: &mut bytes,
+    };
+
+    ValueSeed { ty, types }
+        .deserialize(bitcode_deserializer)
+        .map_err(|err| MigrationError::Decode(err.to_string()))
+}
+
+fn encode<'a>(
+    value: &Value,
+    ty: StableId<'a>,
+    types: &Signatures<'a>,
+) -> Result<Vec<u8>, MigrationError> {
+    bitcode::serialize(&TypedValue { value, ty, types })
+        .map_err(|err| MigrationError::Encode(err.to_string()))
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    UnknownType(OwnedStableId),
+    UnsupportedType(OwnedStableId),
+    Decode(String),
+    Encode(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownType(id) => write!(f, "Unknown type: {:?}", id),
+            Self::UnsupportedType(id) => write!(f, "Unsupported type: {:?}", id),
+            Self::Decode(err) => write!(f, "Could not decode value: {}", err),
+            Self::Encode(err) => write!(f, "Could not encode value: {}", err),
+        }
+    }
+}
+
+impl Error for MigrationError {}
+
+#[cfg(test)]
+mod tests {
+    use common::FieldSignature;
+
+    use super::*;
+
+    const RESOURCE: StableId<'static> = StableId {
+        crate_name: "my_mod",
+        name: "MyResource",
+    };
+    const U32: StableId<'static> = StableId {
+        crate_name: "core",
+        name: "u32",
+    };
+    const STRING: StableId<'static> = StableId {
+        crate_name: "alloc",
+        name: "String",
+    };
+
+    fn resource<'a>(fields: &[(&'a str, StableId<'a>)]) -> TypeSignature<'a> {
+        TypeSignature::Struct {
+            ty: RESOURCE,
+            generics: Vec::new(),
+            fields: fields
+                .iter()
+                .map(|&(name, ty)| FieldSignature { name, ty })
+                .collect(),
+        }
+    }
+
+    fn enum_resource(variants: Vec<VariantSignature<'static>>) -> TypeSignature<'static> {
+        TypeSignature::Enum {
+            ty: RESOURCE,
+            generics: Vec::new(),
+            variants,
+        }
+    }
+
+    /// Encodes the old value, migrates it and decodes the result
+    fn migrate(
+        old: &[TypeSignature],
+        new: &[TypeSignature],
+        old_value: Value,
+        new_default: Value,
+    ) -> Value {
+        let migration = Migration::new(old, new);
+        let old_bytes = encode(&old_value, RESOURCE, &migration.old).unwrap();
+        let new_bytes = encode(&new_default, RESOURCE, &migration.new).unwrap();
+        let migrated = migration
+            .migrate(&RESOURCE.to_owned(), &old_bytes, &new_bytes)
+            .unwrap();
+        decode(&migrated, RESOURCE, &migration.new).unwrap()
+    }
+
+    #[test]
+    fn unchanged_type_is_copied_byte_for_byte() {
+        let old = [resource(&[("count", U32), ("name", STRING)])];
+        let new = [resource(&[("count", U32), ("name", STRING)])];
+        let migration = Migration::new(&old, &new);
+
+        let value = Value::Fields(vec![Value::U32(7), Value::String("old".into())]);
+        let default = Value::Fields(vec![Value::U32(0), Value::String("new".into())]);
+        let bytes = encode(&value, RESOURCE, &migration.old).unwrap();
+        let default = encode(&default, RESOURCE, &migration.new).unwrap();
+
+        let migrated = migration
+            .migrate(&RESOURCE.to_owned(), &bytes, &default)
+            .unwrap();
+        assert_eq!(migrated, bytes);
+    }
+
+    #[test]
+    fn added_field_takes_the_new_default() {
+        let migrated = migrate(
+            &[resource(&[("count", U32)])],
+            &[resource(&[("count", U32), ("name", STRING)])],
+            Value::Fields(vec![Value::U32(7)]),
+            Value::Fields(vec![Value::U32(0), Value::String("default".into())]),
+        );
+        assert_eq!(
+            migrated,
+            Value::Fields(vec![Value::U32(7), Value::String("default".into())])
+        );
+    }
+
+    #[test]
+    fn removed_field_is_dropped() {
+        let migrated = migrate(
+            &[resource(&[("count", U32), ("name", STRING)])],
+            &[resource(&[("name", STRING)])],
+            Value::Fields(vec![Value::U32(7), Value::String("old".into())]),
+            Value::Fields(vec![Value::String("default".into())]),
+        );
+        assert_eq!(migrated, Value::Fields(vec![Value::String("old".into())]));
+    }
+
+    #[test]
+    fn reordered_fields_are_matched_by_name() {
+        let migrated = migrate(
+            &[resource(&[("count", U32), ("name", STRING)])],
+            &[resource(&[("name", STRING), ("count", U32)])],
+            Value::Fields(vec![Value::U32(7), Value::String("old".into())]),
+            Value::Fields(vec![Value::String("default".into()), Value::U32(0)]),
+        );
+        assert_eq!(
+            migrated,
+            Value::Fields(vec![Value::String("old".into()), Value::U32(7)])
+        );
+    }
+
+    #[test]
+    fn added_variant_shifts_the_variant_index() {
+        let migrated = migrate(
+            &[enum_resource(vec![
+                VariantSignature::Unit { name: "Idle" },
+                VariantSignature::Tuple {
+                    name: "Running",
+                    fields: vec![U32],
+                },
+            ])],
+            &[enum_resource(vec![
+                VariantSignature::Unit { name: "Paused" },
+                VariantSignature::Unit { name: "Idle" },
+                VariantSignature::Tuple {
+                    name: "Running",
+                    fields: vec![U32],
+                },
+            ])],
+            Value::Variant {
+                index: 1,
+                fields: vec![Value::U32(7)],
+            },
+            Value::Variant {
+                index: 0,
+                fields: Vec::new(),
+            },
+        );
+        assert_eq!(
+            migrated,
+            Value::Variant {
+                index: 2,
+                fields: vec![Value::U32(7)],
+            }
+        );
+    }
+
+    #[test]
+    fn removed_variant_falls_back_to_the_new_default() {
+        let migrated = migrate(
+            &[enum_resource(vec![
+                VariantSignature::Unit { name: "Idle" },
+                VariantSignature::Tuple {
+                    name: "Running",
+                    fields: vec![U32],
+                },
+            ])],
+            &[enum_resource(vec![VariantSignature::Unit { name: "Idle" }])],
+            Value::Variant {
+                index: 1,
+                fields: vec![Value::U32(7)],
+            },
+            Value::Variant {
+                index: 0,
+                fields: Vec::new(),
+            },
+        );
+        assert_eq!(
+            migrated,
+            Value::Variant {
+                index: 0,
+                fields: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn structs_with_many_fields_are_migrated() {
+        let names: Vec<_> = (0..300).map(|index| format!("field_{}", index)).collect();
+        let old_fields: Vec<_> = names.iter().map(|name| (name.as_str(), U32)).collect();
+        let mut new_fields = old_fields.clone();
+        new_fields.push(("added", STRING));
+
+        let migrated = migrate(
+            &[resource(&old_fields)],
+            &[resource(&new_fields)],
+            Value::Fields((0..300).map(Value::U32).collect()),
+            Value::Fields(
+                (0..300)
+                    .map(|_| Value::U32(0))
+                    .chain([Value::String("default".into())])
+                    .collect(),
+            ),
+        );
+
+        let mut expected: Vec<_> = (0..300).map(Value::U32).collect();
+        expected.push(Value::String("default".into()));
+        assert_eq!(migrated, Value::Fields(expected));
+    }
+}
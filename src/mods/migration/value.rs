@@ -0,0 +1,616 @@
+use std::{fmt, sync::RwLock};
+
+use bevy_utils::HashMap;
+use common::{StableId, TypeSignature, VariantSignature};
+use serde::{
+    de::{self, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor},
+    ser::{
+        self, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+        SerializeTupleStruct, SerializeTupleVariant,
+    },
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use super::MigrationError;
+
+/// Bitcode only relies on the number of fields and variants, never on their names
+///
+/// Serde requires `'static` names, so the slice is leaked and only replaced by a larger one when a type has more
+/// fields or variants than any type seen before.
+fn names(len: usize) -> &'static [&'static str] {
+    static NAMES: RwLock<&'static [&'static str]> = RwLock::new(&[""; 256]);
+
+    if let Some(names) = NAMES.read().unwrap().get(..len) {
+        return names;
+    }
+    let mut names = NAMES.write().unwrap();
+    if names.len() < len {
+        *names = Vec::leak(vec![""; len.next_power_of_two()]);
+    }
+    &names[..len]
+}
+
+/// The type signatures of a manifest, by stable id
+pub(crate) struct Signatures<'a>(HashMap<StableId<'a>, &'a TypeSignature<'a>>);
+
+impl<'a> Signatures<'a> {
+    pub fn new(types: &'a [TypeSignature<'a>]) -> Self {
+        Self(
+            types
+                .iter()
+                .map(|signature| (*signature.ty(), signature))
+                .collect(),
+        )
+    }
+
+    pub fn get<'s>(&'s self, ty: &StableId<'s>) -> Option<&'a TypeSignature<'a>> {
+        let signatures: &'s HashMap<StableId<'s>, &'a TypeSignature<'a>> = &self.0;
+        signatures.get(ty).copied()
+    }
+
+    pub fn shape(&self, ty: &StableId<'a>) -> Result<Shape<'a>, MigrationError> {
+        // Primitives are opaque types, so their encoding is only known by name
+        if let Some(primitive) = Primitive::from_stable_id(ty) {
+            return Ok(Shape::Primitive(primitive));
+        }
+        self.get(ty)
+            .map(Shape::Signature)
+            .ok_or_else(|| MigrationError::UnknownType(ty.to_owned()))
+    }
+}
+
+pub(crate) enum Shape<'a> {
+    Primitive(Primitive),
+    Signature(&'a TypeSignature<'a>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Primitive {
+    Bool,
+    Char,
+    String,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Isize,
+    F32,
+    F64,
+}
+
+impl Primitive {
+    fn from_stable_id(ty: &StableId) -> Option<Self> {
+        // Mod types may share a name with a primitive, but never a crate
+        if !matches!(ty.crate_name, "unknown" | "core" | "alloc" | "std") {
+            return None;
+        }
+
+        Some(match ty.name {
+            "bool" => Self::Bool,
+            "char" => Self::Char,
+            "String" => Self::String,
+            "u8" => Self::U8,
+            "u16" => Self::U16,
+            "u32" => Self::U32,
+            "u64" => Self::U64,
+            "u128" => Self::U128,
+            "usize" => Self::Usize,
+            "i8" => Self::I8,
+            "i16" => Self::I16,
+            "i32" => Self::I32,
+            "i64" => Self::I64,
+            "i128" => Self::I128,
+            "isize" => Self::Isize,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn deserialize<'de, D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match self {
+            Self::Bool => Value::Bool(bool::deserialize(deserializer)?),
+            Self::Char => Value::Char(char::deserialize(deserializer)?),
+            Self::String => Value::String(String::deserialize(deserializer)?),
+            Self::U8 => Value::U8(u8::deserialize(deserializer)?),
+            Self::U16 => Value::U16(u16::deserialize(deserializer)?),
+            Self::U32 => Value::U32(u32::deserialize(deserializer)?),
+            Self::U64 => Value::U64(u64::deserialize(deserializer)?),
+            Self::U128 => Value::U128(u128::deserialize(deserializer)?),
+            // Serde encodes pointer sized integers as 64 bit integers
+            Self::Usize => Value::U64(u64::deserialize(deserializer)?),
+            Self::I8 => Value::I8(i8::deserialize(deserializer)?),
+            Self::I16 => Value::I16(i16::deserialize(deserializer)?),
+            Self::I32 => Value::I32(i32::deserialize(deserializer)?),
+            Self::I64 => Value::I64(i64::deserialize(deserializer)?),
+            Self::I128 => Value::I128(i128::deserialize(deserializer)?),
+            Self::Isize => Value::I64(i64::deserialize(deserializer)?),
+            Self::F32 => Value::F32(f32::deserialize(deserializer)?),
+            Self::F64 => Value::F64(f64::deserialize(deserializer)?),
+        })
+    }
+}
+
+pub(crate) fn is_option(ty: &StableId) -> bool {
+    ty.crate_name == "core" && ty.name.starts_with("Option<")
+}
+
+fn option_some_ty<'a>(variants: &[VariantSignature<'a>]) -> Option<StableId<'a>> {
+    variants.iter().find_map(|variant| match variant {
+        VariantSignature::Tuple { name, fields } if *name == "Some" && fields.len() == 1 => {
+            Some(fields[0])
+        }
+        _ => None,
+    })
+}
+
+/// A value of a mod type, decoded using only its [`TypeSignature`]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Bool(bool),
+    Char(char),
+    String(String),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    I128(i128),
+    F32(f32),
+    F64(f64),
+    /// The fields of a struct, tuple struct, tuple or array, in declaration order
+    Fields(Vec<Value>),
+    /// The items of a list or set
+    Items(Vec<Value>),
+    Entries(Vec<(Value, Value)>),
+    Option(Option<Box<Value>>),
+    Variant {
+        index: u32,
+        fields: Vec<Value>,
+    },
+}
+
+/// Decodes a [`Value`] the same way `bevy_reflect`'s `TypedReflectDeserializer` would decode the actual type
+#[derive(Clone, Copy)]
+pub(crate) struct ValueSeed<'s, 'a> {
+    pub ty: StableId<'a>,
+    pub types: &'s Signatures<'a>,
+}
+
+impl<'s, 'a> ValueSeed<'s, 'a> {
+    fn with(&self, ty: StableId<'a>) -> Self {
+        Self {
+            ty,
+            types: self.types,
+        }
+    }
+
+    fn fields(&self, tys: Vec<StableId<'a>>) -> FieldsVisitor<'s, 'a> {
+        FieldsVisitor {
+            tys,
+            types: self.types,
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_, '_> {
+    type Value = Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let signature = match self.types.shape(&self.ty).map_err(de::Error::custom)? {
+            Shape::Primitive(primitive) => return primitive.deserialize(deserializer),
+            Shape::Signature(signature) => signature,
+        };
+
+        match signature {
+            TypeSignature::Struct { fields, .. } => {
+                let tys = fields.iter().map(|field| field.ty).collect();
+                deserializer
+                    .deserialize_struct("", names(fields.len()), self.fields(tys))
+                    .map(Value::Fields)
+            }
+            TypeSignature::TupleStruct { fields, .. } if fields.len() == 1 => deserializer
+                .deserialize_newtype_struct("", NewtypeVisitor(self.with(fields[0])))
+                .map(|value| Value::Fields(vec![value])),
+            TypeSignature::TupleStruct { fields, .. } => deserializer
+                .deserialize_tuple_struct("", fields.len(), self.fields(fields.clone()))
+                .map(Value::Fields),
+            TypeSignature::Tuple { fields, .. } => deserializer
+                .deserialize_tuple(fields.len(), self.fields(fields.clone()))
+                .map(Value::Fields),
+            TypeSignature::Array {
+                item_ty, capacity, ..
+            } => deserializer
+                .deserialize_tuple(*capacity, self.fields(vec![*item_ty; *capacity]))
+                .map(Value::Fields),
+            TypeSignature::List { item_ty, .. }
+            | TypeSignature::Set {
+                value_ty: item_ty, ..
+            } => deserializer
+                .deserialize_seq(ItemsVisitor(self.with(*item_ty)))
+                .map(Value::Items),
+            TypeSignature::Map {
+                key_ty, value_ty, ..
+            } => deserializer
+                .deserialize_map(EntriesVisitor {
+                    key: self.with(*key_ty),
+                    value: self.with(*value_ty),
+                })
+                .map(Value::Entries),
+            TypeSignature::Enum { ty, variants, .. } if is_option(ty) => {
+                let some_ty = option_some_ty(variants).ok_or_else(|| {
+                    de::Error::custom(format!("Option without a Some variant: {:?}", ty))
+                })?;
+                deserializer
+                    .deserialize_option(OptionVisitor(self.with(some_ty)))
+                    .map(Value::Option)
+            }
+            TypeSignature::Enum { variants, .. } => deserializer.deserialize_enum(
+                "",
+                names(variants.len()),
+                EnumVisitor {
+                    variants,
+                    types: self.types,
+                },
+            ),
+            TypeSignature::Opaque { ty, .. } => Err(de::Error::custom(
+                MigrationError::UnsupportedType(ty.to_owned()),
+            )),
+        }
+    }
+}
+
+struct FieldsVisitor<'s, 'a> {
+    tys: Vec<StableId<'a>>,
+    types: &'s Signatures<'a>,
+}
+
+impl<'de> Visitor<'de> for FieldsVisitor<'_, '_> {
+    type Value = Vec<Value>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of {} fields", self.tys.len())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(self.tys.len());
+        for (index, ty) in self.tys.iter().enumerate() {
+            let seed = ValueSeed {
+                ty: *ty,
+                types: self.types,
+            };
+            let value = seq
+                .next_element_seed(seed)?
+                .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+struct NewtypeVisitor<'s, 'a>(ValueSeed<'s, 'a>);
+
+impl<'de> Visitor<'de> for NewtypeVisitor<'_, '_> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a newtype struct")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.0.deserialize(deserializer)
+    }
+}
+
+struct ItemsVisitor<'s, 'a>(ValueSeed<'s, 'a>);
+
+impl<'de> Visitor<'de> for ItemsVisitor<'_, '_> {
+    type Value = Vec<Value>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(value) = seq.next_element_seed(self.0)? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+struct EntriesVisitor<'s, 'a> {
+    key: ValueSeed<'s, 'a>,
+    value: ValueSeed<'s, 'a>,
+}
+
+impl<'de> Visitor<'de> for EntriesVisitor<'_, '_> {
+    type Value = Vec<(Value, Value)>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or_default());
+        while let Some(entry) = map.next_entry_seed(self.key, self.value)? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+struct OptionVisitor<'s, 'a>(ValueSeed<'s, 'a>);
+
+impl<'de> Visitor<'de> for OptionVisitor<'_, '_> {
+    type Value = Option<Box<Value>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an option")
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(None)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.0
+            .deserialize(deserializer)
+            .map(|value| Some(Box::new(value)))
+    }
+}
+
+struct EnumVisitor<'s, 'a> {
+    variants: &'a [VariantSignature<'a>],
+    types: &'s Signatures<'a>,
+}
+
+impl<'de> Visitor<'de> for EnumVisitor<'_, '_> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an enum with {} variants", self.variants.len())
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let (index, variant) = data.variant::<u32>()?;
+        let signature = self.variants.get(index as usize).ok_or_else(|| {
+            de::Error::invalid_value(de::Unexpected::Unsigned(index as u64), &self)
+        })?;
+
+        let seed = |ty| ValueSeed {
+            ty,
+            types: self.types,
+        };
+        let fields = match signature {
+            VariantSignature::Unit { .. } => {
+                variant.unit_variant()?;
+                Vec::new()
+            }
+            VariantSignature::Tuple { fields, .. } if fields.len() == 1 => {
+                vec![variant.newtype_variant_seed(seed(fields[0]))?]
+            }
+            VariantSignature::Tuple { fields, .. } => variant.tuple_variant(
+                fields.len(),
+                FieldsVisitor {
+                    tys: fields.clone(),
+                    types: self.types,
+                },
+            )?,
+            VariantSignature::Struct { fields, .. } => variant.struct_variant(
+                names(fields.len()),
+                FieldsVisitor {
+                    tys: fields.iter().map(|field| field.ty).collect(),
+                    types: self.types,
+                },
+            )?,
+        };
+
+        Ok(Value::Variant { index, fields })
+    }
+}
+
+/// Encodes a [`Value`] the same way `bevy_reflect`'s `TypedReflectSerializer` would encode the actual type
+pub(crate) struct TypedValue<'s, 'a> {
+    pub value: &'s Value,
+    pub ty: StableId<'a>,
+    pub types: &'s Signatures<'a>,
+}
+
+impl<'s, 'a> TypedValue<'s, 'a> {
+    fn with(&self, value: &'s Value, ty: StableId<'a>) -> Self {
+        Self {
+            value,
+            ty,
+            types: self.types,
+        }
+    }
+}
+
+impl Serialize for TypedValue<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mismatch = || ser::Error::custom(format!("Value does not match type {:?}", self.ty));
+
+        let signature = match self.types.shape(&self.ty).map_err(ser::Error::custom)? {
+            Shape::Primitive(_) => {
+                return match self.value {
+                    Value::Bool(value) => serializer.serialize_bool(*value),
+                    Value::Char(value) => serializer.serialize_char(*value),
+                    Value::String(value) => serializer.serialize_str(value),
+                    Value::U8(value) => serializer.serialize_u8(*value),
+                    Value::U16(value) => serializer.serialize_u16(*value),
+                    Value::U32(value) => serializer.serialize_u32(*value),
+                    Value::U64(value) => serializer.serialize_u64(*value),
+                    Value::U128(value) => serializer.serialize_u128(*value),
+                    Value::I8(value) => serializer.serialize_i8(*value),
+                    Value::I16(value) => serializer.serialize_i16(*value),
+                    Value::I32(value) => serializer.serialize_i32(*value),
+                    Value::I64(value) => serializer.serialize_i64(*value),
+                    Value::I128(value) => serializer.serialize_i128(*value),
+                    Value::F32(value) => serializer.serialize_f32(*value),
+                    Value::F64(value) => serializer.serialize_f64(*value),
+                    _ => Err(mismatch()),
+                };
+            }
+            Shape::Signature(signature) => signature,
+        };
+
+        match (signature, self.value) {
+            (TypeSignature::Struct { fields, .. }, Value::Fields(values))
+                if fields.len() == values.len() =>
+            {
+                let mut state = serializer.serialize_struct("", values.len())?;
+                for (field, value) in fields.iter().zip(values) {
+                    state.serialize_field("", &self.with(value, field.ty))?;
+                }
+                state.end()
+            }
+            (TypeSignature::TupleStruct { fields, .. }, Value::Fields(values))
+                if fields.len() == 1 && values.len() == 1 =>
+            {
+                serializer.serialize_newtype_struct("", &self.with(&values[0], fields[0]))
+            }
+            (TypeSignature::TupleStruct { fields, .. }, Value::Fields(values))
+                if fields.len() == values.len() =>
+            {
+                let mut state = serializer.serialize_tuple_struct("", values.len())?;
+                for (ty, value) in fields.iter().zip(values) {
+                    state.serialize_field(&self.with(value, *ty))?;
+                }
+                state.end()
+            }
+            (TypeSignature::Tuple { fields, .. }, Value::Fields(values))
+                if fields.len() == values.len() =>
+            {
+                let mut state = serializer.serialize_tuple(values.len())?;
+                for (ty, value) in fields.iter().zip(values) {
+                    state.serialize_element(&self.with(value, *ty))?;
+                }
+                state.end()
+            }
+            (TypeSignature::Array { item_ty, .. }, Value::Fields(values)) => {
+                let mut state = serializer.serialize_tuple(values.len())?;
+                for value in values {
+                    state.serialize_element(&self.with(value, *item_ty))?;
+                }
+                state.end()
+            }
+            (
+                TypeSignature::List { item_ty, .. }
+                | TypeSignature::Set {
+                    value_ty: item_ty, ..
+                },
+                Value::Items(values),
+            ) => {
+                let mut state = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    state.serialize_element(&self.with(value, *item_ty))?;
+                }
+                state.end()
+            }
+            (
+                TypeSignature::Map {
+                    key_ty, value_ty, ..
+                },
+                Value::Entries(entries),
+            ) => {
+                let mut state = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    state
+                        .serialize_entry(&self.with(key, *key_ty), &self.with(value, *value_ty))?;
+                }
+                state.end()
+            }
+            (TypeSignature::Enum { ty, variants, .. }, Value::Option(value)) if is_option(ty) => {
+                match value {
+                    None => serializer.serialize_none(),
+                    Some(value) => {
+                        let some_ty = option_some_ty(variants).ok_or_else(mismatch)?;
+                        serializer.serialize_some(&self.with(value, some_ty))
+                    }
+                }
+            }
+            (
+                TypeSignature::Enum { variants, .. },
+                Value::Variant {
+                    index,
+                    fields: values,
+                },
+            ) => match variants.get(*index as usize) {
+                Some(VariantSignature::Unit { .. }) if values.is_empty() => {
+                    serializer.serialize_unit_variant("", *index, "")
+                }
+                Some(VariantSignature::Tuple { fields, .. })
+                    if fields.len() == 1 && values.len() == 1 =>
+                {
+                    serializer.serialize_newtype_variant(
+                        "",
+                        *index,
+                        "",
+                        &self.with(&values[0], fields[0]),
+                    )
+                }
+                Some(VariantSignature::Tuple { fields, .. }) if fields.len() == values.len() => {
+                    let mut state =
+                        serializer.serialize_tuple_variant("", *index, "", values.len())?;
+                    for (ty, value) in fields.iter().zip(values) {
+                        state.serialize_field(&self.with(value, *ty))?;
+                    }
+                    state.end()
+                }
+                Some(VariantSignature::Struct { fields, .. }) if fields.len() == values.len() => {
+                    let mut state =
+                        serializer.serialize_struct_variant("", *index, "", values.len())?;
+                    for (field, value) in fields.iter().zip(values) {
+                        state.serialize_field("", &self.with(value, field.ty))?;
+                    }
+                    state.end()
+                }
+                _ => Err(mismatch()),
+            },
+            _ => Err(mismatch()),
+        }
+    }
+}
@@ -1,77 +1,837 @@
-use std::{future::Future, path::Path};
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+};
 
-use bevy_app::{App, Plugin, Update};
-use bevy_ecs::system::ResMut;
+use bevy_app::{App, First, FixedUpdate, Last, Plugin, PostUpdate, PreUpdate, Update};
+use bevy_asset::{AssetApp, Handle};
+use bevy_ecs::{
+    event::EventWriter,
+    reflect::AppTypeRegistry,
+    schedule::IntoSystemConfigs,
+    system::{Res, ResMut},
+    world::World,
+};
 use bevy_ecs_macros::Resource;
 use bevy_tasks::{block_on, poll_once, AsyncComputeTaskPool, Task};
-use bevy_utils::tracing::{error, info, warn};
+use bevy_utils::{
+    tracing::{error, info, warn},
+    HashMap, HashSet,
+};
 
 mod schedule;
-pub(crate) use schedule::{Cycle, SchedulingError};
+pub use schedule::{
+    ConstraintDescription, Cycle, ModCycle, ModSchedule, ModSchedules, ModSystem, SchedulingError,
+    SetDescription,
+};
+
+mod migration;
+
+mod executor;
+pub use executor::{ModAccess, ModExecutor, ModScheduleApp, RunModSystems};
+
+mod health;
+pub use health::{ModHealth, QuarantinePolicy, SystemFailure};
+
+mod permissions;
+pub use permissions::{Capability, PermissionPolicy};
 
 mod loaded;
-use loaded::{LoadedMod, LoadedModResult};
+use loaded::LoadedModResult;
+pub use loaded::{
+    DotGraph, LoadedDependency, LoadedFeature, LoadedMod, LoadedSchedule, LoadedSchedules,
+    LoadedSystem, LoadingError, Trust, TrustPolicy,
+};
+
+mod events;
+pub use events::*;
+
+mod asset;
+pub use asset::{ModAsset, ModAssetError, ModAssetLoader};
+
+mod runtime;
+pub use runtime::{ExecutionBudget, MemoryGrowth, MemoryLimits, ModResources, SystemError};
 
 pub(crate) struct ModPlugin;
 
 impl Plugin for ModPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<Mods>()
-            .add_systems(Update, handle_loading_mods);
+        app.init_asset::<ModAsset>()
+            .init_asset_loader::<ModAssetLoader>()
+            .init_resource::<AppTypeRegistry>()
+            .init_resource::<Mods>()
+            .init_resource::<ModExecutor>()
+            .init_resource::<ExecutionBudget>()
+            .init_resource::<QuarantinePolicy>()
+            .init_resource::<PermissionPolicy>()
+            .add_event::<ModLoading>()
+            .add_event::<ModLoaded>()
+            .add_event::<ModLoadFailed>()
+            .add_event::<ModReloaded>()
+            .add_event::<ModUnloaded>()
+            .add_event::<ModQuarantined>()
+            .add_systems(First, executor::start_mod_frame.before(RunModSystems))
+            .add_systems(
+                Update,
+                (
+                    asset::handle_mod_assets,
+                    handle_loading_mods,
+                    handle_unloaded_mods,
+                    executor::run_start_schedule,
+                )
+                    .chain()
+                    .before(RunModSystems),
+            )
+            .add_mod_schedule::<common::First>(First)
+            .add_mod_schedule::<common::PreUpdate>(PreUpdate)
+            .add_mod_schedule::<common::Update>(Update)
+            .add_mod_schedule::<common::PostUpdate>(PostUpdate)
+            .add_mod_schedule::<common::Last>(Last)
+            .add_mod_schedule::<common::FixedUpdate>(FixedUpdate);
+    }
+}
+
+/// Identifies a mod for as long as it is loading or loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModHandle(usize);
+
+/// Where a mod is in its lifecycle, see [`Mods::status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModStatus {
+    Loading,
+    /// The mod finished loading, but some of the mods it depends on are not active yet
+    WaitingForDependencies,
+    Loaded,
+    /// The mod is still loaded but its systems don't run anymore, see [`ModHealth`]
+    Quarantined,
+    /// The mod could not be loaded, see [`ModLoadFailed`]
+    Failed,
+    /// This build replaced the build with the given handle, see [`ModReloaded`]
+    ///
+    /// The previous build may also have been waiting for its dependencies, in which case no event is sent.
+    Reloaded(ModHandle),
+    Unloaded,
+}
+
+enum Slot {
+    Loaded(Box<LoadedMod>),
+    /// Loaded, but not active until its dependencies are
+    Waiting(Box<LoadedMod>),
+    NotLoaded(ModStatus),
+}
+
+impl Slot {
+    fn status(&self) -> ModStatus {
+        match self {
+            Self::Waiting(_) => ModStatus::WaitingForDependencies,
+            Self::Loaded(loaded) if loaded.health().is_quarantined() => ModStatus::Quarantined,
+            Self::Loaded(_) => ModStatus::Loaded,
+            Self::NotLoaded(status) => *status,
+        }
+    }
+
+    fn get(&self) -> Option<&LoadedMod> {
+        match self {
+            Self::Loaded(loaded) => Some(loaded),
+            Self::Waiting(_) | Self::NotLoaded(_) => None,
+        }
+    }
+
+    fn get_mut(&mut self) -> Option<&mut LoadedMod> {
+        match self {
+            Self::Loaded(loaded) => Some(loaded),
+            Self::Waiting(_) | Self::NotLoaded(_) => None,
+        }
+    }
+
+    /// Takes the loaded or waiting mod out of this slot, leaving the given status behind
+    fn take(&mut self, status: ModStatus) -> Option<LoadedMod> {
+        match std::mem::replace(self, Self::NotLoaded(status)) {
+            Self::Loaded(loaded) | Self::Waiting(loaded) => Some(*loaded),
+            Self::NotLoaded(previous) => {
+                *self = Self::NotLoaded(previous);
+                None
+            }
+        }
     }
 }
 
 #[derive(Resource, Default)]
 pub struct Mods {
-    loading: Vec<Task<LoadedModResult>>,
-    loaded: Vec<Option<LoadedMod>>,
+    loading: Vec<(ModHandle, Task<LoadedModResult>)>,
+    /// Mods that started loading since the last [`ModLoading`] events were sent
+    started_loading: Vec<(ModHandle, PathBuf)>,
+    /// Mods whose loading was cancelled since the last [`ModLoadFailed`] events were sent
+    cancelled: Vec<ModHandle>,
+    /// Mods waiting for their asset to finish loading
+    pending_assets: Vec<(ModHandle, Handle<ModAsset>)>,
+    /// Assets mods were loaded from, the mods are reloaded when they are modified
+    watched_assets: Vec<(ModHandle, Handle<ModAsset>)>,
+    /// Indexed by [`ModHandle`], slots are never reused so stale handles can't refer to another mod
+    loaded: Vec<Slot>,
+    /// Active mods in the order they were activated, which always comes after the mods they depend on
+    order: Vec<ModHandle>,
+    /// The schedules of every active mod merged together
+    schedules: ModSchedules,
+    /// Mods that were unloaded, waiting for their entities to be despawned
+    unloaded: Vec<(ModHandle, LoadedMod)>,
+    /// Applied to mods when they start loading
+    memory_limits: MemoryLimits,
+    /// Verifies the signatures of mods as they load
+    trust_policy: TrustPolicy,
+    /// Labels of the schedules mods may add systems to, see [`ModScheduleApp`]
+    schedule_labels: HashSet<common::OwnedStableId>,
 }
 
 impl Mods {
-    pub fn load_from_path<P>(&mut self, path: P)
+    pub fn load_from_path<P>(&mut self, path: P) -> ModHandle
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref().to_owned();
-        self.enque_loading(LoadedMod::try_from_path(path))
+        let handle = self.enque_loading(LoadedMod::try_from_path(
+            path.clone(),
+            self.memory_limits,
+            self.trust_policy.clone(),
+            self.schedule_labels.clone(),
+        ));
+        self.started_loading.push((handle, path));
+        handle
+    }
+
+    /// Unloads a mod, or cancels its loading
+    ///
+    /// Its systems stop running immediately. The entities it spawned are despawned, and its resources and wasm
+    /// instance freed, during the next [`Update`]. Active mods depending on it are unloaded as well, each with
+    /// its own [`ModUnloaded`] event.
+    ///
+    /// Returns false if the handle does not refer to a loading or loaded mod.
+    pub fn unload(&mut self, handle: ModHandle) -> bool {
+        self.watched_assets
+            .retain(|(watched, _)| *watched != handle);
+
+        let loading = self.loading.len() + self.pending_assets.len();
+        self.loading.retain(|(loading, _)| *loading != handle);
+        self.pending_assets
+            .retain(|(pending, _)| *pending != handle);
+        if self.loading.len() + self.pending_assets.len() != loading {
+            info!("Mod loading cancelled: {:?}", handle);
+            self.loaded[handle.0] = Slot::NotLoaded(ModStatus::Unloaded);
+            self.cancelled.push(handle);
+            return true;
+        }
+
+        let slot = self.loaded.get_mut(handle.0);
+        let Some(loaded) = slot.and_then(|slot| slot.take(ModStatus::Unloaded)) else {
+            return false;
+        };
+        info!("Mod unloaded: {:?}", handle);
+        self.unloaded.push((handle, loaded));
+
+        // Mods can't run without the mods they depend on, which may in turn be depended on
+        loop {
+            let dependents: Vec<_> = self
+                .iter()
+                .filter(|(_, other)| self.check_dependencies(other).is_err())
+                .map(|(dependent, _)| dependent)
+                .collect();
+            if dependents.is_empty() {
+                break;
+            }
+            for dependent in dependents {
+                let loaded = self.loaded[dependent.0].take(ModStatus::Unloaded).unwrap();
+                info!("Mod unloaded: {:?}, it depends on {:?}", dependent, handle);
+                self.unloaded.push((dependent, loaded));
+            }
+        }
+
+        self.schedules = ModSchedules::try_build(self.iter_ordered())
+            .expect("removing a mod can not introduce a cycle");
+        true
+    }
+
+    pub fn memory_limits(&self) -> &MemoryLimits {
+        &self.memory_limits
+    }
+
+    /// Limits the memory of mods loaded from now on, mods already loaded keep their limits
+    pub fn set_memory_limits(&mut self, limits: MemoryLimits) {
+        self.memory_limits = limits;
+    }
+
+    pub fn trust_policy(&self) -> &TrustPolicy {
+        &self.trust_policy
+    }
+
+    /// Decides which mods may load from now on, mods already loaded stay loaded
+    pub fn set_trust_policy(&mut self, policy: TrustPolicy) {
+        self.trust_policy = policy;
+    }
+
+    pub fn get(&self, handle: ModHandle) -> Option<&LoadedMod> {
+        self.loaded.get(handle.0)?.get()
+    }
+
+    /// Returns `None` if the handle does not refer to a loaded mod
+    pub fn health(&self, handle: ModHandle) -> Option<&ModHealth> {
+        self.get(handle).map(LoadedMod::health)
+    }
+
+    /// Returns `None` if the handle was not returned by this [`Mods`]
+    pub fn status(&self, handle: ModHandle) -> Option<ModStatus> {
+        self.loaded.get(handle.0).map(Slot::status)
+    }
+
+    /// The schedules of every active mod, merged into one graph per schedule label
+    pub fn schedules(&self) -> &ModSchedules {
+        &self.schedules
+    }
+
+    /// Iterates over every active mod, in the order they were activated
+    pub fn iter_ordered(&self) -> impl Iterator<Item = (ModHandle, &LoadedMod)> {
+        self.order
+            .iter()
+            .filter_map(|handle| Some((*handle, self.get(*handle)?)))
+    }
+
+    /// Iterates over every loaded mod
+    pub fn iter(&self) -> impl Iterator<Item = (ModHandle, &LoadedMod)> {
+        self.loaded
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((ModHandle(index), slot.get()?)))
+    }
+
+    /// Iterates over the status of every mod that was ever loaded, including mods that failed or were unloaded
+    pub fn statuses(&self) -> impl Iterator<Item = (ModHandle, ModStatus)> + '_ {
+        self.loaded
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| (ModHandle(index), slot.status()))
+    }
+
+    /// Checks that every mod this mod depends on is active, with a matching version
+    fn check_dependencies(&self, loaded: &LoadedMod) -> Result<(), LoadingError> {
+        let active: Vec<_> = self.iter().map(|(_, other)| other).collect();
+        check_dependencies(loaded, &active)
     }
 
-    fn enque_loading(&mut self, future: impl Future<Output = LoadedModResult> + Send + 'static) {
+    /// Finds the active or waiting mod loaded from the same path as the given mod
+    fn find_by_path(&self, loaded: &LoadedMod) -> Option<ModHandle> {
+        let path = loaded.path.as_ref()?;
+        self.loaded
+            .iter()
+            .position(|slot| match slot {
+                Slot::Loaded(other) | Slot::Waiting(other) => other.path.as_ref() == Some(path),
+                Slot::NotLoaded(_) => false,
+            })
+            .map(ModHandle)
+    }
+
+    fn enque_loading(
+        &mut self,
+        future: impl Future<Output = LoadedModResult> + Send + 'static,
+    ) -> ModHandle {
+        let handle = self.reserve_slot();
+        self.spawn_loading(handle, future);
+        handle
+    }
+
+    /// Reserves a slot so the handle is known before loading finishes
+    fn reserve_slot(&mut self) -> ModHandle {
+        let handle = ModHandle(self.loaded.len());
+        self.loaded.push(Slot::NotLoaded(ModStatus::Loading));
+        handle
+    }
+
+    fn spawn_loading(
+        &mut self,
+        handle: ModHandle,
+        future: impl Future<Output = LoadedModResult> + Send + 'static,
+    ) {
         let thread_pool = AsyncComputeTaskPool::get();
         let task = thread_pool.spawn(future);
-        self.loading.push(task);
+        self.loading.push((handle, task));
+    }
+}
+
+/// Checks that every mod this mod depends on is among the given mods, with a matching version
+fn check_dependencies(loaded: &LoadedMod, active: &[&LoadedMod]) -> Result<(), LoadingError> {
+    for dependency in loaded.dependencies() {
+        let found = active
+            .iter()
+            .find(|other| other.name() == dependency.name)
+            .map(|other| other.version())
+            .ok_or_else(|| LoadingError::MissingDependency(dependency.name.clone()))?;
+
+        if !dependency.version.matches(found) {
+            return Err(LoadingError::IncompatibleDependency {
+                name: dependency.name.clone(),
+                required: dependency.version.clone(),
+                found: found.clone(),
+            });
+        }
     }
+    Ok(())
 }
 
-fn handle_loading_mods(mut mods: ResMut<Mods>) {
+fn handle_loading_mods(
+    mut mods: ResMut<Mods>,
+    policy: Res<PermissionPolicy>,
+    mut loading_events: EventWriter<ModLoading>,
+    mut loaded_events: EventWriter<ModLoaded>,
+    mut failed_events: EventWriter<ModLoadFailed>,
+    mut reloaded_events: EventWriter<ModReloaded>,
+) {
+    for (handle, path) in std::mem::take(&mut mods.started_loading) {
+        loading_events.send(ModLoading { handle, path });
+    }
+    for handle in std::mem::take(&mut mods.cancelled) {
+        failed_events.send(ModLoadFailed {
+            handle,
+            error: LoadingError::Cancelled,
+        });
+    }
+
     // Remove loaded tasks from loading
     let mut loaded = Vec::new();
-    mods.loading.retain_mut(|task| {
+    mods.loading.retain_mut(|(handle, task)| {
         if let Some(result) = block_on(poll_once(task)) {
-            loaded.push(result);
+            loaded.push((*handle, result));
             false
         } else {
             true
         }
     });
 
-    for loaded in loaded {
+    for (handle, loaded) in loaded {
         match loaded {
-            Ok(loaded) => {
-                let opt = Some(loaded);
-                if mods.loaded.contains(&opt) {
-                    warn!(
-                        "Mod already loaded: {:#?}. Skipping.",
-                        opt.as_ref().unwrap().manifest_hash
-                    );
+            Ok(mut loaded) => {
+                warn_ungranted_capabilities(&loaded, &policy);
+
+                let existing = mods
+                    .iter()
+                    .find(|(_, other)| **other == loaded)
+                    .map(|(handle, _)| handle);
+                if let Some(existing) = existing {
+                    warn!("Mod already loaded: {:#?}. Skipping.", loaded.manifest_hash);
+                    mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Failed);
+                    failed_events.send(ModLoadFailed {
+                        handle,
+                        error: LoadingError::AlreadyLoaded(existing),
+                    });
+                } else if let Some(previous) = mods.find_by_path(&loaded) {
+                    if let Slot::Waiting(_) = mods.loaded[previous.0] {
+                        // The previous build never started, so the new one waits for the dependencies in its place
+                        info!(
+                            "Mod replaced while waiting for its dependencies: {:?}",
+                            previous
+                        );
+                        mods.loaded[previous.0] = Slot::Waiting(Box::new(loaded));
+                        mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Reloaded(previous));
+                        continue;
+                    }
+
+                    // The new build has to satisfy the mods depending on the previous one, and find its own dependencies
+                    let active: Vec<_> = mods
+                        .iter()
+                        .map(|(handle, other)| if handle == previous { &loaded } else { other })
+                        .collect();
+                    let dependencies = check_dependencies(&loaded, &active).and_then(|()| {
+                        mods.iter()
+                            .filter(|(handle, _)| *handle != previous)
+                            .try_for_each(|(_, dependent)| {
+                                check_dependencies(dependent, &active).map_err(|error| {
+                                    LoadingError::BreaksDependent {
+                                        name: dependent.name().to_owned(),
+                                        error: Box::new(error),
+                                    }
+                                })
+                            })
+                    });
+                    if let Err(error) = dependencies {
+                        error!("Failed to reload mod {}: {:?}", loaded.name(), error);
+                        mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Failed);
+                        failed_events.send(ModLoadFailed { handle, error });
+                        continue;
+                    }
+
+                    // The new build may order itself differently relative to other mods
+                    let schedules =
+                        ModSchedules::try_build(mods.iter_ordered().map(|(handle, other)| {
+                            (handle, if handle == previous { &loaded } else { other })
+                        }));
+                    let schedules = match schedules {
+                        Ok(schedules) => schedules,
+                        Err(error) => {
+                            error!("Failed to reload mod {}: {}", loaded.name(), error);
+                            mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Failed);
+                            failed_events.send(ModLoadFailed {
+                                handle,
+                                error: LoadingError::SchedulingError(error),
+                            });
+                            continue;
+                        }
+                    };
+
+                    // A new build of a mod that is already loaded replaces it, keeping its handle
+                    mods.schedules = schedules;
+                    let previous_build = mods.loaded[previous.0].take(ModStatus::Loading).unwrap();
+                    loaded.reload_from(previous_build);
+                    info!("Mod reloaded: {:?}", previous);
+                    reloaded_events.send(ModReloaded {
+                        handle: previous,
+                        name: loaded.name().to_owned(),
+                    });
+                    mods.loaded[previous.0] = Slot::Loaded(Box::new(loaded));
+                    mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Reloaded(previous));
                 } else {
-                    info!("Mod loaded: {:#?}", opt.as_ref().unwrap());
-                    mods.loaded.push(opt);
+                    mods.loaded[handle.0] = Slot::Waiting(Box::new(loaded));
+                }
+            }
+            Err(error) => {
+                error!("Failed to load mod: {:?}", error);
+                mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Failed);
+                failed_events.send(ModLoadFailed { handle, error });
+            }
+        }
+    }
+
+    activate_waiting_mods(&mut mods, &mut loaded_events, &mut failed_events);
+}
+
+/// Activates the mods whose dependencies are all active, in dependency order
+///
+/// Mods whose dependencies are incompatible fail to load. Mods whose dependencies are missing keep waiting until
+/// no other mod is loading anymore, then fail as well. Mods depending on each other fail right away, since none of
+/// them can start first.
+fn activate_waiting_mods(
+    mods: &mut Mods,
+    loaded_events: &mut EventWriter<ModLoaded>,
+    failed_events: &mut EventWriter<ModLoadFailed>,
+) {
+    let waiting = |mods: &Mods| -> Vec<ModHandle> {
+        (0..mods.loaded.len())
+            .map(ModHandle)
+            .filter(|handle| matches!(mods.loaded[handle.0], Slot::Waiting(_)))
+            .collect()
+    };
+
+    // Activating a mod may satisfy the dependencies of others
+    loop {
+        let ready: Vec<_> = waiting(mods)
+            .into_iter()
+            .filter(|handle| {
+                let Slot::Waiting(loaded) = &mods.loaded[handle.0] else {
+                    unreachable!();
+                };
+                mods.check_dependencies(loaded).is_ok()
+            })
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+
+        for handle in ready {
+            let loaded = mods.loaded[handle.0].take(ModStatus::Loading).unwrap();
+
+            // Its systems may form cycles with the systems of the mods already active
+            let schedules = ModSchedules::try_build(mods.iter_ordered().chain([(handle, &loaded)]));
+            let schedules = match schedules {
+                Ok(schedules) => schedules,
+                Err(error) => {
+                    error!("Mod {} can not start: {}", loaded.name(), error);
+                    mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Failed);
+                    failed_events.send(ModLoadFailed {
+                        handle,
+                        error: LoadingError::SchedulingError(error),
+                    });
+                    continue;
                 }
+            };
+            mods.schedules = schedules;
+
+            info!("Mod loaded: {:#?}", loaded);
+            loaded_events.send(ModLoaded {
+                handle,
+                name: loaded.name().to_owned(),
+            });
+            mods.loaded[handle.0] = Slot::Loaded(Box::new(loaded));
+            mods.order.push(handle);
+        }
+    }
+
+    // Errors are collected first, so every mod of a cycle sees the others still waiting
+    let still_loading = !mods.loading.is_empty() || !mods.pending_assets.is_empty();
+    let mut failed = Vec::new();
+    for handle in waiting(mods) {
+        let Slot::Waiting(loaded) = &mods.loaded[handle.0] else {
+            unreachable!();
+        };
+        let error = match mods.check_dependencies(loaded) {
+            Ok(()) => continue,
+            Err(LoadingError::MissingDependency(name)) => match dependency_cycle(mods, loaded) {
+                Some(cycle) => LoadingError::DependencyCycle(cycle),
+                None if still_loading => continue,
+                None => LoadingError::MissingDependency(name),
+            },
+            Err(error) => error,
+        };
+        error!("Mod {} can not start: {:?}", loaded.name(), error);
+        failed.push((handle, error));
+    }
+    for (handle, error) in failed {
+        mods.loaded[handle.0] = Slot::NotLoaded(ModStatus::Failed);
+        failed_events.send(ModLoadFailed { handle, error });
+    }
+}
+
+/// The names of the waiting mods a waiting mod depends on, directly or not, if they lead back to it
+///
+/// Such mods wait for each other forever. The cycle starts with the name of the given mod.
+fn dependency_cycle(mods: &Mods, loaded: &LoadedMod) -> Option<Vec<String>> {
+    fn visit<'a>(
+        current: &'a LoadedMod,
+        start: &str,
+        waiting: &HashMap<&str, &'a LoadedMod>,
+        visited: &mut HashSet<&'a str>,
+        cycle: &mut Vec<String>,
+    ) -> bool {
+        for dependency in current.dependencies() {
+            if dependency.name == start {
+                return true;
             }
-            Err(err) => {
-                error!("Failed to load mod: {:?}", err);
+            let Some(&next) = waiting.get(dependency.name.as_str()) else {
+                continue;
+            };
+            if !visited.insert(next.name()) {
+                continue;
             }
+            cycle.push(next.name().to_owned());
+            if visit(next, start, waiting, visited, cycle) {
+                return true;
+            }
+            cycle.pop();
+        }
+        false
+    }
+
+    let waiting: HashMap<&str, &LoadedMod> = mods
+        .loaded
+        .iter()
+        .filter_map(|slot| match slot {
+            Slot::Waiting(waiting) => Some((waiting.name(), &**waiting)),
+            _ => None,
+        })
+        .collect();
+    let mut cycle = vec![loaded.name().to_owned()];
+    let found = visit(
+        loaded,
+        loaded.name(),
+        &waiting,
+        &mut HashSet::new(),
+        &mut cycle,
+    );
+    found.then_some(cycle)
+}
+
+/// Mods are loaded even if they request more than they are granted, but will trap when they try to use it
+///
+/// Queries accessing ungranted components still work, but skip the entities the mod did not spawn.
+fn warn_ungranted_capabilities(loaded: &LoadedMod, policy: &PermissionPolicy) {
+    let grants = policy.grants_for(loaded);
+    for capability in loaded.requested_capabilities() {
+        if grants.allows(&capability) {
+            continue;
+        }
+        match capability {
+            Capability::ReadComponent(_) | Capability::WriteComponent(_) => warn!(
+                "Mod {} requests {:?}, its queries will only match the entities it spawned",
+                loaded.name(),
+                capability
+            ),
+            _ => warn!(
+                "Mod {} requests {:?}, which the permission policy does not grant",
+                loaded.name(),
+                capability
+            ),
+        }
+    }
+}
+
+/// Despawns the entities of unloaded mods, then frees their resources and wasm instances
+fn handle_unloaded_mods(world: &mut World) {
+    let unloaded = std::mem::take(&mut world.resource_mut::<Mods>().unloaded);
+    for (handle, loaded) in unloaded {
+        let name = loaded.name().to_owned();
+        loaded.despawn_entities(world);
+        world.send_event(ModUnloaded { handle, name });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{event::Events, system::RunSystemOnce};
+    use common::{Dependency, FeatureDescriptor, FileHash, ModManifest};
+
+    use super::*;
+
+    /// A mod without systems, which only needs its memory and the function running systems
+    const WAT: &str = r#"
+        (module
+            (import "env" "memory" (memory 1))
+            (func (export "bevy_harmonize_run_system") (param i64)))
+    "#;
+
+    fn build(name: &str, version: &str, dependencies: &[(&str, &str)]) -> LoadedMod {
+        let manifest = ModManifest {
+            wasm_hash: FileHash::empty(),
+            api_version: "0.0.0",
+            version,
+            dependencies: dependencies
+                .iter()
+                .map(|&(name, version)| Dependency { name, version })
+                .collect(),
+            types: Vec::new(),
+            features: vec![FeatureDescriptor {
+                name,
+                resources: Vec::new(),
+                schedules: Vec::new(),
+            }],
+        };
+        LoadedMod::from_wat(manifest, WAT, &MemoryLimits::default())
+    }
+
+    /// Adds a mod that finished loading, it still has to wait for its dependencies
+    fn finish_loading(mods: &mut Mods, loaded: LoadedMod) -> ModHandle {
+        let handle = mods.reserve_slot();
+        mods.loaded[handle.0] = Slot::Waiting(Box::new(loaded));
+        handle
+    }
+
+    /// Activates the waiting mods whose dependencies are active, returning the mods that failed to load
+    fn activate(mods: &mut Mods) -> Vec<ModLoadFailed> {
+        let mut world = World::new();
+        world.init_resource::<Events<ModLoaded>>();
+        world.init_resource::<Events<ModLoadFailed>>();
+        world.insert_resource(std::mem::take(mods));
+        world
+            .run_system_once(
+                |mut mods: ResMut<Mods>,
+                 mut loaded_events: EventWriter<ModLoaded>,
+                 mut failed_events: EventWriter<ModLoadFailed>| {
+                    activate_waiting_mods(&mut mods, &mut loaded_events, &mut failed_events);
+                },
+            )
+            .unwrap();
+        *mods = world.remove_resource::<Mods>().unwrap();
+        world
+            .resource_mut::<Events<ModLoadFailed>>()
+            .drain()
+            .collect()
+    }
+
+    #[test]
+    fn mods_wait_for_dependencies_that_are_still_loading() {
+        let mut mods = Mods::default();
+        let physics = mods.reserve_slot();
+        mods.pending_assets.push((physics, Handle::default()));
+        let audio = finish_loading(&mut mods, build("audio", "0.1.0", &[("physics", "^1.0")]));
+
+        assert!(activate(&mut mods).is_empty());
+        assert_eq!(mods.status(audio), Some(ModStatus::WaitingForDependencies));
+        assert!(mods.get(audio).is_none());
+
+        // Once the dependency loads, both start in dependency order
+        mods.pending_assets.clear();
+        mods.loaded[physics.0] = Slot::Waiting(Box::new(build("physics", "1.2.0", &[])));
+        assert!(activate(&mut mods).is_empty());
+        assert_eq!(mods.status(physics), Some(ModStatus::Loaded));
+        assert_eq!(mods.status(audio), Some(ModStatus::Loaded));
+        assert_eq!(mods.order, [physics, audio]);
+    }
+
+    #[test]
+    fn missing_dependencies_fail_once_nothing_is_loading() {
+        let mut mods = Mods::default();
+        let audio = finish_loading(&mut mods, build("audio", "0.1.0", &[("physics", "^1.0")]));
+
+        let failed = activate(&mut mods);
+        let [ModLoadFailed { handle, error }] = failed.as_slice() else {
+            panic!("expected a single failure, got {:?}", failed);
+        };
+        assert_eq!(*handle, audio);
+        assert!(
+            matches!(error, LoadingError::MissingDependency(name) if name == "physics"),
+            "{:?}",
+            error
+        );
+        assert_eq!(mods.status(audio), Some(ModStatus::Failed));
+    }
+
+    #[test]
+    fn dependencies_must_match_the_version_requirement() {
+        let mut mods = Mods::default();
+        let physics = finish_loading(&mut mods, build("physics", "2.0.0", &[]));
+        let audio = finish_loading(&mut mods, build("audio", "0.1.0", &[("physics", "^1.0")]));
+
+        let failed = activate(&mut mods);
+        let [ModLoadFailed { handle, error }] = failed.as_slice() else {
+            panic!("expected a single failure, got {:?}", failed);
+        };
+        assert_eq!(*handle, audio);
+        let LoadingError::IncompatibleDependency {
+            name,
+            required,
+            found,
+        } = error
+        else {
+            panic!("expected an incompatible dependency, got {:?}", error);
+        };
+        assert_eq!(name, "physics");
+        assert_eq!(*required, semver::VersionReq::parse("^1.0").unwrap());
+        assert_eq!(*found, semver::Version::parse("2.0.0").unwrap());
+        assert_eq!(mods.status(physics), Some(ModStatus::Loaded));
+        assert_eq!(mods.status(audio), Some(ModStatus::Failed));
+    }
+
+    #[test]
+    fn unloading_a_mod_unloads_its_dependents() {
+        let mut mods = Mods::default();
+        let physics = finish_loading(&mut mods, build("physics", "1.0.0", &[]));
+        let audio = finish_loading(&mut mods, build("audio", "0.1.0", &[("physics", "^1.0")]));
+        let ui = finish_loading(&mut mods, build("ui", "0.1.0", &[("audio", "*")]));
+        let input = finish_loading(&mut mods, build("input", "0.1.0", &[]));
+        assert!(activate(&mut mods).is_empty());
+
+        assert!(mods.unload(physics));
+
+        // Dependents of dependents are unloaded too, unrelated mods stay
+        for handle in [physics, audio, ui] {
+            assert_eq!(mods.status(handle), Some(ModStatus::Unloaded));
         }
+        assert_eq!(mods.status(input), Some(ModStatus::Loaded));
+        let mut unloaded: Vec<_> = mods.unloaded.iter().map(|(handle, _)| *handle).collect();
+        unloaded.sort();
+        assert_eq!(unloaded, [physics, audio, ui]);
+        assert_eq!(
+            mods.iter_ordered()
+                .map(|(handle, _)| handle)
+                .collect::<Vec<_>>(),
+            [input]
+        );
+    }
+
+    #[test]
+    fn new_builds_are_found_while_waiting_for_dependencies() {
+        let mut mods = Mods::default();
+        let mut waiting = build("audio", "0.1.0", &[("physics", "^1.0")]);
+        waiting.path = Some(PathBuf::from("mods/audio.hmod"));
+        let audio = finish_loading(&mut mods, waiting);
+
+        let mut new_build = build("audio", "0.2.0", &[("physics", "^1.0")]);
+        assert_eq!(mods.find_by_path(&new_build), None);
+        new_build.path = Some(PathBuf::from("mods/audio.hmod"));
+        assert_eq!(mods.find_by_path(&new_build), Some(audio));
     }
 }
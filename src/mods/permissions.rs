@@ -0,0 +1,239 @@
+use bevy_ecs_macros::Resource;
+use bevy_utils::{HashMap, HashSet};
+use common::{OwnedParam, OwnedStableId};
+
+use super::LoadedMod;
+
+/// Something a mod may do through the `bevy_harmonize` imports, see [`PermissionPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Spawn entities and insert components into them, requested by systems taking `Commands`
+    ///
+    /// Commands can only insert components into the entities the mod spawned, which it builds like any value it
+    /// creates, so this does not require [`Self::WriteComponent`]. Changing components of entities spawned by others
+    /// always goes through a query, and requires [`Self::WriteComponent`].
+    SpawnEntities,
+    /// Read a resource, requested by systems taking `Res`
+    ///
+    /// Resources the mod does not declare itself are read from the world, the resource type must be registered
+    /// with `#[reflect(Resource)]` on the host.
+    ReadResource(OwnedStableId),
+    /// Read and write a resource, requested by systems taking `ResMut`
+    ///
+    /// Writes to resources of the host are applied to the world once the system's batch is done.
+    WriteResource(OwnedStableId),
+    /// Read a component of entities the mod did not spawn, requested by systems querying `&T`
+    ReadComponent(OwnedStableId),
+    /// Read and write a component of entities the mod did not spawn, requested by systems querying `&mut T`
+    WriteComponent(OwnedStableId),
+}
+
+impl Capability {
+    /// The capabilities a system needs for one of its parameters
+    pub fn from_param(param: &OwnedParam) -> Vec<Self> {
+        match param {
+            OwnedParam::Command => vec![Self::SpawnEntities],
+            OwnedParam::Res { mutable: false, id } => vec![Self::ReadResource(id.clone())],
+            OwnedParam::Res { mutable: true, id } => vec![Self::WriteResource(id.clone())],
+            OwnedParam::Query(query) => query
+                .components
+                .iter()
+                .map(|component| match component.mutable {
+                    false => Self::ReadComponent(component.id.clone()),
+                    true => Self::WriteComponent(component.id.clone()),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Decides what mods are allowed to do
+///
+/// Mods request capabilities through the parameters of their systems, see [`LoadedMod::requested_capabilities`].
+/// Every call a mod makes into the host is checked against this policy, and a mod doing something it was not
+/// granted traps.
+#[derive(Resource, Debug, Clone)]
+pub struct PermissionPolicy {
+    /// Whether mods may read and write the resources they declare in their schema
+    pub own_resources: bool,
+    /// Whether the queries of mods match the entities they spawned, whatever components they access
+    ///
+    /// Entities spawned by others only match if the mod was granted [`Capability::ReadComponent`] or
+    /// [`Capability::WriteComponent`] for every component its query accesses.
+    pub own_entities: bool,
+    /// Granted to every mod
+    pub granted: HashSet<Capability>,
+    /// Granted to the mod with the given name, on top of [`Self::granted`]
+    pub granted_to: HashMap<String, HashSet<Capability>>,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        Self {
+            own_resources: true,
+            own_entities: true,
+            granted: HashSet::from_iter([Capability::SpawnEntities]),
+            granted_to: HashMap::new(),
+        }
+    }
+}
+
+impl PermissionPolicy {
+    /// A policy granting nothing, not even access to the mods' own resources and entities
+    pub fn deny_all() -> Self {
+        Self {
+            own_resources: false,
+            own_entities: false,
+            granted: HashSet::new(),
+            granted_to: HashMap::new(),
+        }
+    }
+
+    /// Grants a capability to every mod
+    pub fn grant(&mut self, capability: Capability) -> &mut Self {
+        self.granted.insert(capability);
+        self
+    }
+
+    /// Grants a capability to the mod with the given name
+    pub fn grant_to(&mut self, name: impl Into<String>, capability: Capability) -> &mut Self {
+        self.granted_to
+            .entry(name.into())
+            .or_default()
+            .insert(capability);
+        self
+    }
+
+    pub fn allows(&self, loaded: &LoadedMod, capability: &Capability) -> bool {
+        self.grants_for(loaded).allows(capability)
+    }
+
+    /// Resolves the capabilities granted to a mod
+    pub(crate) fn grants_for(&self, loaded: &LoadedMod) -> Grants {
+        let mut capabilities = self.granted.clone();
+        if let Some(granted) = self.granted_to.get(loaded.name()) {
+            capabilities.extend(granted.iter().cloned());
+        }
+        if self.own_resources {
+            capabilities.extend(
+                loaded
+                    .resources()
+                    .iter()
+                    .map(|(id, _)| Capability::WriteResource(id.clone())),
+            );
+        }
+        Grants::new(capabilities, self.own_entities)
+    }
+}
+
+/// The capabilities granted to a single mod, checked by the import functions
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Grants {
+    capabilities: HashSet<Capability>,
+    /// See [`PermissionPolicy::own_entities`]
+    pub own_entities: bool,
+}
+
+impl Grants {
+    pub fn new(capabilities: impl IntoIterator<Item = Capability>, own_entities: bool) -> Self {
+        Self {
+            capabilities: capabilities.into_iter().collect(),
+            own_entities,
+        }
+    }
+
+    pub fn allows(&self, capability: &Capability) -> bool {
+        match capability {
+            // Writing a resource or component requires reading it too
+            Capability::ReadResource(id) => {
+                self.capabilities.contains(capability)
+                    || self
+                        .capabilities
+                        .contains(&Capability::WriteResource(id.clone()))
+            }
+            Capability::ReadComponent(id) => {
+                self.capabilities.contains(capability)
+                    || self
+                        .capabilities
+                        .contains(&Capability::WriteComponent(id.clone()))
+            }
+            _ => self.capabilities.contains(capability),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(name: &str) -> OwnedStableId {
+        OwnedStableId {
+            crate_name: "host".to_owned(),
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn writing_implies_reading() {
+        let grants = Grants::new(
+            [
+                Capability::WriteResource(id("Score")),
+                Capability::WriteComponent(id("Transform")),
+                Capability::ReadComponent(id("Health")),
+            ],
+            false,
+        );
+
+        assert!(grants.allows(&Capability::ReadResource(id("Score"))));
+        assert!(grants.allows(&Capability::WriteResource(id("Score"))));
+        assert!(grants.allows(&Capability::ReadComponent(id("Transform"))));
+        assert!(grants.allows(&Capability::WriteComponent(id("Transform"))));
+
+        // Reading does not imply writing
+        assert!(grants.allows(&Capability::ReadComponent(id("Health"))));
+        assert!(!grants.allows(&Capability::WriteComponent(id("Health"))));
+
+        // Capabilities are granted per type
+        assert!(!grants.allows(&Capability::ReadResource(id("Time"))));
+        assert!(!grants.allows(&Capability::ReadComponent(id("Score"))));
+        assert!(!grants.allows(&Capability::SpawnEntities));
+    }
+
+    #[test]
+    fn mods_only_access_their_own_data_by_default() {
+        let policy = PermissionPolicy::default();
+        assert!(policy.own_resources);
+        assert!(policy.own_entities);
+        assert_eq!(
+            policy.granted,
+            HashSet::from_iter([Capability::SpawnEntities])
+        );
+        assert!(policy.granted_to.is_empty());
+
+        let policy = PermissionPolicy::deny_all();
+        assert!(!policy.own_resources);
+        assert!(!policy.own_entities);
+        assert!(policy.granted.is_empty());
+        assert!(policy.granted_to.is_empty());
+    }
+
+    #[test]
+    fn capabilities_are_granted_to_every_mod_or_by_name() {
+        let mut policy = PermissionPolicy::deny_all();
+        policy
+            .grant(Capability::ReadResource(id("Time")))
+            .grant_to("physics", Capability::WriteComponent(id("Transform")));
+
+        assert_eq!(
+            policy.granted,
+            HashSet::from_iter([Capability::ReadResource(id("Time"))])
+        );
+        assert_eq!(
+            policy.granted_to.get("physics"),
+            Some(&HashSet::from_iter([Capability::WriteComponent(id(
+                "Transform"
+            ))]))
+        );
+        assert!(!policy.granted_to.contains_key("audio"));
+    }
+}
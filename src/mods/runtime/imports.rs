@@ -0,0 +1,446 @@
+use std::{
+    error::Error,
+    fmt,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use bevy_utils::HashSet;
+use common::{OwnedQueryDescriptor, OwnedStableId};
+use wasmer::{FunctionEnv, Memory, MemoryView, Store};
+
+use super::{
+    super::permissions::{Capability, Grants},
+    HostResources, ModCommand, ModResources, QuerySnapshot,
+};
+
+/// Host state shared with the import functions of a single mod instance
+pub(super) struct State {
+    pub memory: Memory,
+    /// Changes to the world requested by the mod, applied once the call into the mod returns
+    pub commands: Vec<ModCommand>,
+    /// The last entity id handed out to the mod, shared by every instance of the mod
+    pub entity_counter: Arc<AtomicU32>,
+    /// Stable ids indexed by the local type id handed out to the mod
+    type_ids: Vec<OwnedStableId>,
+    /// Queries indexed by the local query id handed out to the mod
+    query_ids: Vec<OwnedQueryDescriptor>,
+    /// Lent by the [`super::ModRuntime`] for the duration of each batch of systems
+    pub resources: ModResources,
+    /// The resources of the mod written during the current batch
+    pub written_resources: HashSet<OwnedStableId>,
+    /// Prepared by the [`super::ModRuntime`] before each batch of systems
+    pub host_resources: HostResources,
+    /// Prepared by the [`super::ModRuntime`] before each batch of systems
+    pub queries: QuerySnapshot,
+    /// Bytes waiting to be copied into the mod's memory by `write_buffer_to`
+    buffer: Vec<u8>,
+    /// What the mod is allowed to do, refreshed every frame from the [`super::super::PermissionPolicy`]
+    pub grants: Grants,
+}
+
+impl State {
+    pub fn new(memory: Memory, entity_counter: Arc<AtomicU32>) -> Self {
+        Self {
+            memory,
+            commands: Vec::new(),
+            entity_counter,
+            type_ids: Vec::new(),
+            query_ids: Vec::new(),
+            resources: ModResources::default(),
+            written_resources: HashSet::new(),
+            host_resources: HostResources::default(),
+            queries: QuerySnapshot::default(),
+            buffer: Vec::new(),
+            grants: Grants::default(),
+        }
+    }
+
+    fn check(&self, capability: Capability) -> Result<(), ImportError> {
+        if self.grants.allows(&capability) {
+            Ok(())
+        } else {
+            Err(ImportError::PermissionDenied(capability))
+        }
+    }
+
+    fn stable_id(&self, local_type_id: u32) -> Result<&OwnedStableId, ImportError> {
+        self.type_ids
+            .get(local_type_id as usize)
+            .ok_or(ImportError::UnknownLocalTypeId(local_type_id))
+    }
+
+    fn query(&self, local_query_id: u32) -> Result<&OwnedQueryDescriptor, ImportError> {
+        self.query_ids
+            .get(local_query_id as usize)
+            .ok_or(ImportError::UnknownLocalQueryId(local_query_id))
+    }
+}
+
+type Context<'a> = wasmer::FunctionEnvMut<'a, State>;
+
+/// Creates the import object for the `bevy_harmonize` wasm import module declared in `bevy_harmonize_api`
+pub(super) fn imports(
+    store: &mut Store,
+    env: &FunctionEnv<State>,
+    memory: Memory,
+) -> wasmer::Imports {
+    wasmer::imports! {
+        "bevy_harmonize" => {
+            "spawn_empty" => wasmer::Function::new_typed_with_env(store, env, spawn_empty),
+            "set_component" => wasmer::Function::new_typed_with_env(store, env, set_component),
+            "get_local_type_id" => wasmer::Function::new_typed_with_env(store, env, get_local_type_id),
+            "set_resource" => wasmer::Function::new_typed_with_env(store, env, set_resource),
+            "buffer_resource" => wasmer::Function::new_typed_with_env(store, env, buffer_resource),
+            "get_local_query_id" => wasmer::Function::new_typed_with_env(store, env, get_local_query_id),
+            "buffer_query" => wasmer::Function::new_typed_with_env(store, env, buffer_query),
+            "set_query_component" => wasmer::Function::new_typed_with_env(store, env, set_query_component),
+            "write_buffer_to" => wasmer::Function::new_typed_with_env(store, env, write_buffer_to),
+        },
+        "env" => {
+            "memory" => memory,
+        },
+    }
+}
+
+fn spawn_empty(mut context: Context) -> Result<u32, ImportError> {
+    let state = context.data_mut();
+    state.check(Capability::SpawnEntities)?;
+
+    let entity = state.entity_counter.fetch_add(1, Ordering::AcqRel) + 1;
+    state.commands.push(ModCommand::Spawn(entity));
+    Ok(entity)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn set_component(
+    mut context: Context,
+    entity: u32,
+    type_short_name_ptr: u32,
+    type_short_name_len: u32,
+    type_crate_name_ptr: u32,
+    type_crate_name_len: u32,
+    buffer_ptr: u32,
+    buffer_len: u32,
+) -> Result<(), ImportError> {
+    let (data, store) = context.data_and_store_mut();
+    // Only entities spawned by the mod can be targeted, see `Capability::SpawnEntities`
+    data.check(Capability::SpawnEntities)?;
+
+    let (id, value) = {
+        let view = data.memory.view(&store);
+        let id = read_stable_id(
+            &view,
+            type_short_name_ptr,
+            type_short_name_len,
+            type_crate_name_ptr,
+            type_crate_name_len,
+        )?;
+        let value = read_bytes(&view, buffer_ptr, buffer_len)?;
+        (id, value)
+    };
+
+    data.commands
+        .push(ModCommand::InsertComponent { entity, id, value });
+    Ok(())
+}
+
+fn get_local_type_id(
+    mut context: Context,
+    type_short_name_ptr: u32,
+    type_short_name_len: u32,
+    type_crate_name_ptr: u32,
+    type_crate_name_len: u32,
+) -> Result<u32, ImportError> {
+    let (data, store) = context.data_and_store_mut();
+    let id = read_stable_id(
+        &data.memory.view(&store),
+        type_short_name_ptr,
+        type_short_name_len,
+        type_crate_name_ptr,
+        type_crate_name_len,
+    )?;
+
+    let local_type_id = match data.type_ids.iter().position(|existing| *existing == id) {
+        Some(index) => index,
+        None => {
+            data.type_ids.push(id);
+            data.type_ids.len() - 1
+        }
+    };
+    Ok(local_type_id as u32)
+}
+
+fn set_resource(
+    mut context: Context,
+    local_type_id: u32,
+    buffer_ptr: u32,
+    buffer_len: u32,
+) -> Result<(), ImportError> {
+    let (data, store) = context.data_and_store_mut();
+    let id = data.stable_id(local_type_id)?.clone();
+    data.check(Capability::WriteResource(id.clone()))?;
+
+    let value = read_bytes(&data.memory.view(&store), buffer_ptr, buffer_len)?;
+    // Resources the mod did not declare belong to the host, which only has those it fetched for this batch
+    if data.resources.get(&id).is_some() {
+        data.resources.insert(id.clone(), value);
+        data.written_resources.insert(id);
+    } else if data.host_resources.write(&id, value.clone()) {
+        data.commands.push(ModCommand::WriteResource { id, value });
+    } else {
+        return Err(ImportError::ResourceNotFound(id));
+    }
+    Ok(())
+}
+
+fn buffer_resource(mut context: Context, local_type_id: u32) -> Result<u32, ImportError> {
+    let state = context.data_mut();
+    let id = state.stable_id(local_type_id)?;
+    state.check(Capability::ReadResource(id.clone()))?;
+
+    let value = state
+        .resources
+        .get(id)
+        .or_else(|| state.host_resources.get(id))
+        .ok_or_else(|| ImportError::ResourceNotFound(id.clone()))?
+        .to_vec();
+
+    let len = value.len() as u32;
+    state.buffer = value;
+    Ok(len)
+}
+
+fn get_local_query_id(
+    mut context: Context,
+    encoded_query_ptr: u32,
+    encoded_query_len: u32,
+) -> Result<u32, ImportError> {
+    let (data, store) = context.data_and_store_mut();
+    let bytes = read_bytes(
+        &data.memory.view(&store),
+        encoded_query_ptr,
+        encoded_query_len,
+    )?;
+    let query = bitcode::decode::<common::QueryDescriptor>(&bytes)
+        .map_err(|_| ImportError::InvalidQuery)?
+        .to_owned();
+
+    let local_query_id = match data
+        .query_ids
+        .iter()
+        .position(|existing| *existing == query)
+    {
+        Some(index) => index,
+        None => {
+            data.query_ids.push(query);
+            data.query_ids.len() - 1
+        }
+    };
+    Ok(local_query_id as u32)
+}
+
+fn buffer_query(mut context: Context, local_query_id: u32) -> Result<u32, ImportError> {
+    let state = context.data_mut();
+    let query = state.query(local_query_id)?;
+    let rows = state
+        .queries
+        .rows(query)
+        .ok_or_else(|| ImportError::QueryNotDeclared(query.clone()))?;
+
+    let value = bitcode::encode(&rows);
+    let len = value.len() as u32;
+    state.buffer = value;
+    Ok(len)
+}
+
+fn set_query_component(
+    mut context: Context,
+    local_query_id: u32,
+    entity: u32,
+    component_index: u32,
+    buffer_ptr: u32,
+    buffer_len: u32,
+) -> Result<(), ImportError> {
+    let (data, store) = context.data_and_store_mut();
+    let id = data
+        .query(local_query_id)?
+        .components
+        .get(component_index as usize)
+        .ok_or(ImportError::UnknownComponentIndex(component_index))?
+        .id
+        .clone();
+
+    let value = read_bytes(&data.memory.view(&store), buffer_ptr, buffer_len)?;
+    let Some(world_entity) = data.queries.write(entity, id.clone(), value.clone()) else {
+        return Err(ImportError::PermissionDenied(Capability::WriteComponent(
+            id,
+        )));
+    };
+    data.commands.push(ModCommand::WriteComponent {
+        entity: world_entity,
+        id,
+        value,
+    });
+    Ok(())
+}
+
+fn write_buffer_to(mut context: Context, ptr: u32) -> Result<(), ImportError> {
+    let (data, store) = context.data_and_store_mut();
+    let buffer = std::mem::take(&mut data.buffer);
+    data.memory
+        .view(&store)
+        .write(ptr as u64, &buffer)
+        .map_err(ImportError::MemoryAccess)
+}
+
+fn read_bytes(view: &MemoryView, ptr: u32, len: u32) -> Result<Vec<u8>, ImportError> {
+    let start = ptr as u64;
+    let range: Range<u64> = start..start + len as u64;
+    view.copy_range_to_vec(range)
+        .map_err(ImportError::MemoryAccess)
+}
+
+fn read_string(view: &MemoryView, ptr: u32, len: u32) -> Result<String, ImportError> {
+    let bytes = read_bytes(view, ptr, len)?;
+    String::from_utf8(bytes).map_err(ImportError::InvalidUtf8)
+}
+
+fn read_stable_id(
+    view: &MemoryView,
+    type_short_name_ptr: u32,
+    type_short_name_len: u32,
+    type_crate_name_ptr: u32,
+    type_crate_name_len: u32,
+) -> Result<OwnedStableId, ImportError> {
+    Ok(OwnedStableId {
+        crate_name: read_string(view, type_crate_name_ptr, type_crate_name_len)?,
+        name: read_string(view, type_short_name_ptr, type_short_name_len)?,
+    })
+}
+
+/// An error raised by an import function, which traps the calling mod
+#[derive(Debug)]
+pub enum ImportError {
+    MemoryAccess(wasmer::MemoryAccessError),
+    InvalidUtf8(std::string::FromUtf8Error),
+    UnknownLocalTypeId(u32),
+    ResourceNotFound(OwnedStableId),
+    InvalidQuery,
+    UnknownLocalQueryId(u32),
+    /// The mod iterated a query that none of the running systems declared in the manifest
+    QueryNotDeclared(OwnedQueryDescriptor),
+    UnknownComponentIndex(u32),
+    /// The mod did something the [`super::super::PermissionPolicy`] does not grant it
+    PermissionDenied(Capability),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MemoryAccess(err) => write!(f, "Invalid memory access: {}", err),
+            Self::InvalidUtf8(err) => write!(f, "Invalid utf-8 string: {}", err),
+            Self::UnknownLocalTypeId(id) => write!(f, "Unknown local type id: {}", id),
+            Self::ResourceNotFound(id) => write!(f, "Resource not found: {:?}", id),
+            Self::InvalidQuery => write!(f, "Invalid query"),
+            Self::UnknownLocalQueryId(id) => write!(f, "Unknown local query id: {}", id),
+            Self::QueryNotDeclared(query) => {
+                write!(f, "Query not declared by the running systems: {:?}", query)
+            }
+            Self::UnknownComponentIndex(index) => {
+                write!(f, "Unknown query component index: {}", index)
+            }
+            Self::PermissionDenied(capability) => {
+                write!(f, "Permission denied: {:?}", capability)
+            }
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::MemoryType;
+
+    use super::*;
+
+    const VALUE: &[u8] = &[1, 2, 3];
+
+    fn score() -> OwnedStableId {
+        OwnedStableId {
+            crate_name: "host".to_owned(),
+            name: "Score".to_owned(),
+        }
+    }
+
+    /// The state of a mod which knows `Score` as local type 0, and has [`VALUE`] at the start of its memory
+    fn state(store: &mut Store, grants: Grants) -> FunctionEnv<State> {
+        let memory = Memory::new(store, MemoryType::new(1, None, false)).unwrap();
+        memory.view(store).write(0, VALUE).unwrap();
+
+        let mut state = State::new(memory, Arc::default());
+        state.type_ids.push(score());
+        state.grants = grants;
+        FunctionEnv::new(store, state)
+    }
+
+    #[test]
+    fn imports_deny_capabilities_that_were_not_granted() {
+        let mut store = Store::default();
+        let env = state(&mut store, Grants::default());
+
+        let error = buffer_resource(env.clone().into_mut(&mut store), 0).unwrap_err();
+        assert!(
+            matches!(&error, ImportError::PermissionDenied(Capability::ReadResource(id)) if *id == score()),
+            "{:?}",
+            error
+        );
+
+        let error = set_resource(env.into_mut(&mut store), 0, 0, VALUE.len() as u32).unwrap_err();
+        assert!(
+            matches!(&error, ImportError::PermissionDenied(Capability::WriteResource(id)) if *id == score()),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn mods_write_resources_they_declared() {
+        let mut store = Store::default();
+        let env = state(
+            &mut store,
+            Grants::new([Capability::WriteResource(score())], false),
+        );
+        env.as_mut(&mut store).resources.insert(score(), Vec::new());
+
+        set_resource(env.clone().into_mut(&mut store), 0, 0, VALUE.len() as u32).unwrap();
+
+        let state = env.as_ref(&store);
+        assert_eq!(state.resources.get(&score()), Some(VALUE));
+        assert!(state.written_resources.contains(&score()));
+        assert!(state.commands.is_empty());
+    }
+
+    #[test]
+    fn mods_cannot_write_resources_nobody_declared() {
+        let mut store = Store::default();
+        let env = state(
+            &mut store,
+            Grants::new([Capability::WriteResource(score())], false),
+        );
+
+        // Neither declared by the mod nor fetched from the host, so the mod does not get a private copy
+        let error =
+            set_resource(env.clone().into_mut(&mut store), 0, 0, VALUE.len() as u32).unwrap_err();
+        assert!(
+            matches!(&error, ImportError::ResourceNotFound(id) if *id == score()),
+            "{:?}",
+            error
+        );
+        assert_eq!(env.as_ref(&store).resources.get(&score()), None);
+    }
+}
@@ -0,0 +1,70 @@
+use super::LoadingError;
+
+/// Limits the linear memory of each mod
+///
+/// The limits are applied when a mod is instantiated, so changing them only affects mods loaded afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLimits {
+    /// The most 64 KiB wasm pages a mod may use
+    pub max_pages: u32,
+    pub growth: MemoryGrowth,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        Self {
+            // 256 MiB
+            max_pages: 4096,
+            growth: MemoryGrowth::OnDemand,
+        }
+    }
+}
+
+/// How the memory of a mod reaches [`MemoryLimits::max_pages`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryGrowth {
+    /// Starts with the pages the mod asks for and grows when the mod requests more
+    OnDemand,
+    /// Allocates every page up front, the mod can never grow its memory
+    Preallocated,
+}
+
+/// The memory imported by mods built with `bevy_harmonize_api`
+const MEMORY_IMPORT: (&str, &str) = ("env", "memory");
+
+/// The pages allocated for a mod when no minimum is declared by its module
+const DEFAULT_MINIMUM_PAGES: u32 = 17;
+
+impl MemoryLimits {
+    /// The type of the memory created for a module, within these limits
+    pub(super) fn memory_type(
+        &self,
+        module: &wasmer::Module,
+    ) -> Result<wasmer::MemoryType, LoadingError> {
+        let declared = module.imports().find_map(|import| match import.ty() {
+            wasmer::ExternType::Memory(ty) if (import.module(), import.name()) == MEMORY_IMPORT => {
+                Some(*ty)
+            }
+            _ => None,
+        });
+
+        let minimum = declared.map_or(DEFAULT_MINIMUM_PAGES, |ty| ty.minimum.0);
+        // The module may declare a lower maximum than ours, which the memory has to honour to be imported
+        let maximum = declared
+            .and_then(|ty| ty.maximum)
+            .map_or(self.max_pages, |maximum| maximum.0.min(self.max_pages));
+
+        if minimum > maximum {
+            return Err(LoadingError::MemoryLimitExceeded {
+                required: minimum,
+                max_pages: maximum,
+            });
+        }
+
+        let initial = match self.growth {
+            MemoryGrowth::OnDemand => minimum,
+            MemoryGrowth::Preallocated => maximum,
+        };
+        Ok(wasmer::MemoryType::new(initial, Some(maximum), false))
+    }
+}
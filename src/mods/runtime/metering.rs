@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use bevy_ecs_macros::Resource;
+use wasmer::{wasmparser::Operator, CompilerConfig};
+use wasmer_middlewares::Metering;
+
+/// Limits how many wasm instructions mod systems may execute
+///
+/// A system that exceeds a budget is stopped and reported as failed, instead of freezing the game.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ExecutionBudget {
+    /// The most instructions a single run of a system may execute
+    pub per_system: u64,
+    /// The most instructions the systems of a single mod may execute per frame
+    pub per_frame: u64,
+}
+
+impl Default for ExecutionBudget {
+    fn default() -> Self {
+        Self {
+            per_system: 10_000_000,
+            per_frame: 50_000_000,
+        }
+    }
+}
+
+/// Creates a store whose modules count the instructions they execute
+pub(crate) fn metered_store() -> wasmer::Store {
+    // The limit is set again before every call into the mod
+    let metering = Arc::new(Metering::new(0, cost));
+
+    let mut compiler = wasmer::Cranelift::default();
+    compiler.push_middleware(metering);
+    wasmer::Store::new(wasmer::EngineBuilder::new(compiler))
+}
+
+fn cost(_operator: &Operator) -> u64 {
+    1
+}
@@ -0,0 +1,665 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    reflect::{AppTypeRegistry, ReflectComponent, ReflectResource},
+    world::World,
+};
+use bevy_reflect::{serde::TypedReflectDeserializer, TypeRegistration, TypeRegistry};
+use bevy_utils::{synccell::SyncCell, tracing::warn, HashMap};
+use common::OwnedStableId;
+use serde::de::DeserializeSeed;
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+
+mod imports;
+use imports::State;
+
+mod memory;
+pub use memory::{MemoryGrowth, MemoryLimits};
+
+mod metering;
+pub(crate) use metering::metered_store;
+pub use metering::ExecutionBudget;
+
+mod queries;
+use queries::QuerySnapshot;
+
+mod resources;
+use resources::HostResources;
+pub use resources::ModResources;
+
+use super::{loaded::LoadingError, permissions::Grants};
+
+const RUN_SYSTEM_EXPORT: &str = "bevy_harmonize_run_system";
+pub(crate) const RUN_CONDITION_EXPORT: &str = "bevy_harmonize_run_condition";
+
+/// The queries and resources the systems of a lane declare, see [`ModRuntime::prepare_lanes`]
+pub(crate) type LaneParams<'a> = (
+    Vec<&'a common::OwnedQueryDescriptor>,
+    Vec<&'a OwnedStableId>,
+);
+
+/// A live mod, made of one or more wasm instances sharing its resources and entities
+///
+/// Each instance has its own store and linear memory. Systems run on the first instance, unless the executor
+/// splits the systems of a batch into lanes, see [`super::ModExecutor::Parallel`]. The other instances are only
+/// created once a batch needs them.
+pub struct ModRuntime {
+    module: wasmer::Module,
+    /// The engine the module was compiled with, every instance gets its own store from it
+    engine: wasmer::Engine,
+    limits: MemoryLimits,
+    instances: Vec<ModInstance>,
+    /// How many instances the resources were lent to for the current batch, see [`Self::prepare_lanes`]
+    lent: usize,
+    resources: ModResources,
+    /// Instructions the mod may still execute this frame, shared by its instances
+    frame_budget: AtomicU64,
+    /// The last entity id handed out to the mod, shared by its instances
+    entity_counter: Arc<AtomicU32>,
+    /// What the mod is allowed to do this frame, given to instances created during the frame
+    grants: Grants,
+    /// Maps the entity ids handed out to the mod to entities in the world
+    entities: HashMap<u32, Entity>,
+    /// The ids handed out by queries for entities the mod did not spawn, which it does not own
+    queried: HashMap<Entity, u32>,
+}
+
+/// A wasm instance of a mod, with its own store and linear memory
+struct ModInstance {
+    store: SyncCell<wasmer::Store>,
+    env: wasmer::FunctionEnv<State>,
+    instance: wasmer::Instance,
+    run_system: wasmer::TypedFunction<u64, ()>,
+    /// Only exported by mods built against an api with run conditions
+    run_condition: Option<wasmer::TypedFunction<u64, u32>>,
+    /// The size the memory of the instance may not grow past
+    max_pages: u32,
+}
+
+impl ModRuntime {
+    pub fn try_new(
+        store: wasmer::Store,
+        module: &wasmer::Module,
+        resources: ModResources,
+        limits: &MemoryLimits,
+    ) -> Result<Self, LoadingError> {
+        let engine = store.engine().clone();
+        let entity_counter = Arc::new(AtomicU32::new(0));
+        let instance = ModInstance::try_new(store, module, limits, entity_counter.clone())?;
+
+        Ok(Self {
+            module: module.clone(),
+            engine,
+            limits: *limits,
+            instances: vec![instance],
+            lent: 0,
+            resources,
+            frame_budget: AtomicU64::new(0),
+            entity_counter,
+            grants: Grants::default(),
+            entities: HashMap::new(),
+            queried: HashMap::new(),
+        })
+    }
+
+    /// Resets the instructions this mod may execute this frame, and what it is allowed to do
+    pub(crate) fn start_frame(&mut self, budget: &ExecutionBudget, grants: Grants) {
+        *self.frame_budget.get_mut() = budget.per_frame;
+        for instance in self.instances.iter_mut() {
+            instance.state().grants = grants.clone();
+        }
+        self.grants = grants;
+    }
+
+    /// Whether the mod exports the function running its run conditions
+    pub fn has_run_conditions(&self) -> bool {
+        self.instances[0].run_condition.is_some()
+    }
+
+    pub fn resources(&self) -> &ModResources {
+        &self.resources
+    }
+
+    pub(crate) fn resources_mut(&mut self) -> &mut ModResources {
+        &mut self.resources
+    }
+
+    /// Makes sure the mod has at least `count` instances, returning how many of them can be used
+    ///
+    /// An instance that can't be created, for example because its memory could not be allocated, leaves the mod
+    /// with fewer lanes.
+    pub(crate) fn ensure_instances(&mut self, count: usize) -> usize {
+        while self.instances.len() < count {
+            let store = wasmer::Store::new(self.engine.clone());
+            let instance = ModInstance::try_new(
+                store,
+                &self.module,
+                &self.limits,
+                self.entity_counter.clone(),
+            );
+            match instance {
+                Ok(mut instance) => {
+                    instance.state().grants = self.grants.clone();
+                    self.instances.push(instance);
+                }
+                Err(err) => {
+                    warn!("Could not create another instance of a mod: {:?}", err);
+                    break;
+                }
+            }
+        }
+        self.instances.len().min(count)
+    }
+
+    /// Takes ownership of the entities spawned by another instance of the same mod
+    pub fn take_entities(&mut self, other: ModRuntime) {
+        self.entities.extend(other.entities);
+        self.queried.extend(other.queried);
+
+        // Keep handing out ids the mod has not seen yet
+        let last = self
+            .entities
+            .keys()
+            .chain(self.queried.values())
+            .max()
+            .copied()
+            .unwrap_or_default();
+        self.entity_counter.fetch_max(last, Ordering::AcqRel);
+    }
+
+    /// Despawns every entity the mod spawned that still exists
+    pub fn despawn_entities(self, world: &mut World) {
+        for entity in self.entities.into_values() {
+            if world.entities().contains(entity) {
+                world.despawn(entity);
+            }
+        }
+    }
+
+    /// Prepares an instance for each lane of the next batch, which must not be more than
+    /// [`Self::ensure_instances`] returned
+    ///
+    /// Every instance is given the entities matching the queries of its lane, the host resources its lane reads,
+    /// and a copy of the resources of the mod. [`Self::apply_commands`] takes back the resources each lane wrote.
+    pub(crate) fn prepare_lanes<'a>(
+        &mut self,
+        world: &World,
+        lanes: impl IntoIterator<Item = LaneParams<'a>>,
+    ) {
+        let mut lent = 0;
+        for (index, (queries, resources)) in lanes.into_iter().enumerate() {
+            self.prepare_queries(index, world, queries);
+            self.prepare_resources(index, world, resources);
+            lent += 1;
+        }
+
+        // Lanes never write the same resource, the first one takes the resources back as they are
+        for instance in self.instances.iter_mut().take(lent).skip(1) {
+            let state = instance.state();
+            state.resources = self.resources.clone();
+            state.written_resources.clear();
+        }
+        if let Some(first) = self.instances[..lent].first_mut() {
+            first.state().resources = std::mem::take(&mut self.resources);
+        }
+        self.lent = lent;
+    }
+
+    /// Fetches the entities matching the given queries, which the mod reads through the import functions
+    ///
+    /// Entities the mod did not spawn only match if it was granted access to every component the query fetches,
+    /// see [`super::PermissionPolicy::own_entities`].
+    fn prepare_queries(
+        &mut self,
+        index: usize,
+        world: &World,
+        queries: Vec<&common::OwnedQueryDescriptor>,
+    ) {
+        let state = self.instances[index].state();
+        if queries.is_empty() {
+            state.queries = QuerySnapshot::default();
+            return;
+        }
+
+        let spawned: HashMap<Entity, u32> = self
+            .entities
+            .iter()
+            .map(|(local, entity)| (*entity, *local))
+            .collect();
+        let grants = &state.grants;
+        let entity_counter = &self.entity_counter;
+        let queried = &mut self.queried;
+        let snapshot = QuerySnapshot::new(world, queries, grants, |entity, granted| {
+            if let Some(&local) = spawned.get(&entity) {
+                return (granted || grants.own_entities).then_some(local);
+            }
+            if !granted {
+                return None;
+            }
+            let local = queried
+                .entry(entity)
+                .or_insert_with(|| entity_counter.fetch_add(1, Ordering::AcqRel) + 1);
+            Some(*local)
+        });
+        state.queries = snapshot;
+    }
+
+    /// Fetches the resources of the host the given resource params read, which the mod reads through the import
+    /// functions
+    ///
+    /// Resources declared by the mod itself are left out, and so are those it was not granted
+    /// [`super::Capability::ReadResource`] for.
+    fn prepare_resources(&mut self, index: usize, world: &World, ids: Vec<&OwnedStableId>) {
+        let state = self.instances[index].state();
+        let ids = ids
+            .into_iter()
+            .filter(|id| self.resources.get(id).is_none());
+        state.host_resources = HostResources::new(world, ids, &state.grants);
+    }
+
+    /// Lends out the instances prepared by [`Self::prepare_lanes`], so their lanes can run at the same time
+    pub(crate) fn lanes(&mut self) -> Vec<Lane<'_>> {
+        let frame_budget = &self.frame_budget;
+        self.instances[..self.lent]
+            .iter_mut()
+            .map(|instance| Lane {
+                instance,
+                frame_budget,
+            })
+            .collect()
+    }
+
+    /// Applies the commands queued by the systems that ran since the last call, lane by lane in the order they
+    /// were queued, and takes back the resources of the mod from its instances
+    ///
+    /// Even a system that trapped may have queued commands before failing.
+    pub fn apply_commands(&mut self, world: &mut World) {
+        let mut commands = Vec::new();
+        for (index, instance) in self.instances[..self.lent].iter_mut().enumerate() {
+            let state = instance.state();
+            commands.append(&mut state.commands);
+            // The snapshots are outdated once the commands are applied
+            state.queries = QuerySnapshot::default();
+            state.host_resources = HostResources::default();
+
+            let resources = std::mem::take(&mut state.resources);
+            if index == 0 {
+                self.resources = resources;
+            } else {
+                for id in state.written_resources.drain() {
+                    if let Some(value) = resources.get(&id) {
+                        self.resources.insert(id, value.to_vec());
+                    }
+                }
+            }
+        }
+        self.lent = 0;
+        if commands.is_empty() {
+            return;
+        }
+
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+        for command in commands {
+            match command {
+                ModCommand::Spawn(local) => {
+                    let entity = world.spawn_empty().id();
+                    self.entities.insert(local, entity);
+                }
+                ModCommand::InsertComponent { entity, id, value } => {
+                    let Some(&spawned) = self.entities.get(&entity) else {
+                        warn!("Mod inserted component {:?} into unknown entity", id);
+                        continue;
+                    };
+                    // Despawned by another mod or the host since the mod spawned it
+                    if !world.entities().contains(spawned) {
+                        self.entities.remove(&entity);
+                        continue;
+                    }
+                    insert_component(world, spawned, id, value, &registry);
+                }
+                ModCommand::WriteComponent { entity, id, value } => {
+                    // Despawned by another mod or the host since the query ran
+                    if world.entities().contains(entity) {
+                        insert_component(world, entity, id, value, &registry);
+                    }
+                }
+                ModCommand::WriteResource { id, value } => {
+                    let Some((registration, reflect_resource)) = reflect_resource(&registry, &id)
+                    else {
+                        continue;
+                    };
+                    match deserialize(&value, registration, &registry) {
+                        Ok(resource) => reflect_resource.insert(world, &*resource, &registry),
+                        Err(err) => warn!("Mod wrote an invalid {:?}: {}", id, err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ModInstance {
+    fn try_new(
+        mut store: wasmer::Store,
+        module: &wasmer::Module,
+        limits: &MemoryLimits,
+        entity_counter: Arc<AtomicU32>,
+    ) -> Result<Self, LoadingError> {
+        let memory_type = limits.memory_type(module)?;
+        let max_pages = memory_type
+            .maximum
+            .map_or(limits.max_pages, |maximum| maximum.0);
+        let memory = wasmer::Memory::new(&mut store, memory_type)
+            .map_err(LoadingError::MemoryAllocationFailed)?;
+
+        let env = wasmer::FunctionEnv::new(&mut store, State::new(memory.clone(), entity_counter));
+        let import_object = imports::imports(&mut store, &env, memory);
+
+        // Mods built against a newer api may call functions this host does not provide
+        if let Some(import) = module.imports().find(|import| {
+            import_object
+                .get_export(import.module(), import.name())
+                .is_none()
+        }) {
+            return Err(LoadingError::MissingImport {
+                module: import.module().to_string(),
+                name: import.name().to_string(),
+            });
+        }
+
+        let instance = wasmer::Instance::new(&mut store, module, &import_object)
+            .map_err(LoadingError::InstantiationFailed)?;
+        let run_system = instance
+            .exports
+            .get_typed_function(&store, RUN_SYSTEM_EXPORT)
+            .map_err(|_| LoadingError::MissingExport(RUN_SYSTEM_EXPORT))?;
+        let run_condition = instance
+            .exports
+            .get_typed_function(&store, RUN_CONDITION_EXPORT)
+            .ok();
+
+        Ok(Self {
+            store: SyncCell::new(store),
+            env,
+            instance,
+            run_system,
+            run_condition,
+            max_pages,
+        })
+    }
+
+    fn state(&mut self) -> &mut State {
+        self.env.as_mut(self.store.get())
+    }
+
+    /// The current size of the linear memory of the instance, in 64 KiB pages
+    fn memory_pages(&mut self) -> u32 {
+        let store = self.store.get();
+        self.env.as_ref(store).memory.view(store).size().0
+    }
+}
+
+/// An instance of a mod lent out to run the systems of a lane, see [`ModRuntime::lanes`]
+///
+/// The lanes of a mod share its frame budget, each system takes the instructions it may execute from it before
+/// running and gives back what it did not use.
+pub(crate) struct Lane<'a> {
+    instance: &'a mut ModInstance,
+    frame_budget: &'a AtomicU64,
+}
+
+impl Lane<'_> {
+    /// Runs a system of the mod
+    ///
+    /// The system does not touch the world, so it can run at the same time as other lanes and mods. The commands
+    /// it queued are applied by [`ModRuntime::apply_commands`].
+    pub fn run_system(
+        &mut self,
+        id: common::SystemId,
+        budget: &ExecutionBudget,
+    ) -> Result<(), SystemError> {
+        let run_system = self.instance.run_system.clone();
+        self.metered(budget, |store| run_system.call(store, id.get_raw()))
+    }
+
+    /// Runs a run condition of the mod, returning whether the systems it gates should run
+    ///
+    /// Conditions are metered like any other system.
+    pub fn run_condition(
+        &mut self,
+        id: common::SystemId,
+        budget: &ExecutionBudget,
+    ) -> Result<bool, SystemError> {
+        let Some(run_condition) = self.instance.run_condition.clone() else {
+            return Err(SystemError::MissingConditions);
+        };
+        self.metered(budget, |store| run_condition.call(store, id.get_raw()))
+            .map(|result| result != 0)
+    }
+
+    /// Instructions the mod may still execute this frame
+    pub fn frame_budget(&self) -> u64 {
+        self.frame_budget.load(Ordering::Acquire)
+    }
+
+    /// Calls into the mod with at most [`ExecutionBudget::per_system`] instructions, taken from the frame budget
+    fn metered<R>(
+        &mut self,
+        budget: &ExecutionBudget,
+        call: impl FnOnce(&mut wasmer::Store) -> Result<R, wasmer::RuntimeError>,
+    ) -> Result<R, SystemError> {
+        // Other lanes of the mod may be taking from the frame budget at the same time
+        let mut available = self.frame_budget.load(Ordering::Acquire);
+        loop {
+            if available == 0 {
+                return Err(SystemError::FrameBudgetExhausted);
+            }
+            let left = available - available.min(budget.per_system);
+            match self.frame_budget.compare_exchange_weak(
+                available,
+                left,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => available = current,
+            }
+        }
+        let fuel = budget.per_system.min(available);
+        // Running out of fuel also empties the frame budget when the system was given all that was left of it
+        let frame_limited = available <= budget.per_system;
+
+        let pages = self.instance.memory_pages();
+        let max_pages = self.instance.max_pages;
+        let store = self.instance.store.get();
+        set_remaining_points(store, &self.instance.instance, fuel);
+        let result = call(store);
+
+        let remaining = get_remaining_points(store, &self.instance.instance);
+        if let MeteringPoints::Remaining(remaining) = remaining {
+            self.frame_budget.fetch_add(remaining, Ordering::AcqRel);
+        }
+
+        match result {
+            Ok(result) => Ok(result),
+            Err(_) if remaining == MeteringPoints::Exhausted => {
+                if frame_limited {
+                    Err(SystemError::FrameBudgetExhausted)
+                } else {
+                    Err(SystemError::OutOfFuel { budget: fuel })
+                }
+            }
+            // Allocations fail once the memory can't grow anymore, which aborts the mod. A mod that was already at
+            // its limit, or whose memory was preallocated, never grew during the call and merely trapped.
+            Err(_) if pages < max_pages && self.instance.memory_pages() >= max_pages => {
+                Err(SystemError::OutOfMemory { max_pages })
+            }
+            Err(err) => Err(SystemError::Trapped(err)),
+        }
+    }
+}
+
+impl fmt::Debug for ModRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModRuntime")
+            .field("instances", &self.instances.len())
+            .field("resources", &self.resources)
+            .field("entities", &self.entities)
+            .field("queried", &self.queried)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why a mod system did not run to completion
+#[derive(Debug)]
+pub enum SystemError {
+    /// The system executed more instructions than [`ExecutionBudget::per_system`] allows
+    OutOfFuel {
+        budget: u64,
+    },
+    /// The systems of the mod already executed [`ExecutionBudget::per_frame`] instructions this frame
+    FrameBudgetExhausted,
+    /// The system trapped after growing the memory of the mod to [`MemoryLimits::max_pages`]
+    ///
+    /// Only reported when the memory reached its limit during the call, traps of mods that were already at their
+    /// limit, such as [`MemoryGrowth::Preallocated`] mods, are reported as [`Self::Trapped`].
+    OutOfMemory {
+        max_pages: u32,
+    },
+    Trapped(wasmer::RuntimeError),
+    /// The mod has run conditions, but does not export the function running them
+    MissingConditions,
+}
+
+impl fmt::Display for SystemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfFuel { budget } => {
+                write!(f, "Ran out of fuel after {} instructions", budget)
+            }
+            Self::FrameBudgetExhausted => write!(f, "Mod exhausted its budget for this frame"),
+            Self::OutOfMemory { max_pages } => {
+                write!(f, "Ran out of memory after growing to {} pages", max_pages)
+            }
+            Self::Trapped(err) => write!(f, "Trapped: {}", err),
+            Self::MissingConditions => write!(f, "Mod does not export its run conditions"),
+        }
+    }
+}
+
+impl Error for SystemError {}
+
+/// A change to the world requested by a mod
+pub(crate) enum ModCommand {
+    Spawn(u32),
+    InsertComponent {
+        entity: u32,
+        id: OwnedStableId,
+        value: Vec<u8>,
+    },
+    /// A component fetched by a query was changed, the entity was resolved when the query ran
+    WriteComponent {
+        entity: Entity,
+        id: OwnedStableId,
+        value: Vec<u8>,
+    },
+    /// A resource of the host was changed
+    WriteResource {
+        id: OwnedStableId,
+        value: Vec<u8>,
+    },
+}
+
+/// Components inserted by mods whose types are not registered on the host, stored as encoded bytes
+#[derive(Component, Default, Debug)]
+pub(crate) struct ModComponents(pub HashMap<OwnedStableId, Vec<u8>>);
+
+/// Looks up a component type registered on the host by its stable id
+fn reflect_component<'r>(
+    registry: &'r TypeRegistry,
+    id: &OwnedStableId,
+) -> Option<(&'r TypeRegistration, &'r ReflectComponent)> {
+    registry
+        .get_with_short_type_path(&id.name)
+        .filter(|registration| {
+            registration.type_info().type_path_table().crate_name() == Some(&id.crate_name)
+        })
+        .and_then(|registration| {
+            registration
+                .data::<ReflectComponent>()
+                .map(|reflect_component| (registration, reflect_component))
+        })
+}
+
+/// Looks up a resource type registered on the host by its stable id
+fn reflect_resource<'r>(
+    registry: &'r TypeRegistry,
+    id: &OwnedStableId,
+) -> Option<(&'r TypeRegistration, &'r ReflectResource)> {
+    registry
+        .get_with_short_type_path(&id.name)
+        .filter(|registration| {
+            registration.type_info().type_path_table().crate_name() == Some(&id.crate_name)
+        })
+        .and_then(|registration| {
+            registration
+                .data::<ReflectResource>()
+                .map(|reflect_resource| (registration, reflect_resource))
+        })
+}
+
+fn insert_component(
+    world: &mut World,
+    entity: Entity,
+    id: OwnedStableId,
+    value: Vec<u8>,
+    registry: &TypeRegistry,
+) {
+    let reflected = reflect_component(registry, &id);
+
+    let mut entity = world.entity_mut(entity);
+    match reflected {
+        Some((registration, reflect_component)) => {
+            match deserialize(&value, registration, registry) {
+                Ok(component) => reflect_component.insert(&mut entity, &*component, registry),
+                Err(err) => warn!("Mod inserted an invalid {:?}: {}", id, err),
+            }
+        }
+        None => {
+            if let Some(mut components) = entity.get_mut::<ModComponents>() {
+                components.0.insert(id, value);
+            } else {
+                let mut components = ModComponents::default();
+                components.0.insert(id, value);
+                entity.insert(components);
+            }
+        }
+    }
+}
+
+/// Decodes a value encoded by `bevy_harmonize_api::runtime::serialize`
+fn deserialize(
+    mut bytes: &[u8],
+    registration: &TypeRegistration,
+    registry: &TypeRegistry,
+) -> Result<Box<dyn bevy_reflect::PartialReflect>, String> {
+    let mut decoder = bitcode::SerdeDecoder::Unspecified { length: 1 };
+    let bitcode_deserializer = bitcode::DecoderWrapper {
+        decoder: &mut decoder,
+        input: &mut bytes,
+    };
+
+    TypedReflectDeserializer::new(registration, registry)
+        .deserialize(bitcode_deserializer)
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,319 @@
+use bevy_ecs::{
+    archetype::Archetype,
+    component::ComponentId,
+    entity::Entity,
+    reflect::{AppTypeRegistry, ReflectComponent},
+    world::{EntityRef, World},
+};
+use bevy_reflect::{serde::TypedReflectSerializer, TypeRegistry};
+use bevy_utils::{tracing::warn, HashMap, HashSet};
+use common::{OwnedQueryDescriptor, OwnedStableId};
+
+use super::{
+    super::permissions::{Capability, Grants},
+    reflect_component, ModComponents,
+};
+
+/// The entities matching the queries of the systems about to run, with their components encoded for the mod
+///
+/// Mods can't access the world while they run, so the entities are fetched by [`super::ModRuntime::prepare_queries`]
+/// before every batch. Components written back by a system are updated here too, so the next systems of the mod see
+/// them before they are applied to the world.
+#[derive(Default)]
+pub(crate) struct QuerySnapshot {
+    /// The local ids of the entities matching each query, in the order the mod iterates them
+    matches: HashMap<OwnedQueryDescriptor, Vec<u32>>,
+    /// Encoded components, by local entity id
+    components: HashMap<(u32, OwnedStableId), Vec<u8>>,
+    /// Components the mod may write back, fetched by a query through `&mut T`
+    writable: HashSet<(u32, OwnedStableId)>,
+    /// The entities in the world behind the local entity ids
+    entities: HashMap<u32, Entity>,
+}
+
+impl QuerySnapshot {
+    /// Matches every entity of the world against the given queries
+    ///
+    /// `local_id` returns the id the mod knows an entity by, or `None` if the mod may not see it. It is told
+    /// whether the mod was granted access to every component the query fetches.
+    pub fn new<'a>(
+        world: &World,
+        queries: impl IntoIterator<Item = &'a OwnedQueryDescriptor>,
+        grants: &Grants,
+        mut local_id: impl FnMut(Entity, bool) -> Option<u32>,
+    ) -> Self {
+        let registry = world.resource::<AppTypeRegistry>().read();
+
+        let mut snapshot = Self::default();
+        for query in queries {
+            if snapshot.matches.contains_key(query) {
+                continue;
+            }
+            let granted = query.components.iter().all(|component| {
+                grants.allows(&match component.mutable {
+                    false => Capability::ReadComponent(component.id.clone()),
+                    true => Capability::WriteComponent(component.id.clone()),
+                })
+            });
+
+            // Type registry lookups are resolved once per query, then only archetypes that can match are visited
+            let lookup = |id: &OwnedStableId| Lookup::new(world, &registry, id);
+            let required: Vec<_> = query
+                .components
+                .iter()
+                .map(|component| &component.id)
+                .chain(query.with.iter())
+                .map(|id| (id, lookup(id)))
+                .collect();
+            let excluded: Vec<_> = query.without.iter().map(|id| (id, lookup(id))).collect();
+            let lookups = &required[..query.components.len()];
+            let mod_components = world.components().component_id::<ModComponents>();
+
+            let entities = world
+                .archetypes()
+                .iter()
+                .filter(|archetype| {
+                    required
+                        .iter()
+                        .all(|(_, lookup)| lookup.in_archetype(archetype, mod_components))
+                        // Components stored in `ModComponents` are only known per entity
+                        && !excluded.iter().any(|(_, lookup)| {
+                            matches!(lookup, Lookup::Reflected(..))
+                                && lookup.in_archetype(archetype, mod_components)
+                        })
+                })
+                .flat_map(Archetype::entities)
+                .map(|entity| world.entity(entity.id()));
+
+            let mut matches = Vec::new();
+            for entity in entities {
+                let matched = required
+                    .iter()
+                    .all(|(id, lookup)| lookup.has_mod_component(entity, id) != Some(false))
+                    && !excluded
+                        .iter()
+                        .any(|(id, lookup)| lookup.has_mod_component(entity, id) == Some(true));
+                if !matched {
+                    continue;
+                }
+                let Some(local) = local_id(entity.id(), granted) else {
+                    continue;
+                };
+
+                let mut fetched = true;
+                for (component, (_, lookup)) in query.components.iter().zip(lookups) {
+                    let key = (local, component.id.clone());
+                    if !snapshot.components.contains_key(&key) {
+                        let Some(value) = lookup.encode(entity, &component.id, &registry) else {
+                            fetched = false;
+                            break;
+                        };
+                        snapshot.components.insert(key.clone(), value);
+                    }
+                    if component.mutable {
+                        snapshot.writable.insert(key);
+                    }
+                }
+                if fetched {
+                    snapshot.entities.insert(local, entity.id());
+                    matches.push(local);
+                }
+            }
+            snapshot.matches.insert(query.clone(), matches);
+        }
+        snapshot
+    }
+
+    /// The local id and encoded components of every entity matching a query, or `None` if no system about to run
+    /// declared it
+    pub fn rows(&self, query: &OwnedQueryDescriptor) -> Option<Vec<(u32, Vec<Vec<u8>>)>> {
+        let matches = self.matches.get(query)?;
+        let rows = matches
+            .iter()
+            .map(|&local| {
+                let components = query
+                    .components
+                    .iter()
+                    .map(|component| self.components[&(local, component.id.clone())].clone())
+                    .collect();
+                (local, components)
+            })
+            .collect();
+        Some(rows)
+    }
+
+    /// Updates a component written back by the mod, returning the entity it belongs to
+    ///
+    /// Returns `None` if the mod did not fetch the component through `&mut T`.
+    pub fn write(&mut self, local: u32, id: OwnedStableId, value: Vec<u8>) -> Option<Entity> {
+        let key = (local, id);
+        if !self.writable.contains(&key) {
+            return None;
+        }
+        self.components.insert(key, value);
+        self.entities.get(&local).copied()
+    }
+}
+
+/// Where the components of a type are found, resolved once per query
+enum Lookup<'r> {
+    /// Registered on the host, the id is `None` if no entity ever had the component
+    Reflected(Option<ComponentId>, &'r ReflectComponent),
+    /// Inserted by mods without being registered on the host, stored in [`ModComponents`]
+    Mod,
+}
+
+impl<'r> Lookup<'r> {
+    fn new(world: &World, registry: &'r TypeRegistry, id: &OwnedStableId) -> Self {
+        match reflect_component(registry, id) {
+            Some((registration, reflect_component)) => Self::Reflected(
+                world.components().get_id(registration.type_id()),
+                reflect_component,
+            ),
+            None => Self::Mod,
+        }
+    }
+
+    /// Whether the entities of an archetype may have the component
+    fn in_archetype(&self, archetype: &Archetype, mod_components: Option<ComponentId>) -> bool {
+        let id = match self {
+            Self::Reflected(id, _) => id,
+            Self::Mod => &mod_components,
+        };
+        id.is_some_and(|id| archetype.contains(id))
+    }
+
+    /// Whether the entity has a component stored in [`ModComponents`], `None` for components registered on the
+    /// host, which are matched by archetype
+    fn has_mod_component(&self, entity: EntityRef, id: &OwnedStableId) -> Option<bool> {
+        match self {
+            Self::Reflected(..) => None,
+            Self::Mod => Some(
+                entity
+                    .get::<ModComponents>()
+                    .is_some_and(|components| components.0.contains_key(id)),
+            ),
+        }
+    }
+
+    /// Encodes a component like `bevy_harmonize_api::runtime::serialize`, so the mod can decode it
+    fn encode(
+        &self,
+        entity: EntityRef,
+        id: &OwnedStableId,
+        registry: &TypeRegistry,
+    ) -> Option<Vec<u8>> {
+        match self {
+            Self::Reflected(_, reflect_component) => {
+                let value = reflect_component.reflect(entity)?;
+                let serializer = TypedReflectSerializer::new(value.as_partial_reflect(), registry);
+                match bitcode::serialize(&serializer) {
+                    Ok(bytes) => Some(bytes),
+                    Err(err) => {
+                        warn!("Could not encode {:?} for a mod query: {}", id, err);
+                        None
+                    }
+                }
+            }
+            Self::Mod => entity.get::<ModComponents>()?.0.get(id).cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::component::Component;
+    use bevy_reflect::Reflect;
+    use common::OwnedQueryComponent;
+
+    use super::*;
+
+    #[derive(Component, Reflect, Default)]
+    #[reflect(Component)]
+    struct Position(u32);
+
+    #[derive(Component, Reflect, Default)]
+    #[reflect(Component)]
+    struct Frozen;
+
+    fn health() -> OwnedStableId {
+        OwnedStableId {
+            crate_name: "my_mod".into(),
+            name: "Health".into(),
+        }
+    }
+
+    fn query(components: Vec<OwnedStableId>, without: Vec<OwnedStableId>) -> OwnedQueryDescriptor {
+        OwnedQueryDescriptor {
+            components: components
+                .into_iter()
+                .map(|id| OwnedQueryComponent { mutable: false, id })
+                .collect(),
+            with: Vec::new(),
+            without,
+        }
+    }
+
+    #[test]
+    fn matches_entities_by_archetype_and_mod_components() {
+        let mut world = World::new();
+        let registry = AppTypeRegistry::default();
+        registry.write().register::<Position>();
+        registry.write().register::<Frozen>();
+        world.insert_resource(registry);
+
+        let moving = world.spawn(Position(1)).id();
+        let frozen = world.spawn((Position(2), Frozen)).id();
+        world.spawn_empty();
+        let mut components = ModComponents::default();
+        components.0.insert(health(), vec![42]);
+        let healthy = world.spawn((Position(3), components)).id();
+
+        let position = OwnedStableId::from_typed::<Position>();
+        let not_frozen = query(
+            vec![position.clone()],
+            vec![OwnedStableId::from_typed::<Frozen>()],
+        );
+        let with_health = query(vec![health()], Vec::new());
+        let without_health = query(vec![position], vec![health()]);
+
+        let locals: HashMap<Entity, u32> = world
+            .iter_entities()
+            .enumerate()
+            .map(|(index, entity)| (entity.id(), index as u32))
+            .collect();
+        let snapshot = QuerySnapshot::new(
+            &world,
+            [&not_frozen, &with_health, &without_health],
+            &Grants::default(),
+            |entity, _| locals.get(&entity).copied(),
+        );
+
+        let matched = |query| {
+            let mut matched: Vec<_> = snapshot
+                .rows(query)
+                .unwrap()
+                .into_iter()
+                .map(|(local, _)| local)
+                .collect();
+            matched.sort();
+            matched
+        };
+        let sorted = |mut locals: Vec<u32>| {
+            locals.sort();
+            locals
+        };
+        assert_eq!(
+            matched(&not_frozen),
+            sorted(vec![locals[&moving], locals[&healthy]])
+        );
+        assert_eq!(
+            matched(&without_health),
+            sorted(vec![locals[&moving], locals[&frozen]])
+        );
+        assert_eq!(
+            snapshot.rows(&with_health),
+            Some(vec![(locals[&healthy], vec![vec![42]])])
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use bevy_ecs::{reflect::AppTypeRegistry, world::World};
+use bevy_reflect::{
+    serde::TypedReflectSerializer, FromReflect, GetTypeRegistration, TypeRegistry, Typed,
+};
+use bevy_utils::{tracing::warn, HashMap};
+use common::OwnedStableId;
+
+use super::{
+    super::{
+        loaded::LoadedFeature,
+        permissions::{Capability, Grants},
+    },
+    deserialize, reflect_resource,
+};
+
+/// The live resources of a single mod, stored as encoded bytes keyed by their stable id
+///
+/// Seeded with the defaults declared by the mod's features, then read and written by the mod's systems through
+/// `ResMut`.
+#[derive(Debug, Default, Clone)]
+pub struct ModResources(HashMap<OwnedStableId, Vec<u8>>);
+
+impl ModResources {
+    pub(crate) fn from_features(features: &[LoadedFeature]) -> Self {
+        let mut resources = HashMap::new();
+        for feature in features {
+            for (id, value) in feature.resources.iter() {
+                resources.entry(id.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        Self(resources)
+    }
+
+    /// Returns the encoded value of a resource
+    pub fn get(&self, id: &OwnedStableId) -> Option<&[u8]> {
+        self.0.get(id).map(Vec::as_slice)
+    }
+
+    /// Decodes the value of a resource whose type is also known to the host
+    ///
+    /// Returns `None` if the mod has no such resource or its value could not be decoded.
+    pub fn get_typed<T>(&self) -> Option<T>
+    where
+        T: FromReflect + Typed + GetTypeRegistration,
+    {
+        let bytes = self.get(&OwnedStableId::from_typed::<T>())?;
+
+        let mut registry = TypeRegistry::new();
+        registry.register::<T>();
+        let registration = registry.get(std::any::TypeId::of::<T>())?;
+
+        let value = deserialize(bytes, registration, &registry).ok()?;
+        T::from_reflect(&*value)
+    }
+
+    pub(crate) fn insert(&mut self, id: OwnedStableId, value: Vec<u8>) {
+        self.0.insert(id, value);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&OwnedStableId, &[u8])> {
+        self.0.iter().map(|(id, value)| (id, value.as_slice()))
+    }
+}
+
+/// The resources of the host read by the systems about to run, encoded for the mod
+///
+/// Mods can't access the world while they run, so the resources are fetched by
+/// [`super::ModRuntime::prepare_resources`] before every batch. Resources written back by a system are updated here
+/// too, so the next systems of the mod see them before they are applied to the world.
+#[derive(Debug, Default)]
+pub(crate) struct HostResources(HashMap<OwnedStableId, Vec<u8>>);
+
+impl HostResources {
+    /// Encodes the given resources of the world, skipping those the mod was not granted access to
+    pub fn new<'a>(
+        world: &World,
+        ids: impl IntoIterator<Item = &'a OwnedStableId>,
+        grants: &Grants,
+    ) -> Self {
+        let registry = world.resource::<AppTypeRegistry>().read();
+
+        let mut resources = HashMap::new();
+        for id in ids {
+            if resources.contains_key(id) || !grants.allows(&Capability::ReadResource(id.clone())) {
+                continue;
+            }
+            if let Some(value) = encode_resource(world, id, &registry) {
+                resources.insert(id.clone(), value);
+            }
+        }
+        Self(resources)
+    }
+
+    pub fn get(&self, id: &OwnedStableId) -> Option<&[u8]> {
+        self.0.get(id).map(Vec::as_slice)
+    }
+
+    /// Updates a resource written back by the mod, returning false if it was not fetched
+    pub fn write(&mut self, id: &OwnedStableId, value: Vec<u8>) -> bool {
+        match self.0.get_mut(id) {
+            Some(existing) => {
+                *existing = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Encodes a resource like `bevy_harmonize_api::runtime::serialize`, so the mod can decode it
+fn encode_resource(world: &World, id: &OwnedStableId, registry: &TypeRegistry) -> Option<Vec<u8>> {
+    let (_, reflect_resource) = reflect_resource(registry, id)?;
+    let value = reflect_resource.reflect(world)?;
+    let serializer = TypedReflectSerializer::new(value.as_partial_reflect(), registry);
+    match bitcode::serialize(&serializer) {
+        Ok(bytes) => Some(bytes),
+        Err(err) => {
+            warn!("Could not encode {:?} for a mod: {}", id, err);
+            None
+        }
+    }
+}
@@ -1,16 +1,527 @@
-// These fields are read by a debug macro
-#[allow(dead_code)]
+use std::{error::Error, fmt};
+
+use bevy_utils::HashMap;
+use common::OwnedStableId;
+
+use super::{
+    loaded::schedule::{Builder, Dag},
+    LoadedMod, ModHandle,
+};
+
+/// Why the systems of a mod could not be scheduled
 #[derive(Debug)]
 pub enum SchedulingError {
-    SystemDeclaredTwice(common::SystemId),
+    /// Two different systems were declared with the same id
+    SystemDeclaredTwice {
+        schedule: OwnedStableId,
+        system: String,
+    },
+    /// The constraints of a mod contradict each other
     Cycles {
-        named_set: Option<String>,
+        schedule: OwnedStableId,
         cycles: Vec<Cycle>,
     },
-    EmptyAnonymousSet,
+    /// Merging the schedules of every active mod creates cycles, see [`ModSchedules`]
+    CyclesBetweenMods {
+        schedule: OwnedStableId,
+        cycles: Vec<ModCycle>,
+    },
+    /// A constraint refers to an anonymous set without systems
+    EmptyAnonymousSet {
+        schedule: OwnedStableId,
+        constraint: ConstraintDescription,
+    },
+}
+
+impl fmt::Display for SchedulingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SystemDeclaredTwice { schedule, system } => write!(
+                f,
+                "System {} is declared twice in schedule {} with different parameters",
+                system, schedule.name
+            ),
+            Self::Cycles { schedule, cycles } => {
+                write!(
+                    f,
+                    "The constraints of schedule {} form cycles",
+                    schedule.name
+                )?;
+                for cycle in cycles {
+                    write!(f, "\n{}", cycle)?;
+                }
+                Ok(())
+            }
+            Self::CyclesBetweenMods { schedule, cycles } => {
+                write!(
+                    f,
+                    "The constraints of schedule {} form cycles between mods",
+                    schedule.name
+                )?;
+                for cycle in cycles {
+                    write!(f, "\n{}", cycle)?;
+                }
+                Ok(())
+            }
+            Self::EmptyAnonymousSet {
+                schedule,
+                constraint,
+            } => write!(
+                f,
+                "Constraint {} of schedule {} refers to an empty set",
+                constraint, schedule.name
+            ),
+        }
+    }
+}
+
+impl Error for SchedulingError {}
+
+/// A cycle in the schedule of a mod, with the constraints that form it in order
+#[derive(Debug)]
+pub struct Cycle {
+    /// The names of the systems along the cycle, empty if it only goes through named sets
+    pub systems: Vec<String>,
+    pub constraints: Vec<ConstraintDescription>,
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cycle through {}", Names(&self.systems))?;
+        for constraint in self.constraints.iter() {
+            write!(f, "\n  {}", constraint)?;
+        }
+        Ok(())
+    }
+}
+
+/// A cycle in the merged schedules, with the mod of every system and constraint that forms it
+#[derive(Debug)]
+pub struct ModCycle {
+    /// The mod and system names of the systems along the cycle
+    pub systems: Vec<(String, String)>,
+    /// The constraints that form the cycle in order, with the name of the mod declaring them
+    pub constraints: Vec<(String, ConstraintDescription)>,
+}
+
+impl fmt::Display for ModCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let systems: Vec<_> = self
+            .systems
+            .iter()
+            .map(|(mod_name, system)| format!("{}::{}", mod_name, system))
+            .collect();
+        write!(f, "Cycle through {}", Names(&systems))?;
+        for (mod_name, constraint) in self.constraints.iter() {
+            write!(f, "\n  {} (in mod {})", constraint, mod_name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Joins the names of the systems along a cycle
+struct Names<'a>(&'a [String]);
+
+impl fmt::Display for Names<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.first() {
+            // The cycle ends where it started
+            Some(first) => write!(f, "{} -> {}", self.0.join(" -> "), first),
+            None => write!(f, "named sets only"),
+        }
+    }
+}
+
+/// A constraint of a mod schedule, with the systems it refers to resolved to their names
+///
+/// Displayed like the call that declared it in the mod, such as `a.before(b)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintDescription {
+    Order {
+        before: SetDescription,
+        after: SetDescription,
+    },
+    Condition {
+        set: SetDescription,
+        condition: String,
+    },
+    Includes {
+        parent: String,
+        set: SetDescription,
+    },
+}
+
+impl ConstraintDescription {
+    pub fn new(constraint: &common::Constraint, name: impl Fn(common::SystemId) -> String) -> Self {
+        match constraint {
+            common::Constraint::Order { before, after } => Self::Order {
+                before: SetDescription::new(before, &name),
+                after: SetDescription::new(after, &name),
+            },
+            common::Constraint::Condition { set, condition } => Self::Condition {
+                set: SetDescription::new(set, &name),
+                condition: name(*condition),
+            },
+            common::Constraint::Includes { parent_name, set } => Self::Includes {
+                parent: parent_name.name.to_owned(),
+                set: SetDescription::new(set, &name),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ConstraintDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Order { before, after } => write!(f, "{}.before({})", before, after),
+            Self::Condition { set, condition } => write!(f, "{}.run_if({})", set, condition),
+            Self::Includes { parent, set } => write!(f, "{}.in_set({})", set, parent),
+        }
+    }
+}
+
+/// A set of systems referred to by a constraint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetDescription {
+    /// The names of the systems of an anonymous set
+    Systems(Vec<String>),
+    /// The name of the type labelling a named set
+    Named(String),
+}
+
+impl SetDescription {
+    pub fn new(set: &common::SystemSet, name: impl Fn(common::SystemId) -> String) -> Self {
+        match set {
+            common::SystemSet::Anonymous(systems) => {
+                Self::Systems(systems.iter().map(|system| name(*system)).collect())
+            }
+            common::SystemSet::Named(id) => Self::Named(id.name.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for SetDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Systems(systems) if systems.len() == 1 => write!(f, "{}", systems[0]),
+            Self::Systems(systems) => write!(f, "({})", systems.join(", ")),
+            Self::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A system of an active mod
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModSystem {
+    pub handle: ModHandle,
+    pub system: common::SystemId,
 }
 
-// These fields are read by a debug macro
-#[allow(dead_code)]
+/// The schedules of every active mod, merged into a single graph per schedule label
+///
+/// Named sets are shared between mods, so a mod can order its systems relative to the sets of another mod.
+#[derive(Debug, Default)]
+pub struct ModSchedules(HashMap<OwnedStableId, ModSchedule>);
+
 #[derive(Debug)]
-pub struct Cycle(pub Vec<common::SystemId>);
+pub struct ModSchedule {
+    dependency: Dag<ModSystem>,
+    /// Every system of the schedule, sorted such that systems always come after their dependencies
+    order: Vec<ModSystem>,
+    /// The run conditions gating each system
+    conditions: HashMap<ModSystem, Vec<ModSystem>>,
+}
+
+impl ModSchedules {
+    /// Merges the schedules of the given mods, failing if their constraints create cycles
+    pub(crate) fn try_build<'a>(
+        mods: impl IntoIterator<Item = (ModHandle, &'a LoadedMod)>,
+    ) -> Result<Self, SchedulingError> {
+        let mods: Vec<_> = mods
+            .into_iter()
+            .map(|(handle, loaded)| (handle, loaded.name(), loaded.manifest()))
+            .collect();
+        Self::from_manifests(&mods)
+    }
+
+    /// Merges the schedules of mods given by their handle, name and manifest
+    pub(super) fn from_manifests(
+        mods: &[(ModHandle, &str, common::ModManifest<'_>)],
+    ) -> Result<Self, SchedulingError> {
+        let manifests: HashMap<ModHandle, (&str, &common::ModManifest)> = mods
+            .iter()
+            .map(|(handle, mod_name, manifest)| (*handle, (*mod_name, manifest)))
+            .collect();
+        let mod_name = |handle: ModHandle| manifests[&handle].0.to_owned();
+        let name = |id: &OwnedStableId, handle: ModHandle, system: common::SystemId| {
+            manifests[&handle]
+                .1
+                .features
+                .iter()
+                .flat_map(|feature| feature.schedules.iter())
+                .filter(|descriptor| descriptor.id.to_owned() == *id)
+                .flat_map(|descriptor| descriptor.schedule.systems.iter())
+                .find(|declared| declared.id == system)
+                .map_or("unknown", |declared| declared.name)
+                .to_owned()
+        };
+
+        let mut builders: HashMap<
+            OwnedStableId,
+            Builder<ModSystem, (ModHandle, &common::Constraint)>,
+        > = HashMap::new();
+        for (handle, _, manifest) in mods {
+            let handle = *handle;
+            for feature in manifest.features.iter() {
+                for descriptor in feature.schedules.iter() {
+                    // Mods with unknown schedule labels are rejected when they load
+                    let id = descriptor.id.to_owned();
+                    let builder = builders.entry(id.clone()).or_default();
+
+                    for system in descriptor.schedule.systems.iter() {
+                        builder.add_system(ModSystem {
+                            handle,
+                            system: system.id,
+                        });
+                    }
+                    for constraint in descriptor.schedule.constraints.iter() {
+                        builder
+                            .add_constraint(constraint, (handle, constraint), |system| ModSystem {
+                                handle,
+                                system,
+                            })
+                            .map_err(|_| SchedulingError::EmptyAnonymousSet {
+                                constraint: ConstraintDescription::new(constraint, |system| {
+                                    name(&id, handle, system)
+                                }),
+                                schedule: id.clone(),
+                            })?;
+                    }
+                }
+            }
+        }
+
+        let mut schedules = HashMap::new();
+        for (id, builder) in builders {
+            let flattened = builder.build().map_err(|cycles| {
+                let cycles = cycles
+                    .into_iter()
+                    .map(|cycle| ModCycle {
+                        systems: cycle
+                            .systems
+                            .into_iter()
+                            .map(|ModSystem { handle, system }| {
+                                (mod_name(handle), name(&id, handle, system))
+                            })
+                            .collect(),
+                        constraints: cycle
+                            .constraints
+                            .into_iter()
+                            .map(|(handle, constraint)| {
+                                let constraint = ConstraintDescription::new(constraint, |system| {
+                                    name(&id, handle, system)
+                                });
+                                (mod_name(handle), constraint)
+                            })
+                            .collect(),
+                    })
+                    .collect();
+                SchedulingError::CyclesBetweenMods {
+                    schedule: id.clone(),
+                    cycles,
+                }
+            })?;
+
+            let order = flattened
+                .systems
+                .into_iter()
+                .map(|(system, _)| system)
+                .collect();
+            let mut conditions: HashMap<ModSystem, Vec<ModSystem>> = HashMap::new();
+            for (condition, gated) in flattened.conditions {
+                for system in gated {
+                    conditions.entry(system).or_default().push(condition);
+                }
+            }
+            schedules.insert(
+                id,
+                ModSchedule {
+                    dependency: flattened.dependency,
+                    order,
+                    conditions,
+                },
+            );
+        }
+
+        Ok(Self(schedules))
+    }
+
+    pub fn get(&self, id: &OwnedStableId) -> Option<&ModSchedule> {
+        self.0.get(id)
+    }
+
+    /// Iterates over every schedule, by schedule label
+    pub fn iter(&self) -> impl Iterator<Item = (&OwnedStableId, &ModSchedule)> {
+        self.0.iter()
+    }
+}
+
+impl ModSchedule {
+    /// Systems of every mod in the order they should be run
+    pub fn order(&self) -> &[ModSystem] {
+        &self.order
+    }
+
+    /// The systems that have to run before the given system, possibly from other mods
+    pub fn dependencies(&self, system: ModSystem) -> impl Iterator<Item = ModSystem> + '_ {
+        self.dependency
+            .neighbors_directed(system, petgraph::Direction::Incoming)
+    }
+
+    /// The run conditions that must all return true for the given system to run
+    pub fn conditions(&self, system: ModSystem) -> &[ModSystem] {
+        self.conditions.get(&system).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        Constraint, FeatureDescriptor, FileHash, ModManifest, Schedule, ScheduleDescriptor,
+        StableId, System, SystemId, SystemSet,
+    };
+
+    use super::*;
+
+    const UPDATE: StableId = StableId {
+        crate_name: "bevy_harmonize_api",
+        name: "Update",
+    };
+
+    /// A mod with a single system in `set`, which it orders before `before`
+    fn manifest<'a>(
+        name: &'a str,
+        id: SystemId,
+        system: &'a str,
+        set: StableId<'a>,
+        before: StableId<'a>,
+    ) -> ModManifest<'a> {
+        ModManifest {
+            wasm_hash: FileHash::empty(),
+            api_version: "0.0.0",
+            version: "0.1.0",
+            dependencies: Vec::new(),
+            types: Vec::new(),
+            features: vec![FeatureDescriptor {
+                name,
+                resources: Vec::new(),
+                schedules: vec![ScheduleDescriptor {
+                    id: UPDATE,
+                    schedule: Schedule {
+                        systems: vec![System {
+                            id,
+                            name: system,
+                            params: Vec::new(),
+                        }],
+                        constraints: vec![
+                            Constraint::Includes {
+                                parent_name: set,
+                                set: SystemSet::Anonymous(vec![id]),
+                            },
+                            Constraint::Order {
+                                before: SystemSet::Named(set),
+                                after: SystemSet::Named(before),
+                            },
+                        ],
+                    },
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn cycles_between_mods_are_explained() {
+        let physics = StableId {
+            crate_name: "physics",
+            name: "PhysicsSet",
+        };
+        let input = StableId {
+            crate_name: "input",
+            name: "InputSet",
+        };
+        let mods = [
+            (
+                ModHandle(0),
+                "physics",
+                manifest("physics", SystemId::from_raw(0), "step", physics, input),
+            ),
+            (
+                ModHandle(1),
+                "input",
+                manifest("input", SystemId::from_raw(1), "read_keys", input, physics),
+            ),
+        ];
+
+        let error = ModSchedules::from_manifests(&mods).unwrap_err();
+        let SchedulingError::CyclesBetweenMods { schedule, cycles } = &error else {
+            panic!("expected a cycle between mods, got {:?}", error);
+        };
+        assert_eq!(*schedule, UPDATE.to_owned());
+        let [cycle] = cycles.as_slice() else {
+            panic!("expected a single cycle, got {:?}", cycles);
+        };
+
+        // The cycle may start from either mod
+        let mut systems = cycle.systems.clone();
+        systems.sort();
+        assert_eq!(
+            systems,
+            [
+                ("input".to_owned(), "read_keys".to_owned()),
+                ("physics".to_owned(), "step".to_owned()),
+            ]
+        );
+        let set = |name: &str| SetDescription::Named(name.to_owned());
+        let system = |name: &str| SetDescription::Systems(vec![name.to_owned()]);
+        let expected = [
+            (
+                "physics",
+                ConstraintDescription::Includes {
+                    parent: "PhysicsSet".to_owned(),
+                    set: system("step"),
+                },
+            ),
+            (
+                "physics",
+                ConstraintDescription::Order {
+                    before: set("PhysicsSet"),
+                    after: set("InputSet"),
+                },
+            ),
+            (
+                "input",
+                ConstraintDescription::Includes {
+                    parent: "InputSet".to_owned(),
+                    set: system("read_keys"),
+                },
+            ),
+            (
+                "input",
+                ConstraintDescription::Order {
+                    before: set("InputSet"),
+                    after: set("PhysicsSet"),
+                },
+            ),
+        ];
+        assert_eq!(cycle.constraints.len(), expected.len());
+        for (mod_name, constraint) in expected {
+            assert!(cycle
+                .constraints
+                .contains(&(mod_name.to_owned(), constraint)));
+        }
+
+        let explanation = error.to_string();
+        assert!(explanation.contains("step.in_set(PhysicsSet) (in mod physics)"));
+        assert!(explanation.contains("InputSet.before(PhysicsSet) (in mod input)"));
+    }
+}